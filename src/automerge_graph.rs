@@ -0,0 +1,783 @@
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::Context;
+use automerge::sync::{self, SyncDoc};
+use automerge::transaction::{CommitOptions, Transactable};
+use automerge::{
+    ActorId, AutoCommit, ChangeHash, ObjId, ObjType, ReadDoc, ScalarValue, Value, ROOT,
+};
+use chrono::{DateTime, SecondsFormat, Utc};
+use masonry::kurbo::{Circle, Point};
+
+use crate::graph::{
+    DatabaseGraph, Graph, GraphEvent, GraphSnapshot, Node, NodeIndex, SnapshotCache, Subscriber,
+    Subscribers, SubscriptionId,
+};
+
+/// A `Graph` stored in an Automerge document,
+/// which keeps the full history of the graph and can be merged with other copies of it.
+///
+/// The document is laid out as:
+///
+/// ```text
+/// {
+///     "tasks": {
+///         "<id>": {
+///             "title": str,
+///             "x": f64,
+///             "y": f64,
+///             "radius": f64,
+///             "completed_at": str | null,
+///             "description": str,
+///             "metadata": { str: str },
+///             "deleted_at": str | null,
+///         },
+///     },
+///     "links": {
+///         "<parent id>/<child id>": {
+///             "added_at": str | null,
+///             "set_aside_at": str | null,
+///         },
+///     },
+/// }
+/// ```
+///
+/// Like `DatabaseGraph`, removed tasks are only marked with `deleted_at`
+/// and keep their links, so that a removal merges cleanly with concurrent edits.
+/// Links written before they were maps are just `true`.
+///
+/// Merging can bring together edges which form a cycle,
+/// so `merge` and `receive_sync_message` set aside edges with `Graph::repair_cycles` afterwards.
+pub struct AutomergeGraph {
+    doc: AutoCommit,
+    tasks: ObjId,
+    links: ObjId,
+    path: Option<PathBuf>,
+    saved_heads: Vec<ChangeHash>,
+    /// Whether changes are being held back as one Automerge change until the transaction ends.
+    in_transaction: bool,
+    snapshot: SnapshotCache,
+    subscribers: Subscribers,
+}
+
+impl AutomergeGraph {
+    /// Opens the graph saved at `path`, or starts a new one there if the file doesn't exist.
+    /// Every change made to the graph is appended to the file as it happens.
+    pub fn open(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref().to_owned();
+        let mut graph = match fs::read(&path) {
+            Ok(bytes) => Self::from_doc(AutoCommit::load(&bytes)?)?,
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                let mut graph = Self::open_in_memory()?;
+                graph.save(&path)?;
+                graph
+            }
+            Err(e) => return Err(e.into()),
+        };
+        graph.path = Some(path);
+        Ok(graph)
+    }
+
+    /// Where the graph lives when nobody says otherwise: `ekad/db.automerge`
+    /// in the user's data directory.
+    pub fn default_path() -> anyhow::Result<PathBuf> {
+        Ok(DatabaseGraph::default_path()?.with_extension("automerge"))
+    }
+
+    /// Opens the graph at `default_path`, starting a new one there if it doesn't exist yet.
+    pub fn open_default() -> anyhow::Result<Self> {
+        let path = Self::default_path()?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)
+                .with_context(|| format!("Failed to create {}", dir.display()))?;
+        }
+        Self::open(path)
+    }
+
+    pub fn open_in_memory() -> anyhow::Result<Self> {
+        Self::from_doc(genesis())
+    }
+
+    /// Writes the whole document, compacted, to `path`.
+    pub fn save(&mut self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        fs::write(path, self.doc.save())?;
+        self.saved_heads = self.doc.get_heads();
+        Ok(())
+    }
+
+    /// Pulls in every change from `other` which this graph hasn't seen yet.
+    pub fn merge(&mut self, other: &mut AutomergeGraph) -> anyhow::Result<()> {
+        let before = self.snapshot()?;
+        self.doc.merge(&mut other.doc)?;
+        self.publish_changes_since(&before)?;
+        self.repair_merged_cycles()?;
+        self.persist()
+    }
+
+    /// Produces the next sync message for the peer tracked by `state`,
+    /// or `None` if there's nothing left to tell them.
+    pub fn generate_sync_message(&mut self, state: &mut sync::State) -> Option<sync::Message> {
+        self.doc.sync().generate_sync_message(state)
+    }
+
+    /// Applies a sync message from the peer tracked by `state`,
+    /// returning whether it brought in any changes.
+    pub fn receive_sync_message(
+        &mut self,
+        state: &mut sync::State,
+        message: sync::Message,
+    ) -> anyhow::Result<bool> {
+        let before = self.snapshot()?;
+        let heads = self.doc.get_heads();
+        self.doc.sync().receive_sync_message(state, message)?;
+        if self.doc.get_heads() == heads {
+            return Ok(false);
+        }
+        self.publish_changes_since(&before)?;
+        self.repair_merged_cycles()?;
+        self.persist()?;
+        Ok(true)
+    }
+
+    /// Tells subscribers about changes which came from another copy of the graph.
+    fn publish_changes_since(&mut self, before: &GraphSnapshot) -> anyhow::Result<()> {
+        self.snapshot.invalidate();
+        for event in self.snapshot()?.changes_since(before) {
+            self.subscribers.publish(event);
+        }
+        Ok(())
+    }
+
+    fn repair_merged_cycles(&mut self) -> anyhow::Result<()> {
+        for (from, to) in self.repair_cycles()? {
+            log::warn!("Set aside edge from {} to {} to break a cycle", from, to);
+        }
+        Ok(())
+    }
+
+    fn from_doc(mut doc: AutoCommit) -> anyhow::Result<Self> {
+        let tasks = get_map(&doc, &ROOT, "tasks")?;
+        let links = get_map(&doc, &ROOT, "links")?;
+        let saved_heads = doc.get_heads();
+        Ok(Self {
+            doc,
+            tasks,
+            links,
+            path: None,
+            saved_heads,
+            in_transaction: false,
+            snapshot: SnapshotCache::default(),
+            subscribers: Subscribers::default(),
+        })
+    }
+
+    fn task(&self, index: NodeIndex) -> anyhow::Result<ObjId> {
+        get_map(&self.doc, &self.tasks, index.to_string())
+    }
+
+    fn is_deleted(&self, index: NodeIndex) -> anyhow::Result<bool> {
+        let task = self.task(index)?;
+        Ok(get_str(&self.doc, &task, "deleted_at")?.is_some())
+    }
+
+    /// Returns the links which haven't been set aside.
+    fn links(&self) -> impl Iterator<Item = (NodeIndex, NodeIndex)> + '_ {
+        self.all_links()
+            .filter(|(parent, child)| !matches!(self.is_set_aside(*parent, *child), Ok(true)))
+    }
+
+    fn all_links(&self) -> impl Iterator<Item = (NodeIndex, NodeIndex)> + '_ {
+        self.doc.keys(&self.links).filter_map(|key| {
+            let (parent, child) = key.split_once('/')?;
+            Some((parent.parse().ok()?, child.parse().ok()?))
+        })
+    }
+
+    /// Returns the map for a link, or `None` if it doesn't exist or is an old style `true` link.
+    fn link(&self, from: NodeIndex, to: NodeIndex) -> anyhow::Result<Option<ObjId>> {
+        match self.doc.get(&self.links, link_key(from, to))? {
+            Some((Value::Object(ObjType::Map), id)) => Ok(Some(id)),
+            _ => Ok(None),
+        }
+    }
+
+    fn is_set_aside(&self, from: NodeIndex, to: NodeIndex) -> anyhow::Result<bool> {
+        match self.link(from, to)? {
+            Some(link) => Ok(get_str(&self.doc, &link, "set_aside_at")?.is_some()),
+            None => Ok(false),
+        }
+    }
+
+    fn put_node(&mut self, task: &ObjId, node: Node) -> anyhow::Result<()> {
+        self.doc.put(task, "title", node.title)?;
+        self.doc.put(task, "x", node.circle.center.x)?;
+        self.doc.put(task, "y", node.circle.center.y)?;
+        self.doc.put(task, "radius", node.circle.radius)?;
+        self.doc
+            .put(task, "completed_at", timestamp_value(node.completed_at))?;
+        self.doc.put(task, "description", node.description)?;
+        // Only the entries which changed are written, so that edits to different entries merge.
+        let metadata = match get_map(&self.doc, task, "metadata") {
+            Ok(metadata) => metadata,
+            Err(_) => self.doc.put_object(task, "metadata", ObjType::Map)?,
+        };
+        for key in self.doc.keys(&metadata).collect::<Vec<_>>() {
+            if !node.metadata.contains_key(&key) {
+                self.doc.delete(&metadata, key)?;
+            }
+        }
+        for (key, value) in node.metadata {
+            if get_str(&self.doc, &metadata, &key)?.as_ref() != Some(&value) {
+                self.doc.put(&metadata, key, value)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Commits pending operations and, if the graph has a file, appends them to it.
+    /// Does nothing during a transaction.
+    fn persist(&mut self) -> anyhow::Result<()> {
+        self.snapshot.invalidate();
+        if self.in_transaction {
+            return Ok(());
+        }
+        self.doc.commit();
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let changes = self.doc.save_after(&self.saved_heads);
+        if !changes.is_empty() {
+            OpenOptions::new()
+                .append(true)
+                .open(path)?
+                .write_all(&changes)?;
+            self.saved_heads = self.doc.get_heads();
+        }
+        Ok(())
+    }
+}
+
+impl Graph for AutomergeGraph {
+    fn add_edge(&mut self, from: NodeIndex, to: NodeIndex) -> anyhow::Result<()> {
+        if self.would_create_cycle(from, to)? {
+            anyhow::bail!("Adding edge would create a cycle");
+        }
+        if self.doc.get(&self.links, link_key(from, to))?.is_some()
+            && !self.is_set_aside(from, to)?
+        {
+            return Ok(());
+        }
+        let link = self
+            .doc
+            .put_object(&self.links, link_key(from, to), ObjType::Map)?;
+        self.doc
+            .put(&link, "added_at", timestamp_value(Some(Utc::now())))?;
+        self.doc.put(&link, "set_aside_at", ScalarValue::Null)?;
+        self.persist()?;
+        self.subscribers.publish(GraphEvent::EdgeAdded { from, to });
+        Ok(())
+    }
+
+    fn insert_node(&mut self, index: NodeIndex, node: Node) -> anyhow::Result<()> {
+        // A node which was removed is still in the document, so bring it back instead.
+        let task = match self.task(index) {
+            Ok(task) if self.is_deleted(index)? => task,
+            Ok(_) => anyhow::bail!("Node {} already exists", index),
+            Err(_) => self
+                .doc
+                .put_object(&self.tasks, index.to_string(), ObjType::Map)?,
+        };
+        self.put_node(&task, node)?;
+        self.doc.put(&task, "deleted_at", ScalarValue::Null)?;
+        self.persist()?;
+        self.subscribers.publish(GraphEvent::NodeAdded(index));
+        Ok(())
+    }
+
+    fn remove_edge(&mut self, from: NodeIndex, to: NodeIndex) -> anyhow::Result<()> {
+        let key = link_key(from, to);
+        if self.doc.get(&self.links, key.as_str())?.is_some() {
+            let was_set_aside = self.is_set_aside(from, to)?;
+            self.doc.delete(&self.links, key)?;
+            self.persist()?;
+            if !was_set_aside {
+                self.subscribers
+                    .publish(GraphEvent::EdgeRemoved { from, to });
+            }
+        }
+        Ok(())
+    }
+
+    fn get_node(&self, index: NodeIndex) -> anyhow::Result<Node> {
+        let task = self.task(index)?;
+        Ok(Node {
+            title: get_str(&self.doc, &task, "title")?.unwrap_or_default(),
+            circle: Circle::new(
+                Point::new(
+                    get_f64(&self.doc, &task, "x")?,
+                    get_f64(&self.doc, &task, "y")?,
+                ),
+                get_f64(&self.doc, &task, "radius")?,
+            ),
+            completed_at: get_timestamp(&self.doc, &task, "completed_at")?,
+            description: get_str(&self.doc, &task, "description")?.unwrap_or_default(),
+            // Tasks written before there was metadata don't have a map for it.
+            metadata: match get_map(&self.doc, &task, "metadata") {
+                Ok(metadata) => self
+                    .doc
+                    .keys(&metadata)
+                    .filter_map(|key| {
+                        let value = get_str(&self.doc, &metadata, &key).transpose()?;
+                        Some(value.map(|value| (key, value)))
+                    })
+                    .collect::<anyhow::Result<_>>()?,
+                Err(_) => Default::default(),
+            },
+        })
+    }
+
+    fn neighbors(&self, index: NodeIndex) -> anyhow::Result<Vec<NodeIndex>> {
+        let mut indices = vec![];
+        for (parent, child) in self.links() {
+            if parent == index && !self.is_deleted(child)? {
+                indices.push(child);
+            }
+        }
+        Ok(indices)
+    }
+
+    fn parents(&self, index: NodeIndex) -> anyhow::Result<Vec<NodeIndex>> {
+        let mut indices = vec![];
+        for (parent, child) in self.links() {
+            if child == index && !self.is_deleted(parent)? {
+                indices.push(parent);
+            }
+        }
+        Ok(indices)
+    }
+
+    fn node_indices(&self) -> anyhow::Result<Vec<NodeIndex>> {
+        let mut indices = vec![];
+        for key in self.doc.keys(&self.tasks) {
+            let index = key.parse()?;
+            if !self.is_deleted(index)? {
+                indices.push(index);
+            }
+        }
+        Ok(indices)
+    }
+
+    fn remove_node(&mut self, index: NodeIndex) -> anyhow::Result<()> {
+        let Ok(task) = self.task(index) else {
+            return Ok(());
+        };
+        if get_str(&self.doc, &task, "deleted_at")?.is_none() {
+            self.doc
+                .put(&task, "deleted_at", timestamp_value(Some(Utc::now())))?;
+            self.persist()?;
+            self.subscribers.publish(GraphEvent::NodeRemoved(index));
+        }
+        Ok(())
+    }
+
+    fn set_node(&mut self, index: NodeIndex, node: Node) -> anyhow::Result<()> {
+        let task = self.task(index)?;
+        self.put_node(&task, node)?;
+        self.persist()?;
+        self.subscribers.publish(GraphEvent::NodeUpdated(index));
+        Ok(())
+    }
+
+    fn edge_added_at(
+        &self,
+        from: NodeIndex,
+        to: NodeIndex,
+    ) -> anyhow::Result<Option<DateTime<Utc>>> {
+        match self.link(from, to)? {
+            Some(link) => get_timestamp(&self.doc, &link, "added_at"),
+            None => Ok(None),
+        }
+    }
+
+    fn set_aside_edge(&mut self, from: NodeIndex, to: NodeIndex) -> anyhow::Result<()> {
+        let key = link_key(from, to);
+        if self.doc.get(&self.links, key.as_str())?.is_none() || self.is_set_aside(from, to)? {
+            anyhow::bail!("Edge from {} to {} does not exist", from, to);
+        }
+        let link = match self.link(from, to)? {
+            Some(link) => link,
+            None => {
+                let link = self.doc.put_object(&self.links, key, ObjType::Map)?;
+                self.doc.put(&link, "added_at", ScalarValue::Null)?;
+                link
+            }
+        };
+        self.doc
+            .put(&link, "set_aside_at", timestamp_value(Some(Utc::now())))?;
+        self.persist()?;
+        self.subscribers
+            .publish(GraphEvent::EdgeRemoved { from, to });
+        Ok(())
+    }
+
+    fn set_aside_edges(&self) -> anyhow::Result<Vec<(NodeIndex, NodeIndex)>> {
+        let mut edges = vec![];
+        for (parent, child) in self.all_links() {
+            if self.is_set_aside(parent, child)?
+                && self.task(parent).is_ok()
+                && !self.is_deleted(parent)?
+                && self.task(child).is_ok()
+                && !self.is_deleted(child)?
+            {
+                edges.push((parent, child));
+            }
+        }
+        Ok(edges)
+    }
+
+    fn snapshot(&self) -> anyhow::Result<Arc<GraphSnapshot>> {
+        self.snapshot.get_or_load(|| GraphSnapshot::load(self))
+    }
+
+    fn begin_transaction(&mut self) -> anyhow::Result<()> {
+        if self.in_transaction {
+            anyhow::bail!("A transaction is already in progress");
+        }
+        self.doc.commit();
+        self.in_transaction = true;
+        self.subscribers.begin_transaction();
+        Ok(())
+    }
+
+    fn commit_transaction(&mut self) -> anyhow::Result<()> {
+        if !self.in_transaction {
+            anyhow::bail!("No transaction is in progress");
+        }
+        self.in_transaction = false;
+        self.persist()?;
+        self.subscribers.commit_transaction();
+        Ok(())
+    }
+
+    fn rollback_transaction(&mut self) -> anyhow::Result<()> {
+        if !self.in_transaction {
+            anyhow::bail!("No transaction is in progress");
+        }
+        self.in_transaction = false;
+        self.doc.rollback();
+        self.snapshot.invalidate();
+        self.subscribers.rollback_transaction();
+        Ok(())
+    }
+
+    fn subscribe(&mut self, subscriber: Subscriber) -> SubscriptionId {
+        self.subscribers.subscribe(subscriber)
+    }
+
+    fn unsubscribe(&mut self, id: SubscriptionId) {
+        self.subscribers.unsubscribe(id);
+    }
+}
+
+/// Builds the document every `AutomergeGraph` starts from.
+///
+/// This is always the exact same change (same actor, same timestamp, same operations),
+/// so graphs which were started separately still agree on which `tasks` and `links`
+/// objects to use, rather than one replacing the other when they're merged.
+fn genesis() -> AutoCommit {
+    let mut doc = AutoCommit::new().with_actor(ActorId::from([0u8; 16].as_slice()));
+    doc.put_object(ROOT, "tasks", ObjType::Map)
+        .expect("Failed to create tasks map");
+    doc.put_object(ROOT, "links", ObjType::Map)
+        .expect("Failed to create links map");
+    doc.commit_with(CommitOptions::default().with_time(0));
+    doc.set_actor(ActorId::random());
+    doc
+}
+
+fn link_key(from: NodeIndex, to: NodeIndex) -> String {
+    format!("{}/{}", from, to)
+}
+
+fn timestamp_value(timestamp: Option<DateTime<Utc>>) -> ScalarValue {
+    match timestamp {
+        Some(timestamp) => timestamp
+            .to_rfc3339_opts(SecondsFormat::AutoSi, true)
+            .into(),
+        None => ScalarValue::Null,
+    }
+}
+
+fn get_map(doc: &AutoCommit, obj: &ObjId, key: impl Into<String>) -> anyhow::Result<ObjId> {
+    let key = key.into();
+    match doc.get(obj, key.as_str())? {
+        Some((Value::Object(ObjType::Map), id)) => Ok(id),
+        _ => anyhow::bail!("Missing map {}", key),
+    }
+}
+
+fn get_str(doc: &AutoCommit, obj: &ObjId, key: &str) -> anyhow::Result<Option<String>> {
+    Ok(doc
+        .get(obj, key)?
+        .and_then(|(value, _)| value.to_str().map(str::to_owned)))
+}
+
+fn get_f64(doc: &AutoCommit, obj: &ObjId, key: &str) -> anyhow::Result<f64> {
+    match doc.get(obj, key)? {
+        Some((value, _)) => value
+            .to_f64()
+            .ok_or_else(|| anyhow::anyhow!("{} is not a number", key)),
+        None => anyhow::bail!("Missing {}", key),
+    }
+}
+
+fn get_timestamp(
+    doc: &AutoCommit,
+    obj: &ObjId,
+    key: &str,
+) -> anyhow::Result<Option<DateTime<Utc>>> {
+    match get_str(doc, obj, key)? {
+        Some(timestamp) => Ok(Some(
+            DateTime::parse_from_rfc3339(&timestamp)?.with_timezone(&Utc),
+        )),
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::graph::DatabaseGraph;
+
+    fn test_node(title: &str) -> Node {
+        Node {
+            title: title.to_owned(),
+            circle: Circle::new(Point::new(1.0, 2.0), 40.0),
+            ..Default::default()
+        }
+    }
+
+    /// Runs the same series of operations against a graph
+    /// and describes the result in terms of titles, rather than indices.
+    fn run_script(graph: &mut impl Graph) -> Vec<(String, String, bool, Vec<String>)> {
+        let a = graph.add_node(test_node("a")).unwrap();
+        let b = graph.add_node(test_node("b")).unwrap();
+        let c = graph.add_node(test_node("c")).unwrap();
+        let d = graph.add_node(test_node("d")).unwrap();
+        graph.add_edge(a, b).unwrap();
+        graph.add_edge(b, c).unwrap();
+        graph.add_edge(a, d).unwrap();
+        assert!(graph.add_edge(c, a).is_err());
+        graph.remove_edge(a, d).unwrap();
+        graph.add_edge(d, c).unwrap();
+        graph.mark_completed(c).unwrap();
+        let mut node = graph.get_node(d).unwrap();
+        node.title = "renamed".to_owned();
+        node.description = "first line\nsecond line".to_owned();
+        node.circle.center = Point::new(10.0, 20.0);
+        graph.set_node(d, node).unwrap();
+        graph.remove_node(b).unwrap();
+        assert!(graph.set_node(NodeIndex::now_v7(), test_node("e")).is_err());
+
+        let mut ancestors: Vec<String> = graph
+            .ancestors(c)
+            .unwrap()
+            .into_iter()
+            .map(|index| graph.get_node(index).unwrap().title)
+            .collect();
+        ancestors.sort();
+        assert_eq!(ancestors, vec!["renamed"]);
+
+        graph
+            .node_indices()
+            .unwrap()
+            .into_iter()
+            .map(|index| {
+                let node = graph.get_node(index).unwrap();
+                let mut children: Vec<String> = graph
+                    .neighbors(index)
+                    .unwrap()
+                    .into_iter()
+                    .map(|child| graph.get_node(child).unwrap().title)
+                    .collect();
+                children.sort();
+                let is_completed = node.is_completed();
+                (node.title, node.description, is_completed, children)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_behaves_like_database_graph() {
+        let expected = run_script(&mut DatabaseGraph::open_in_memory().unwrap());
+        let actual = run_script(&mut AutomergeGraph::open_in_memory().unwrap());
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn test_save_and_open() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("graph.automerge");
+
+        let mut graph = AutomergeGraph::open(&path).unwrap();
+        let a = graph.add_node(test_node("a")).unwrap();
+        let b = graph.add_node(test_node("b")).unwrap();
+        graph.add_edge(a, b).unwrap();
+        graph.mark_completed(b).unwrap();
+        let completed_at = graph.get_node(b).unwrap().completed_at;
+        drop(graph);
+
+        let graph = AutomergeGraph::open(&path).unwrap();
+        assert_eq!(graph.node_indices().unwrap(), vec![a, b]);
+        assert_eq!(graph.neighbors(a).unwrap(), vec![b]);
+        assert_eq!(graph.get_node(b).unwrap().completed_at, completed_at);
+
+        let compacted = dir.path().join("compacted.automerge");
+        AutomergeGraph::open(&path)
+            .unwrap()
+            .save(&compacted)
+            .unwrap();
+        let graph = AutomergeGraph::open(&compacted).unwrap();
+        assert_eq!(graph.node_indices().unwrap(), vec![a, b]);
+    }
+
+    #[test]
+    fn test_merge_independent_graphs() {
+        let mut first = AutomergeGraph::open_in_memory().unwrap();
+        let mut second = AutomergeGraph::open_in_memory().unwrap();
+        let a = first.add_node(test_node("a")).unwrap();
+        let b = second.add_node(test_node("b")).unwrap();
+
+        first.merge(&mut second).unwrap();
+        second.merge(&mut first).unwrap();
+        assert_eq!(first.node_indices().unwrap(), vec![a, b]);
+        assert_eq!(second.node_indices().unwrap(), vec![a, b]);
+    }
+
+    #[test]
+    fn test_merge_concurrent_edits() {
+        let mut first = AutomergeGraph::open_in_memory().unwrap();
+        let a = first.add_node(test_node("a")).unwrap();
+        let b = first.add_node(test_node("b")).unwrap();
+        let mut second = AutomergeGraph::open_in_memory().unwrap();
+        second.merge(&mut first).unwrap();
+
+        first.add_edge(a, b).unwrap();
+        let mut node = second.get_node(b).unwrap();
+        node.title = "renamed".to_owned();
+        second.set_node(b, node).unwrap();
+
+        first.merge(&mut second).unwrap();
+        assert_eq!(first.neighbors(a).unwrap(), vec![b]);
+        assert_eq!(first.get_node(b).unwrap().title, "renamed");
+    }
+
+    #[test]
+    fn test_merge_concurrent_metadata_edits() {
+        let mut first = AutomergeGraph::open_in_memory().unwrap();
+        let mut node = test_node("a");
+        node.metadata.insert("tags".to_owned(), "home".to_owned());
+        let a = first.add_node(node).unwrap();
+        let mut second = AutomergeGraph::open_in_memory().unwrap();
+        second.merge(&mut first).unwrap();
+
+        let mut node = first.get_node(a).unwrap();
+        node.metadata
+            .insert("due".to_owned(), "tomorrow".to_owned());
+        first.set_node(a, node).unwrap();
+        let mut node = second.get_node(a).unwrap();
+        node.metadata.remove("tags");
+        second.set_node(a, node).unwrap();
+
+        first.merge(&mut second).unwrap();
+        let metadata = first.get_node(a).unwrap().metadata;
+        assert_eq!(
+            metadata.into_iter().collect::<Vec<_>>(),
+            [("due".to_owned(), "tomorrow".to_owned())]
+        );
+    }
+
+    #[test]
+    fn test_merge_repairs_cycles() {
+        let mut first = AutomergeGraph::open_in_memory().unwrap();
+        let a = first.add_node(test_node("a")).unwrap();
+        let b = first.add_node(test_node("b")).unwrap();
+        let mut second = AutomergeGraph::open_in_memory().unwrap();
+        second.merge(&mut first).unwrap();
+
+        first.add_edge(a, b).unwrap();
+        second.add_edge(b, a).unwrap();
+
+        first.merge(&mut second).unwrap();
+        assert_eq!(first.find_cycles().unwrap(), Vec::<Vec<NodeIndex>>::new());
+        assert_eq!(first.neighbors(a).unwrap(), vec![b]);
+        assert_eq!(first.set_aside_edges().unwrap(), vec![(b, a)]);
+
+        second.merge(&mut first).unwrap();
+        assert_eq!(second.neighbors(a).unwrap(), vec![b]);
+        assert_eq!(second.neighbors(b).unwrap(), Vec::<NodeIndex>::new());
+        assert_eq!(second.set_aside_edges().unwrap(), vec![(b, a)]);
+    }
+
+    #[test]
+    fn test_merge_publishes_changes() {
+        let mut first = AutomergeGraph::open_in_memory().unwrap();
+        let a = first.add_node(test_node("a")).unwrap();
+        let b = first.add_node(test_node("b")).unwrap();
+        let mut second = AutomergeGraph::open_in_memory().unwrap();
+        second.merge(&mut first).unwrap();
+        first.add_edge(a, b).unwrap();
+        second.add_edge(b, a).unwrap();
+        second.mark_completed(b).unwrap();
+
+        let events = Arc::new(Mutex::new(vec![]));
+        {
+            let events = events.clone();
+            first.subscribe(Box::new(move |event| events.lock().unwrap().push(*event)));
+        }
+        first.merge(&mut second).unwrap();
+        // The edge which closed a cycle is set aside again straight away.
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec![
+                GraphEvent::NodeUpdated(b),
+                GraphEvent::EdgeAdded { from: b, to: a },
+                GraphEvent::EdgeRemoved { from: b, to: a },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_rolled_back_transaction_is_not_saved() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("graph.automerge");
+        let mut graph = AutomergeGraph::open(&path).unwrap();
+        let a = graph.add_node(test_node("a")).unwrap();
+
+        let result = graph.transaction(|graph| {
+            let b = graph.add_node(test_node("b"))?;
+            graph.add_edge(a, b)?;
+            graph.add_edge(b, a)
+        });
+        assert!(result.is_err());
+        assert_eq!(graph.node_indices().unwrap(), vec![a]);
+
+        let b = graph
+            .transaction(|graph| {
+                let b = graph.add_node(test_node("b"))?;
+                graph.add_edge(a, b)?;
+                Ok(b)
+            })
+            .unwrap();
+        drop(graph);
+
+        let graph = AutomergeGraph::open(&path).unwrap();
+        assert_eq!(graph.node_indices().unwrap(), vec![a, b]);
+        assert_eq!(graph.neighbors(a).unwrap(), vec![b]);
+    }
+}
@@ -0,0 +1,448 @@
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use anyhow::Context;
+use ekad::graph::{DatabaseGraph, Graph, GraphSnapshot, Node, NodeIndex};
+use ekad::graph_viewer::{new_node_position, CIRCLE_RADIUS};
+use masonry::kurbo::Circle;
+use serde_json::{json, Value};
+
+const USAGE: &str = "\
+Usage: ek [OPTIONS] <COMMAND>
+
+Commands:
+  add TITLE [-d DESCRIPTION]  Add a task
+  link TASK DEPENDENCY        Make TASK depend on DEPENDENCY
+  done TASK                   Mark a task as completed
+  rm TASK                     Remove a task
+  ls                          List every task
+  next                        List the tasks which can be worked on now
+
+Tasks are found by their ID, the start of their ID, or their title.
+
+Options:
+      --db PATH  Use the database at PATH instead of the one in your data directory
+      --json     Print JSON instead of text
+  -h, --help     Print this message";
+
+#[derive(Debug, PartialEq)]
+enum Command {
+    Add { title: String, description: String },
+    Link { task: String, dependency: String },
+    Done { task: String },
+    Rm { task: String },
+    Ls,
+    Next,
+}
+
+#[derive(Debug, PartialEq)]
+struct Args {
+    database: Option<PathBuf>,
+    json: bool,
+    command: Command,
+}
+
+impl Args {
+    /// Returns `None` if the user only asked for help.
+    fn parse(args: impl IntoIterator<Item = String>) -> anyhow::Result<Option<Self>> {
+        let mut args = args.into_iter();
+        let mut database = None;
+        let mut json = false;
+        let mut description = None;
+        let mut positional = vec![];
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-h" | "--help" => return Ok(None),
+                "--json" => json = true,
+                "--db" => database = Some(args.next().context("--db needs a path")?.into()),
+                "-d" | "--description" => {
+                    description = Some(args.next().context("--description needs some text")?)
+                }
+                "--" => positional.extend(args.by_ref()),
+                _ if arg.starts_with('-') && arg.len() > 1 => {
+                    anyhow::bail!("Unknown option {}, see --help", arg)
+                }
+                _ => positional.push(arg),
+            }
+        }
+
+        let mut positional = positional.into_iter();
+        let name = positional.next().context("Missing a command, see --help")?;
+        let mut operand = |what: &str| {
+            positional
+                .next()
+                .with_context(|| format!("ek {} needs a {}", name, what))
+        };
+        let command = match name.as_str() {
+            "add" => Command::Add {
+                title: operand("title")?,
+                description: description.take().unwrap_or_default(),
+            },
+            "link" => Command::Link {
+                task: operand("task")?,
+                dependency: operand("dependency")?,
+            },
+            "done" => Command::Done {
+                task: operand("task")?,
+            },
+            "rm" => Command::Rm {
+                task: operand("task")?,
+            },
+            "ls" => Command::Ls,
+            "next" => Command::Next,
+            _ => anyhow::bail!("Unknown command {}, see --help", name),
+        };
+        if let Some(extra) = positional.next() {
+            anyhow::bail!("Unexpected argument {}", extra);
+        }
+        if description.is_some() {
+            anyhow::bail!("Only ek add takes a description");
+        }
+        Ok(Some(Self {
+            database,
+            json,
+            command,
+        }))
+    }
+}
+
+/// Finds the one task which `query` refers to.
+fn find_task(snapshot: &GraphSnapshot, query: &str) -> anyhow::Result<NodeIndex> {
+    let matches: Vec<NodeIndex> = snapshot
+        .nodes()
+        .filter(|(index, node)| index.to_string().starts_with(query) || node.title == query)
+        .map(|(index, _)| index)
+        .collect();
+    match matches[..] {
+        [index] => Ok(index),
+        [] => anyhow::bail!("No task matches {:?}", query),
+        _ => anyhow::bail!(
+            "{:?} matches {} tasks, use an ID instead:\n{}",
+            query,
+            matches.len(),
+            matches
+                .iter()
+                .map(|&index| describe(snapshot, index))
+                .collect::<Vec<_>>()
+                .join("\n"),
+        ),
+    }
+}
+
+/// The tasks which aren't done yet, but whose dependencies all are.
+fn next_tasks(snapshot: &GraphSnapshot) -> Vec<NodeIndex> {
+    let is_completed = |index| snapshot.node(index).is_some_and(Node::is_completed);
+    snapshot
+        .nodes()
+        .filter(|(index, node)| {
+            !node.is_completed() && snapshot.neighbors(*index).all(is_completed)
+        })
+        .map(|(index, _)| index)
+        .collect()
+}
+
+fn describe(snapshot: &GraphSnapshot, index: NodeIndex) -> String {
+    let node = snapshot.node(index);
+    let completed = node.is_some_and(Node::is_completed);
+    let title = node.map_or("", |node| node.title.as_str());
+    format!(
+        "[{}] {}  {}",
+        if completed { "x" } else { " " },
+        index,
+        title
+    )
+}
+
+fn task_json(snapshot: &GraphSnapshot, index: NodeIndex) -> Value {
+    let node = snapshot.node(index).cloned().unwrap_or_default();
+    json!({
+        "id": index.to_string(),
+        "title": node.title,
+        "description": node.description,
+        "completed_at": node.completed_at.map(|at| at.to_rfc3339()),
+        "depends_on": snapshot
+            .neighbors(index)
+            .map(|index| index.to_string())
+            .collect::<Vec<_>>(),
+    })
+}
+
+/// Prints tasks one per line, with what they're waiting on underneath.
+fn print_tasks(
+    snapshot: &GraphSnapshot,
+    tasks: &[NodeIndex],
+    json: bool,
+    out: &mut impl Write,
+) -> anyhow::Result<()> {
+    if json {
+        let tasks = tasks.iter().map(|&index| task_json(snapshot, index));
+        writeln!(out, "{:#}", Value::Array(tasks.collect()))?;
+        return Ok(());
+    }
+    for &index in tasks {
+        writeln!(out, "{}", describe(snapshot, index))?;
+        let waiting_on: Vec<&str> = snapshot
+            .neighbors(index)
+            .filter_map(|dependency| snapshot.node(dependency))
+            .filter(|dependency| !dependency.is_completed())
+            .map(|dependency| dependency.title.as_str())
+            .collect();
+        if !waiting_on.is_empty() {
+            writeln!(out, "      waiting on: {}", waiting_on.join(", "))?;
+        }
+    }
+    Ok(())
+}
+
+fn print_task(
+    snapshot: &GraphSnapshot,
+    index: NodeIndex,
+    json: bool,
+    out: &mut impl Write,
+) -> anyhow::Result<()> {
+    if json {
+        writeln!(out, "{:#}", task_json(snapshot, index))?;
+    } else {
+        writeln!(out, "{}", describe(snapshot, index))?;
+    }
+    Ok(())
+}
+
+fn run(
+    command: Command,
+    json: bool,
+    graph: &mut impl Graph,
+    out: &mut impl Write,
+) -> anyhow::Result<()> {
+    let snapshot = graph.snapshot()?;
+    let changed = match command {
+        Command::Add { title, description } => graph.add_node(Node {
+            title,
+            description,
+            circle: Circle::new(new_node_position(&snapshot), CIRCLE_RADIUS),
+            ..Default::default()
+        })?,
+        Command::Link { task, dependency } => {
+            let task = find_task(&snapshot, &task)?;
+            let dependency = find_task(&snapshot, &dependency)?;
+            let title = |index| snapshot.node(index).map_or("", |node| node.title.as_str());
+            graph.add_edge(task, dependency).with_context(|| {
+                format!(
+                    "Can't make {:?} depend on {:?}",
+                    title(task),
+                    title(dependency)
+                )
+            })?;
+            task
+        }
+        Command::Done { task } => {
+            let index = find_task(&snapshot, &task)?;
+            graph.mark_completed(index)?;
+            index
+        }
+        Command::Rm { task } => {
+            let index = find_task(&snapshot, &task)?;
+            graph.remove_node(index)?;
+            // Describe the task as it was before it went away.
+            return print_task(&snapshot, index, json, out);
+        }
+        Command::Ls => {
+            let tasks: Vec<NodeIndex> = snapshot.nodes().map(|(index, _)| index).collect();
+            return print_tasks(&snapshot, &tasks, json, out);
+        }
+        Command::Next => return print_tasks(&snapshot, &next_tasks(&snapshot), json, out),
+    };
+    let snapshot = graph.snapshot()?;
+    print_task(&snapshot, changed, json, out)
+}
+
+fn main() -> ExitCode {
+    let result = Args::parse(std::env::args().skip(1)).and_then(|args| {
+        let Some(args) = args else {
+            println!("{}", USAGE);
+            return Ok(());
+        };
+        let mut graph = match &args.database {
+            Some(path) => DatabaseGraph::open(path)?,
+            None => DatabaseGraph::open_default()?,
+        };
+        run(
+            args.command,
+            args.json,
+            &mut graph,
+            &mut io::stdout().lock(),
+        )
+    });
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("ek: {:#}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> anyhow::Result<Option<Args>> {
+        Args::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    /// Runs `args` against `graph`, returning what was printed.
+    fn ek(graph: &mut DatabaseGraph, args: &[&str]) -> anyhow::Result<String> {
+        let args = parse(args)?.unwrap();
+        let mut out = vec![];
+        run(args.command, args.json, graph, &mut out)?;
+        Ok(String::from_utf8(out).unwrap())
+    }
+
+    #[test]
+    fn test_parse_args() {
+        assert_eq!(
+            parse(&[
+                "--db",
+                "tasks.sqlite",
+                "add",
+                "-d",
+                "details",
+                "--json",
+                "a"
+            ])
+            .unwrap(),
+            Some(Args {
+                database: Some("tasks.sqlite".into()),
+                json: true,
+                command: Command::Add {
+                    title: "a".to_owned(),
+                    description: "details".to_owned(),
+                },
+            })
+        );
+        assert_eq!(parse(&["ls", "--help"]).unwrap(), None);
+        assert_eq!(
+            parse(&["rm", "--", "-a"]).unwrap().unwrap().command,
+            Command::Rm {
+                task: "-a".to_owned()
+            }
+        );
+
+        assert!(parse(&[]).is_err());
+        assert!(parse(&["frobnicate"]).is_err());
+        assert!(parse(&["link", "a"]).is_err());
+        assert!(parse(&["done", "a", "b"]).is_err());
+        assert!(parse(&["done", "-d", "details", "a"]).is_err());
+        assert!(parse(&["--db"]).is_err());
+    }
+
+    #[test]
+    fn test_commands() {
+        let mut graph = DatabaseGraph::open_in_memory().unwrap();
+        ek(&mut graph, &["add", "write report"]).unwrap();
+        ek(
+            &mut graph,
+            &["add", "gather data", "-d", "from last quarter"],
+        )
+        .unwrap();
+        ek(&mut graph, &["add", "make coffee"]).unwrap();
+        let [report, data, coffee] = graph.node_indices().unwrap()[..] else {
+            panic!("Expected three tasks");
+        };
+        assert_eq!(
+            graph.get_node(data).unwrap().description,
+            "from last quarter"
+        );
+        // New tasks don't cover each other up.
+        let report_circle = graph.get_node(report).unwrap().circle;
+        let data_circle = graph.get_node(data).unwrap().circle;
+        assert!(
+            report_circle.center.distance(data_circle.center)
+                > report_circle.radius + data_circle.radius
+        );
+
+        ek(&mut graph, &["link", "write report", "gather data"]).unwrap();
+        assert_eq!(graph.neighbors(report).unwrap(), vec![data]);
+        let error = ek(&mut graph, &["link", "gather data", "write report"]).unwrap_err();
+        assert!(format!("{:#}", error).contains("cycle"), "{:#}", error);
+
+        assert_eq!(
+            ek(&mut graph, &["next"]).unwrap(),
+            format!("[ ] {}  gather data\n[ ] {}  make coffee\n", data, coffee)
+        );
+        assert_eq!(
+            ek(&mut graph, &["ls"]).unwrap(),
+            format!(
+                "[ ] {}  write report\n      waiting on: gather data\n\
+                 [ ] {}  gather data\n[ ] {}  make coffee\n",
+                report, data, coffee
+            )
+        );
+
+        ek(&mut graph, &["done", &data.to_string()[..30]]).unwrap();
+        assert!(graph.get_node(data).unwrap().is_completed());
+        assert_eq!(
+            ek(&mut graph, &["next"]).unwrap(),
+            format!(
+                "[ ] {}  write report\n[ ] {}  make coffee\n",
+                report, coffee
+            )
+        );
+
+        ek(&mut graph, &["rm", "make coffee"]).unwrap();
+        assert_eq!(graph.node_indices().unwrap(), vec![report, data]);
+        assert!(ek(&mut graph, &["rm", "make coffee"]).is_err());
+    }
+
+    #[test]
+    fn test_ambiguous_tasks() {
+        let mut graph = DatabaseGraph::open_in_memory().unwrap();
+        ek(&mut graph, &["add", "a"]).unwrap();
+        ek(&mut graph, &["add", "a"]).unwrap();
+        let error = ek(&mut graph, &["done", "a"]).unwrap_err();
+        assert!(error.to_string().contains("matches 2 tasks"), "{}", error);
+    }
+
+    #[test]
+    fn test_json_output() {
+        let mut graph = DatabaseGraph::open_in_memory().unwrap();
+        ek(&mut graph, &["add", "a"]).unwrap();
+        ek(&mut graph, &["add", "b"]).unwrap();
+        ek(&mut graph, &["link", "a", "b"]).unwrap();
+        let [a, b] = graph.node_indices().unwrap()[..] else {
+            panic!("Expected two tasks");
+        };
+
+        let output = ek(&mut graph, &["--json", "done", "b"]).unwrap();
+        let completed_at = graph.get_node(b).unwrap().completed_at.unwrap();
+        assert_eq!(
+            output,
+            format!(
+                "{:#}\n",
+                json!({
+                    "id": b.to_string(),
+                    "title": "b",
+                    "description": "",
+                    "completed_at": completed_at.to_rfc3339(),
+                    "depends_on": [],
+                })
+            )
+        );
+
+        let output = ek(&mut graph, &["--json", "next"]).unwrap();
+        assert_eq!(
+            output,
+            format!(
+                "{:#}\n",
+                json!([{
+                    "id": a.to_string(),
+                    "title": "a",
+                    "description": "",
+                    "completed_at": null,
+                    "depends_on": [b.to_string()],
+                }])
+            )
+        );
+    }
+}
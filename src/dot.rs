@@ -0,0 +1,620 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use anyhow::Context;
+use masonry::kurbo::Point;
+
+use crate::graph::{Graph, Node, NodeIndex};
+use crate::import::Import;
+
+/// Writes `graph` as a Graphviz digraph, with an edge from each task to each task it depends on.
+///
+/// Tasks are labelled with their titles, and pinned where they are in ekad with `pos`
+/// (flipped upside down, since y goes up in Graphviz), so `neato -n` draws them there.
+/// Set-aside edges are left out.
+pub fn export<G: Graph + ?Sized>(graph: &G) -> anyhow::Result<String> {
+    let snapshot = graph.snapshot()?;
+    let mut dot = String::from("digraph ekad {\n");
+    for (index, node) in snapshot.nodes() {
+        let center = node.circle.center;
+        writeln!(
+            dot,
+            "  \"{}\" [label={}, pos=\"{},{}!\"];",
+            index,
+            quote(&node.title),
+            center.x,
+            // Adding zero turns -0 into 0.
+            -center.y + 0.0
+        )?;
+    }
+    for (from, to) in snapshot.edges() {
+        writeln!(dot, "  \"{}\" -> \"{}\";", from, to)?;
+    }
+    dot.push_str("}\n");
+    Ok(dot)
+}
+
+fn quote(text: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => {}
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Adds the nodes and edges of a Graphviz digraph to `graph`, and returns the new tasks in the
+/// order they first appear.
+///
+/// Each node becomes a task titled with its `label`, or its name if it has none, and each edge
+/// `a -> b` makes `a` depend on `b`. Nodes with a `pos` are put there, and the rest are laid out
+/// below the tasks which depend on them. Nothing is added if the edges form a cycle.
+pub fn import<G: Graph>(graph: &mut G, dot: &str) -> anyhow::Result<Vec<NodeIndex>> {
+    parse(dot)?.apply(graph)
+}
+
+/// Reads a Graphviz digraph, without adding it to a graph yet.
+pub fn parse(dot: &str) -> anyhow::Result<Import> {
+    let mut parser = Parser {
+        tokens: tokenize(dot)?,
+        position: 0,
+        nodes: vec![],
+        names: HashMap::new(),
+        edges: vec![],
+    };
+    parser.graph()?;
+
+    let mut import = Import::default();
+    for node in parser.nodes {
+        let title = match node.attributes.get("label") {
+            Some(label) => unescape_label(label, &node.name),
+            None => node.name.clone(),
+        };
+        let position = match node.attributes.get("pos") {
+            Some(pos) => Some(
+                parse_pos(pos)
+                    .with_context(|| format!("Invalid pos for node {:?}: {:?}", node.name, pos))?,
+            ),
+            None => None,
+        };
+        import.add_node(
+            Node {
+                title,
+                ..Default::default()
+            },
+            position,
+        );
+    }
+    for (from, to) in parser.edges {
+        import.add_edge(from, to);
+    }
+    Ok(import)
+}
+
+/// Reads a `pos` like "12,34" or "12,34!", in Graphviz's coordinates where y goes up.
+fn parse_pos(pos: &str) -> Option<Point> {
+    let (x, y) = pos.trim().trim_end_matches('!').split_once(',')?;
+    let (x, y) = (x.trim().parse::<f64>().ok()?, y.trim().parse::<f64>().ok()?);
+    (x.is_finite() && y.is_finite()).then(|| Point::new(x, -y))
+}
+
+/// Turns the escape sequences Graphviz allows in labels back into the text they stand for.
+fn unescape_label(label: &str, name: &str) -> String {
+    let mut text = String::new();
+    let mut chars = label.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            text.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n' | 'l' | 'r') => text.push('\n'),
+            Some('N') => text.push_str(name),
+            Some(c) => text.push(c),
+            None => text.push('\\'),
+        }
+    }
+    // The last line of a label is usually ended with one of the line breaks as well.
+    text.strip_suffix('\n').map(str::to_owned).unwrap_or(text)
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    /// A name, number or string. Keywords are only keywords when they aren't quoted.
+    Id {
+        text: String,
+        quoted: bool,
+    },
+    Open(char),
+    Close(char),
+    Equals,
+    Separator,
+    Colon,
+    Arrow,
+    UndirectedEdge,
+}
+
+impl Token {
+    fn is_keyword(&self, keyword: &str) -> bool {
+        matches!(self, Token::Id { text, quoted: false } if text.eq_ignore_ascii_case(keyword))
+    }
+}
+
+fn tokenize(dot: &str) -> anyhow::Result<Vec<Token>> {
+    let mut tokens = vec![];
+    let mut chars = dot.chars().peekable();
+    let mut line_start = true;
+    // Whether the last string is followed by a +, and the next one is joined onto it.
+    let mut joining = false;
+    while let Some(c) = chars.next() {
+        if joining && c != '"' && !c.is_whitespace() {
+            anyhow::bail!("Expected a string after +");
+        }
+        match c {
+            '\n' => {
+                line_start = true;
+                continue;
+            }
+            c if c.is_whitespace() => continue,
+            // Lines starting with # are C preprocessor output.
+            '#' if line_start => {
+                chars.by_ref().find(|&c| c == '\n');
+                continue;
+            }
+            '/' if chars.peek() == Some(&'/') => {
+                chars.by_ref().find(|&c| c == '\n');
+                line_start = true;
+                continue;
+            }
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let mut previous = ' ';
+                loop {
+                    let c = chars.next().context("Unterminated comment")?;
+                    if previous == '*' && c == '/' {
+                        break;
+                    }
+                    previous = c;
+                }
+            }
+            '{' | '[' => tokens.push(Token::Open(c)),
+            '}' | ']' => tokens.push(Token::Close(c)),
+            '=' => tokens.push(Token::Equals),
+            ';' | ',' => tokens.push(Token::Separator),
+            ':' => tokens.push(Token::Colon),
+            '-' if chars.peek() == Some(&'>') => {
+                chars.next();
+                tokens.push(Token::Arrow);
+            }
+            '-' if chars.peek() == Some(&'-') => {
+                chars.next();
+                tokens.push(Token::UndirectedEdge);
+            }
+            '"' => {
+                let mut text = String::new();
+                loop {
+                    match chars.next().context("Unterminated string")? {
+                        '"' => break,
+                        '\\' => match chars.next().context("Unterminated string")? {
+                            '"' => text.push('"'),
+                            // Long strings can be split over several lines.
+                            '\n' => {}
+                            c => {
+                                text.push('\\');
+                                text.push(c);
+                            }
+                        },
+                        c => text.push(c),
+                    }
+                }
+                match tokens.last_mut() {
+                    Some(Token::Id { text: previous, .. }) if joining => {
+                        previous.push_str(&text);
+                        joining = false;
+                    }
+                    _ => tokens.push(Token::Id { text, quoted: true }),
+                }
+            }
+            // Strings can be joined together with +.
+            '+' => match tokens.last() {
+                Some(Token::Id { quoted: true, .. }) => joining = true,
+                _ => anyhow::bail!("Unexpected +"),
+            },
+            '<' => {
+                // HTML-like labels can nest their own angle brackets.
+                let mut text = String::new();
+                let mut depth = 1;
+                loop {
+                    let c = chars.next().context("Unterminated HTML string")?;
+                    match c {
+                        '<' => depth += 1,
+                        '>' => depth -= 1,
+                        _ => {}
+                    }
+                    if depth == 0 {
+                        break;
+                    }
+                    text.push(c);
+                }
+                tokens.push(Token::Id { text, quoted: true });
+            }
+            c if c.is_alphanumeric() || c == '_' || c == '.' || c == '-' => {
+                let mut text = String::from(c);
+                while let Some(&c) = chars.peek() {
+                    if !(c.is_alphanumeric() || c == '_' || c == '.') {
+                        break;
+                    }
+                    text.push(c);
+                    chars.next();
+                }
+                tokens.push(Token::Id {
+                    text,
+                    quoted: false,
+                });
+            }
+            c => anyhow::bail!("Unexpected {:?}", c),
+        }
+        line_start = false;
+    }
+    if joining {
+        anyhow::bail!("Expected a string after +");
+    }
+    Ok(tokens)
+}
+
+struct DotNode {
+    name: String,
+    attributes: HashMap<String, String>,
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+    nodes: Vec<DotNode>,
+    names: HashMap<String, usize>,
+    edges: Vec<(usize, usize)>,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> anyhow::Result<Token> {
+        let token = self.peek().cloned().context("Unexpected end of graph")?;
+        self.position += 1;
+        Ok(token)
+    }
+
+    fn expect(&mut self, expected: Token) -> anyhow::Result<()> {
+        let token = self.next()?;
+        if token != expected {
+            anyhow::bail!("Expected {:?}, found {:?}", expected, token);
+        }
+        Ok(())
+    }
+
+    fn id(&mut self) -> anyhow::Result<String> {
+        match self.next()? {
+            Token::Id { text, .. } => Ok(text),
+            token => anyhow::bail!("Expected a name, found {:?}", token),
+        }
+    }
+
+    /// `[strict] digraph [name] { statements }`
+    fn graph(&mut self) -> anyhow::Result<()> {
+        if self.peek().is_some_and(|token| token.is_keyword("strict")) {
+            self.position += 1;
+        }
+        let token = self.next()?;
+        if token.is_keyword("graph") {
+            anyhow::bail!("Only directed graphs (digraph) can be imported");
+        } else if !token.is_keyword("digraph") {
+            anyhow::bail!("Expected digraph, found {:?}", token);
+        }
+        if let Some(Token::Id { .. }) = self.peek() {
+            self.position += 1;
+        }
+        self.expect(Token::Open('{'))?;
+        self.statements()?;
+        if let Some(token) = self.peek() {
+            anyhow::bail!("Unexpected {:?} after the graph", token);
+        }
+        Ok(())
+    }
+
+    /// Reads statements up to and including the closing brace,
+    /// and returns the nodes they mention.
+    fn statements(&mut self) -> anyhow::Result<Vec<usize>> {
+        let mut nodes = vec![];
+        loop {
+            match self.peek().context("Missing }")? {
+                Token::Close('}') => {
+                    self.position += 1;
+                    return Ok(nodes);
+                }
+                Token::Separator => self.position += 1,
+                _ => nodes.extend(self.statement()?),
+            }
+        }
+    }
+
+    fn statement(&mut self) -> anyhow::Result<Vec<usize>> {
+        let token = self.peek().context("Unexpected end of graph")?;
+        let defaults = ["graph", "node", "edge"]
+            .iter()
+            .any(|keyword| token.is_keyword(keyword));
+        if defaults && self.tokens.get(self.position + 1) == Some(&Token::Open('[')) {
+            // Default attributes don't say anything about particular tasks.
+            self.position += 1;
+            self.attributes()?;
+            return Ok(vec![]);
+        }
+        if matches!(token, Token::Id { .. })
+            && self.tokens.get(self.position + 1) == Some(&Token::Equals)
+        {
+            self.position += 2;
+            self.id()?;
+            return Ok(vec![]);
+        }
+
+        let mut operands = vec![self.operand()?];
+        loop {
+            match self.peek() {
+                Some(Token::Arrow) => self.position += 1,
+                Some(Token::UndirectedEdge) => {
+                    anyhow::bail!("Undirected edges (--) can't be imported")
+                }
+                _ => break,
+            }
+            operands.push(self.operand()?);
+        }
+        let attributes = match self.peek() {
+            Some(Token::Open('[')) => self.attributes()?,
+            _ => vec![],
+        };
+        if let [Operand::Node(node)] = operands[..] {
+            self.nodes[node].attributes.extend(attributes);
+        }
+        for pair in operands.windows(2) {
+            for &from in pair[0].nodes() {
+                for &to in pair[1].nodes() {
+                    self.edges.push((from, to));
+                }
+            }
+        }
+        Ok(operands
+            .into_iter()
+            .flat_map(|operand| operand.nodes().to_vec())
+            .collect())
+    }
+
+    /// A node, or a subgraph standing for every node in it.
+    fn operand(&mut self) -> anyhow::Result<Operand> {
+        let subgraph = self
+            .peek()
+            .is_some_and(|token| token.is_keyword("subgraph"));
+        if subgraph {
+            self.position += 1;
+            if let Some(Token::Id { .. }) = self.peek() {
+                self.position += 1;
+            }
+        }
+        if subgraph || self.peek() == Some(&Token::Open('{')) {
+            self.expect(Token::Open('{'))?;
+            return Ok(Operand::Subgraph(self.statements()?));
+        }
+
+        let name = self.id()?;
+        // Ports only say where on the node edges are drawn.
+        while self.peek() == Some(&Token::Colon) {
+            self.position += 1;
+            self.id()?;
+        }
+        let node = *self.names.entry(name.clone()).or_insert_with(|| {
+            self.nodes.push(DotNode {
+                name,
+                attributes: HashMap::new(),
+            });
+            self.nodes.len() - 1
+        });
+        Ok(Operand::Node(node))
+    }
+
+    /// Reads one or more attribute lists like `[a=b, c=d][e=f]`.
+    fn attributes(&mut self) -> anyhow::Result<Vec<(String, String)>> {
+        let mut attributes = vec![];
+        while self.peek() == Some(&Token::Open('[')) {
+            self.position += 1;
+            loop {
+                match self.peek().context("Missing ]")? {
+                    Token::Close(']') => {
+                        self.position += 1;
+                        break;
+                    }
+                    Token::Separator => self.position += 1,
+                    _ => {
+                        let key = self.id()?;
+                        self.expect(Token::Equals)?;
+                        attributes.push((key, self.id()?));
+                    }
+                }
+            }
+        }
+        Ok(attributes)
+    }
+}
+
+enum Operand {
+    Node(usize),
+    Subgraph(Vec<usize>),
+}
+
+impl Operand {
+    fn nodes(&self) -> &[usize] {
+        match self {
+            Operand::Node(node) => std::slice::from_ref(node),
+            Operand::Subgraph(nodes) => nodes,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graph::PetgraphGraph;
+    use crate::import::test_helpers::sorted;
+    use masonry::kurbo::Circle;
+
+    #[test]
+    fn test_export() {
+        let mut graph = PetgraphGraph::default();
+        let a = graph
+            .add_node(Node {
+                title: "Say \"hi\"\nloudly".to_owned(),
+                circle: Circle::new(Point::new(10.0, 20.5), 40.0),
+                ..Default::default()
+            })
+            .unwrap();
+        let b = graph
+            .add_node(Node {
+                title: "C:\\".to_owned(),
+                ..Default::default()
+            })
+            .unwrap();
+        graph.add_edge(a, b).unwrap();
+        assert_eq!(
+            export(&graph).unwrap(),
+            format!(
+                "digraph ekad {{\n  \
+                   \"{a}\" [label=\"Say \\\"hi\\\"\\nloudly\", pos=\"10,-20.5!\"];\n  \
+                   \"{b}\" [label=\"C:\\\\\", pos=\"0,0!\"];\n  \
+                   \"{a}\" -> \"{b}\";\n\
+                 }}\n"
+            )
+        );
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let mut graph = PetgraphGraph::default();
+        let titles = ["Say \"hi\"\nloudly", "a\\b", "plain"];
+        let indices: Vec<NodeIndex> = titles
+            .iter()
+            .enumerate()
+            .map(|(i, title)| {
+                graph
+                    .add_node(Node {
+                        title: title.to_string(),
+                        circle: Circle::new(Point::new(i as f64 * 100.0, -50.0), 40.0),
+                        ..Default::default()
+                    })
+                    .unwrap()
+            })
+            .collect();
+        graph.add_edge(indices[0], indices[1]).unwrap();
+        graph.add_edge(indices[0], indices[2]).unwrap();
+        graph.add_edge(indices[1], indices[2]).unwrap();
+
+        let mut imported = PetgraphGraph::default();
+        let new = import(&mut imported, &export(&graph).unwrap()).unwrap();
+        for (old, new) in indices.iter().zip(&new) {
+            let (old_node, new_node) = (
+                graph.get_node(*old).unwrap(),
+                imported.get_node(*new).unwrap(),
+            );
+            assert_eq!(new_node.title, old_node.title);
+            assert_eq!(new_node.circle.center, old_node.circle.center);
+        }
+        assert_eq!(
+            sorted(imported.neighbors(new[0]).unwrap()),
+            vec![new[1], new[2]]
+        );
+        assert_eq!(sorted(imported.neighbors(new[1]).unwrap()), vec![new[2]]);
+    }
+
+    #[test]
+    fn test_import() {
+        let dot = r#"
+            # generated by make
+            /* The build */ strict digraph "build" {
+                graph [rankdir=LR]; node [shape=box]
+                rankdir = TB
+                app -> {lib "util"} -> core:n  // every crate needs core
+                app [label="The \N" + " app\l"]
+                lib -> core [color=red] [style=dashed]
+                subgraph cluster_tools { test; lint }
+                "test" -> app
+                html [label=<<b>bold</b>>, pos="5.5,-3"]
+                -1.5
+            }
+        "#;
+        let mut graph = PetgraphGraph::default();
+        let indices = import(&mut graph, dot).unwrap();
+        let titles: Vec<String> = indices
+            .iter()
+            .map(|index| graph.get_node(*index).unwrap().title)
+            .collect();
+        assert_eq!(
+            titles,
+            [
+                "The app app",
+                "lib",
+                "util",
+                "core",
+                "test",
+                "lint",
+                "<b>bold</b>",
+                "-1.5"
+            ]
+        );
+        let [app, lib, util, core, test, _, html, _] = indices[..] else {
+            unreachable!()
+        };
+        assert_eq!(sorted(graph.neighbors(app).unwrap()), vec![lib, util]);
+        assert_eq!(sorted(graph.neighbors(lib).unwrap()), vec![core]);
+        assert_eq!(sorted(graph.neighbors(util).unwrap()), vec![core]);
+        assert_eq!(sorted(graph.neighbors(test).unwrap()), vec![app]);
+        assert_eq!(
+            graph.get_node(html).unwrap().circle.center,
+            Point::new(5.5, 3.0)
+        );
+        // Everything else is laid out below what depends on it.
+        let y = |index| graph.get_node(index).unwrap().circle.center.y;
+        assert!(y(test) < y(app) && y(app) < y(lib) && y(lib) < y(core));
+    }
+
+    #[test]
+    fn test_import_rejects_invalid_graphs() {
+        let invalid = [
+            (
+                "digraph { a -> b -> c -> a }",
+                "cycle: \"a\" -> \"b\" -> \"c\" -> \"a\"",
+            ),
+            ("digraph { a -> a }", "cycle: \"a\" -> \"a\""),
+            ("graph { a -- b }", "Only directed graphs"),
+            ("digraph { a -- b }", "Undirected edges"),
+            ("digraph { a -> b", "Missing }"),
+            ("digraph { a [label=\"b] }", "Unterminated string"),
+            ("digraph { a [pos=\"1\"] }", "Invalid pos for node \"a\""),
+            ("digraph { a } b", "after the graph"),
+        ];
+        for (dot, message) in invalid {
+            let mut graph = PetgraphGraph::default();
+            let error = format!("{:#}", import(&mut graph, dot).unwrap_err());
+            assert!(
+                error.contains(message),
+                "{} should contain {}",
+                error,
+                message
+            );
+            assert_eq!(graph.node_indices().unwrap(), Vec::<NodeIndex>::new());
+        }
+    }
+}
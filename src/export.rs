@@ -0,0 +1,339 @@
+use std::collections::HashSet;
+
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use masonry::kurbo::{Circle, Point};
+use serde_json::{json, Value};
+use uuid::Uuid;
+
+use crate::graph::{metadata_from_json, metadata_to_json, Graph, Node, NodeIndex};
+
+/// Written into every export, and bumped whenever the format changes
+/// in a way older versions of ekad wouldn't understand.
+pub const FORMAT_VERSION: u32 = 2;
+
+/// Writes out every task and edge in `graph`, in a format which `import` reads back.
+///
+/// ```json
+/// {
+///   "format": "ekad",
+///   "version": 2,
+///   "tasks": [
+///     {
+///       "id": "0190c8e2-…",
+///       "title": "Write report",
+///       "description": "",
+///       "x": 120, "y": 80, "radius": 40,
+///       "completed_at": "2024-07-01T09:30:00+00:00",
+///       "metadata": {"due": "2024-07-02T17:00:00+00:00", "tags": "work"}
+///     }
+///   ],
+///   "edges": [{"from": "0190c8e2-…", "to": "0190c8e3-…"}],
+///   "set_aside_edges": []
+/// }
+/// ```
+///
+/// Each task is identified by its node's ID. `completed_at` is `null` for tasks which aren't
+/// done, and `metadata` holds `Node::metadata`, which version 1 didn't have. An edge goes from a
+/// task to a task it depends on. `set_aside_edges` are the edges which were taken out of the
+/// graph to break cycles.
+pub fn export<G: Graph + ?Sized>(graph: &G) -> anyhow::Result<Value> {
+    let snapshot = graph.snapshot()?;
+    let edges_json = |edges: &[(NodeIndex, NodeIndex)]| {
+        edges
+            .iter()
+            .map(|(from, to)| json!({"from": from.to_string(), "to": to.to_string()}))
+            .collect::<Vec<_>>()
+    };
+    Ok(json!({
+        "format": "ekad",
+        "version": FORMAT_VERSION,
+        "tasks": snapshot
+            .nodes()
+            .map(|(index, node)| task_json(index, node))
+            .collect::<Vec<_>>(),
+        "edges": edges_json(snapshot.edges()),
+        "set_aside_edges": edges_json(snapshot.set_aside_edges()),
+    }))
+}
+
+fn task_json(index: NodeIndex, node: &Node) -> Value {
+    json!({
+        "id": index.to_string(),
+        "title": node.title,
+        "description": node.description,
+        "x": node.circle.center.x,
+        "y": node.circle.center.y,
+        "radius": node.circle.radius,
+        "completed_at": node.completed_at.map(|at| at.to_rfc3339()),
+        "metadata": metadata_to_json(&node.metadata),
+    })
+}
+
+/// Adds the tasks and edges from an `export` to `graph`, keeping their IDs,
+/// and returns the imported tasks.
+///
+/// Everything is checked before the graph is changed, and nothing is imported if any of it is
+/// wrong: if a task already exists in `graph`, or an edge would create a cycle, for example.
+pub fn import<G: Graph>(graph: &mut G, json: &Value) -> anyhow::Result<Vec<NodeIndex>> {
+    match json.get("format").and_then(Value::as_str) {
+        Some("ekad") => {}
+        _ => anyhow::bail!("Not an ekad export"),
+    }
+    let version = field(json, "version").context("Missing the format version")?;
+    let version = version
+        .as_u64()
+        .filter(|&version| version >= 1)
+        .with_context(|| format!("Invalid format version {}", version))?;
+    if version > FORMAT_VERSION.into() {
+        anyhow::bail!(
+            "This export has format version {}, but this version of ekad only supports up to {}",
+            version,
+            FORMAT_VERSION,
+        );
+    }
+
+    let tasks = array(json, "tasks")?
+        .iter()
+        .enumerate()
+        .map(|(i, task)| parse_task(task).with_context(|| format!("Invalid task {}", i + 1)))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let mut ids = HashSet::new();
+    for (index, _) in &tasks {
+        if !ids.insert(*index) {
+            anyhow::bail!("Task {} appears more than once", index);
+        }
+    }
+    let existing: HashSet<NodeIndex> = graph.node_indices()?.into_iter().collect();
+    if let Some(index) = ids.iter().find(|index| existing.contains(index)) {
+        anyhow::bail!("Task {} is already in the graph", index);
+    }
+    let parse_edges = |key| -> anyhow::Result<Vec<(NodeIndex, NodeIndex)>> {
+        array(json, key)?
+            .iter()
+            .enumerate()
+            .map(|(i, edge)| {
+                let parse = || {
+                    let from = id(edge, "from")?;
+                    let to = id(edge, "to")?;
+                    for index in [from, to] {
+                        if !ids.contains(&index) {
+                            anyhow::bail!("Task {} isn't in the export", index);
+                        }
+                    }
+                    Ok((from, to))
+                };
+                parse().with_context(|| format!("Invalid edge {} in {}", i + 1, key))
+            })
+            .collect()
+    };
+    let edges = parse_edges("edges")?;
+    let set_aside_edges = parse_edges("set_aside_edges")?;
+
+    graph.transaction(|graph| {
+        for (index, node) in &tasks {
+            graph.insert_node(*index, node.clone())?;
+        }
+        // Set-aside edges may well close cycles, so they go in while there are no other edges
+        // yet, and are set aside straight away.
+        for &(from, to) in &set_aside_edges {
+            graph.add_edge(from, to)?;
+            graph.set_aside_edge(from, to)?;
+        }
+        for &(from, to) in &edges {
+            if graph.would_create_cycle(from, to)? {
+                anyhow::bail!("The edge from {} to {} would create a cycle", from, to);
+            }
+            graph.add_edge(from, to)?;
+        }
+        Ok(())
+    })?;
+    Ok(tasks.into_iter().map(|(index, _)| index).collect())
+}
+
+fn array<'a>(json: &'a Value, key: &str) -> anyhow::Result<&'a [Value]> {
+    match json.get(key) {
+        None => Ok(&[]),
+        Some(value) => value
+            .as_array()
+            .map(Vec::as_slice)
+            .with_context(|| format!("{} must be a list", key)),
+    }
+}
+
+fn field<'a>(json: &'a Value, key: &str) -> anyhow::Result<&'a Value> {
+    json.get(key).with_context(|| format!("Missing {}", key))
+}
+
+fn string<'a>(json: &'a Value, key: &str) -> anyhow::Result<&'a str> {
+    field(json, key)?
+        .as_str()
+        .with_context(|| format!("{} must be a string", key))
+}
+
+fn number(json: &Value, key: &str) -> anyhow::Result<f64> {
+    field(json, key)?
+        .as_f64()
+        .with_context(|| format!("{} must be a number", key))
+}
+
+fn id(json: &Value, key: &str) -> anyhow::Result<NodeIndex> {
+    let id = string(json, key)?;
+    Uuid::parse_str(id).with_context(|| format!("{} isn't a valid ID: {}", key, id))
+}
+
+fn parse_task(json: &Value) -> anyhow::Result<(NodeIndex, Node)> {
+    let completed_at = match field(json, "completed_at")? {
+        Value::Null => None,
+        _ => {
+            let at = string(json, "completed_at")?;
+            Some(
+                DateTime::parse_from_rfc3339(at)
+                    .with_context(|| format!("completed_at isn't a valid time: {}", at))?
+                    .with_timezone(&Utc),
+            )
+        }
+    };
+    let radius = number(json, "radius")?;
+    if radius <= 0.0 {
+        anyhow::bail!("radius must be positive");
+    }
+    Ok((
+        id(json, "id")?,
+        Node {
+            title: string(json, "title")?.to_owned(),
+            description: string(json, "description")?.to_owned(),
+            circle: Circle::new(Point::new(number(json, "x")?, number(json, "y")?), radius),
+            completed_at,
+            metadata: match json.get("metadata") {
+                Some(metadata) => metadata_from_json(metadata)?,
+                None => Default::default(),
+            },
+        },
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::automerge_graph::AutomergeGraph;
+    use crate::graph::{DatabaseGraph, PetgraphGraph};
+
+    fn test_node(title: &str) -> Node {
+        Node {
+            title: title.to_owned(),
+            circle: Circle::new(Point::new(1.5, -2.25), 40.0),
+            ..Default::default()
+        }
+    }
+
+    /// Builds a graph using everything the format has to hold.
+    fn fill(graph: &mut impl Graph) {
+        let a = graph.add_node(test_node("a \"quoted\"\n")).unwrap();
+        let b = graph
+            .add_node(Node {
+                description: "details".to_owned(),
+                metadata: [("tags", "a b"), ("due", "2024-07-02T17:00:00+00:00")]
+                    .map(|(key, value)| (key.to_owned(), value.to_owned()))
+                    .into(),
+                ..test_node("b")
+            })
+            .unwrap();
+        let c = graph.add_node(test_node("c")).unwrap();
+        graph.mark_completed(c).unwrap();
+        graph.add_edge(a, b).unwrap();
+        graph.add_edge(b, c).unwrap();
+        graph.add_edge(c, a).unwrap_err();
+        graph.add_edge(a, c).unwrap();
+        graph.set_aside_edge(a, c).unwrap();
+        // A set-aside edge which would close a cycle, like `repair_cycles` leaves behind.
+        let d = graph.add_node(test_node("d")).unwrap();
+        graph.add_edge(d, a).unwrap();
+        graph.set_aside_edge(d, a).unwrap();
+        graph.add_edge(a, d).unwrap();
+    }
+
+    fn check_roundtrip(from: &mut impl Graph, to: &mut impl Graph) {
+        fill(from);
+        let json = export(from).unwrap();
+        // It survives being written out and read back in.
+        let json = serde_json::from_str(&format!("{:#}", json)).unwrap();
+        let imported = import(to, &json).unwrap();
+        assert_eq!(imported, from.node_indices().unwrap());
+        assert_eq!(*to.snapshot().unwrap(), *from.snapshot().unwrap());
+        assert_eq!(export(to).unwrap(), json);
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        check_roundtrip(&mut PetgraphGraph::default(), &mut PetgraphGraph::default());
+        check_roundtrip(
+            &mut DatabaseGraph::open_in_memory().unwrap(),
+            &mut DatabaseGraph::open_in_memory().unwrap(),
+        );
+        check_roundtrip(
+            &mut AutomergeGraph::open_in_memory().unwrap(),
+            &mut DatabaseGraph::open_in_memory().unwrap(),
+        );
+    }
+
+    #[test]
+    fn test_import_rejects_invalid_exports() {
+        let mut graph = PetgraphGraph::default();
+        fill(&mut graph);
+        let json = export(&graph).unwrap();
+        let set = |key: &str, value: Value| {
+            let mut json = json.clone();
+            json[key] = value;
+            json
+        };
+        let task = |i: usize| json["tasks"][i].clone();
+        let edge = |from: &Value, to: &Value| json!({"from": from["id"], "to": to["id"]});
+
+        let invalid = [
+            ("Not an ekad export", set("format", json!("other"))),
+            ("format version 3", set("version", json!(3))),
+            ("Invalid format version 1.5", set("version", json!(1.5))),
+            ("Invalid format version 0", set("version", json!(0))),
+            ("Invalid format version -1", set("version", json!(-1))),
+            ("Invalid format version \"2\"", set("version", json!("2"))),
+            ("Invalid format version null", set("version", Value::Null)),
+            (
+                "appears more than once",
+                set("tasks", json!([task(0), task(0)])),
+            ),
+            ("Invalid task 1", set("tasks", json!([{"id": "a"}]))),
+            (
+                "isn't in the export",
+                set("tasks", json!([task(0), task(1)])),
+            ),
+            (
+                "would create a cycle",
+                set(
+                    "edges",
+                    json!([edge(&task(0), &task(1)), edge(&task(1), &task(0))]),
+                ),
+            ),
+        ];
+        for (message, json) in invalid {
+            let mut to = PetgraphGraph::default();
+            let error = format!("{:#}", import(&mut to, &json).unwrap_err());
+            assert!(
+                error.contains(message),
+                "{} should contain {}",
+                error,
+                message
+            );
+            // Nothing was imported.
+            assert_eq!(to.node_indices().unwrap(), Vec::<NodeIndex>::new());
+        }
+
+        // Tasks can't be imported twice.
+        let error = import(&mut graph, &json).unwrap_err();
+        assert!(
+            error.to_string().contains("already in the graph"),
+            "{}",
+            error
+        );
+    }
+}
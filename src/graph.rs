@@ -1,26 +1,450 @@
-use std::path::Path;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
+use anyhow::Context;
+use chrono::{DateTime, Duration, FixedOffset, Local, NaiveDate, NaiveDateTime, TimeZone, Utc};
 use masonry::kurbo::{Circle, Point};
-use petgraph::graph::{DiGraph, NodeIndex as PetgraphNodeIndex};
-use rusqlite::Connection;
+use petgraph::algo::tarjan_scc;
+use petgraph::graphmap::DiGraphMap;
+use petgraph::stable_graph::{NodeIndex as PetgraphNodeIndex, StableDiGraph};
+use petgraph::Direction;
+use rusqlite::functions::FunctionFlags;
+use rusqlite::{Connection, DatabaseName, OpenFlags, Row};
+use serde_json::Value;
+use uuid::Uuid;
 
-#[derive(Clone, PartialEq)]
+#[derive(Clone, Debug, Default, PartialEq)]
 pub struct Node {
     pub title: String,
     pub circle: Circle,
+    pub completed_at: Option<DateTime<Utc>>,
+    pub description: String,
+    /// Anything else known about the task, mostly from other tools, by name.
+    /// Dates like `due` and `scheduled` are in RFC 3339, or just the day like `2024-07-05`, and
+    /// `tags` are separated by spaces.
+    pub metadata: BTreeMap<String, String>,
 }
 
-pub type NodeIndex = usize;
+impl Node {
+    pub fn is_completed(&self) -> bool {
+        self.completed_at.is_some()
+    }
+}
+
+/// Writes `metadata` as a JSON object of strings.
+pub(crate) fn metadata_to_json(metadata: &BTreeMap<String, String>) -> Value {
+    metadata
+        .iter()
+        .map(|(key, value)| (key.as_str(), value.as_str()))
+        .collect()
+}
+
+/// Reads metadata written by `metadata_to_json`.
+pub(crate) fn metadata_from_json(json: &Value) -> anyhow::Result<BTreeMap<String, String>> {
+    json.as_object()
+        .context("metadata must be an object")?
+        .iter()
+        .map(|(key, value)| match value.as_str() {
+            Some(value) => Ok((key.clone(), value.to_owned())),
+            None => anyhow::bail!("metadata {} must be a string", key),
+        })
+        .collect()
+}
+
+/// A date in metadata like `due` or `scheduled`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum MetadataDate {
+    /// A whole day, with no time of its own.
+    Day(NaiveDate),
+    Time(DateTime<FixedOffset>),
+}
+
+impl MetadataDate {
+    pub fn parse(text: &str) -> anyhow::Result<Self> {
+        match NaiveDate::parse_from_str(text, "%Y-%m-%d") {
+            Ok(day) => Ok(Self::Day(day)),
+            Err(_) => Ok(Self::Time(DateTime::parse_from_rfc3339(text)?)),
+        }
+    }
+
+    /// The time itself, or the start of the day in local time.
+    pub fn to_utc(self) -> DateTime<Utc> {
+        match self {
+            Self::Day(day) => {
+                let midnight = NaiveDateTime::from(day);
+                from_local_time(&Local, midnight).unwrap_or_else(|| midnight.and_utc())
+            }
+            Self::Time(time) => time.to_utc(),
+        }
+    }
+}
+
+impl std::fmt::Display for MetadataDate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Day(day) => write!(f, "{}", day.format("%Y-%m-%d")),
+            Self::Time(time) => write!(f, "{}", time.to_rfc3339()),
+        }
+    }
+}
+
+/// Finds when it was `date` on clocks in `zone`. A time which the clocks skipped, by going
+/// forward for daylight saving time, means the one an hour later.
+pub(crate) fn from_local_time<Tz: TimeZone>(
+    zone: &Tz,
+    date: NaiveDateTime,
+) -> Option<DateTime<Utc>> {
+    zone.from_local_datetime(&date)
+        .earliest()
+        .or_else(|| {
+            zone.from_local_datetime(&(date + Duration::hours(1)))
+                .earliest()
+        })
+        .map(|date| date.to_utc())
+}
+
+/// Identifies a node across every `Graph` implementation.
+///
+/// These are UUIDv7s, so they're globally unique (and stay valid when graphs are exported,
+/// imported, or merged) and sort in the order the nodes were created.
+pub type NodeIndex = Uuid;
+
+/// Every node and edge of a `Graph`, as they were when the snapshot was taken.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct GraphSnapshot {
+    nodes: BTreeMap<NodeIndex, Node>,
+    /// Sorted, so that each node's edges are next to each other.
+    edges: Vec<(NodeIndex, NodeIndex)>,
+    set_aside_edges: Vec<(NodeIndex, NodeIndex)>,
+}
+
+impl GraphSnapshot {
+    pub fn new(
+        nodes: BTreeMap<NodeIndex, Node>,
+        mut edges: Vec<(NodeIndex, NodeIndex)>,
+        set_aside_edges: Vec<(NodeIndex, NodeIndex)>,
+    ) -> Self {
+        edges.sort();
+        Self {
+            nodes,
+            edges,
+            set_aside_edges,
+        }
+    }
+
+    /// Takes a snapshot one node at a time,
+    /// for graphs which have no faster way to load everything at once.
+    pub fn load<G: Graph + ?Sized>(graph: &G) -> anyhow::Result<Self> {
+        let mut nodes = BTreeMap::new();
+        let mut edges = vec![];
+        for index in graph.node_indices()? {
+            nodes.insert(index, graph.get_node(index)?);
+            for child in graph.neighbors(index)? {
+                edges.push((index, child));
+            }
+        }
+        Ok(Self::new(nodes, edges, graph.set_aside_edges()?))
+    }
+
+    /// Returns the nodes in index order.
+    pub fn nodes(&self) -> impl Iterator<Item = (NodeIndex, &Node)> {
+        self.nodes.iter().map(|(index, node)| (*index, node))
+    }
+
+    /// Returns the ID each node has in another tool, from its `key` metadata, or its own index
+    /// if it has none. Imports can bring in the same task twice, so where nodes share an ID,
+    /// every one but the first uses its index instead.
+    pub fn external_ids(&self, key: &str) -> HashMap<NodeIndex, String> {
+        let mut taken = HashSet::new();
+        self.nodes()
+            .map(|(index, node)| match node.metadata.get(key) {
+                Some(id) if taken.insert(id) => (index, id.clone()),
+                _ => (index, index.to_string()),
+            })
+            .collect()
+    }
+
+    pub fn node(&self, index: NodeIndex) -> Option<&Node> {
+        self.nodes.get(&index)
+    }
+
+    /// Returns every edge, as `(parent, child)`.
+    pub fn edges(&self) -> &[(NodeIndex, NodeIndex)] {
+        &self.edges
+    }
+
+    /// Returns the children of `index`, like `Graph::neighbors`.
+    pub fn neighbors(&self, index: NodeIndex) -> impl Iterator<Item = NodeIndex> + '_ {
+        let start = self.edges.partition_point(|(from, _)| *from < index);
+        self.edges[start..]
+            .iter()
+            .take_while(move |(from, _)| *from == index)
+            .map(|(_, to)| *to)
+    }
+
+    pub fn set_aside_edges(&self) -> &[(NodeIndex, NodeIndex)] {
+        &self.set_aside_edges
+    }
+
+    /// Describes how the graph changed between `before` and this snapshot.
+    pub fn changes_since(&self, before: &GraphSnapshot) -> Vec<GraphEvent> {
+        let mut events = vec![];
+        for (index, node) in self.nodes() {
+            match before.node(index) {
+                None => events.push(GraphEvent::NodeAdded(index)),
+                Some(before_node) if before_node != node => {
+                    events.push(GraphEvent::NodeUpdated(index))
+                }
+                Some(_) => {}
+            }
+        }
+        for (index, _) in before.nodes() {
+            if self.node(index).is_none() {
+                events.push(GraphEvent::NodeRemoved(index));
+            }
+        }
+        for &(from, to) in self.edges() {
+            if before.edges.binary_search(&(from, to)).is_err() {
+                events.push(GraphEvent::EdgeAdded { from, to });
+            }
+        }
+        for &(from, to) in before.edges() {
+            if self.edges.binary_search(&(from, to)).is_err() {
+                events.push(GraphEvent::EdgeRemoved { from, to });
+            }
+        }
+        events
+    }
+}
+
+/// A change to a `Graph`, as published to its subscribers.
+///
+/// Removing a node implicitly takes its edges with it, without separate `EdgeRemoved` events.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum GraphEvent {
+    NodeAdded(NodeIndex),
+    NodeUpdated(NodeIndex),
+    NodeRemoved(NodeIndex),
+    EdgeAdded { from: NodeIndex, to: NodeIndex },
+    EdgeRemoved { from: NodeIndex, to: NodeIndex },
+}
+
+/// Identifies a subscriber so that it can be unsubscribed later.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct SubscriptionId(u64);
+
+pub type Subscriber = Box<dyn Fn(&GraphEvent) + Send>;
+
+/// Keeps track of who is subscribed to a graph, for `Graph` implementations to publish to.
+#[derive(Default)]
+pub struct Subscribers {
+    next_id: u64,
+    subscribers: Vec<(SubscriptionId, Subscriber)>,
+    /// The events of the current transaction, which are held back until it's committed.
+    pending: Option<Vec<GraphEvent>>,
+}
+
+impl Subscribers {
+    pub fn subscribe(&mut self, subscriber: Subscriber) -> SubscriptionId {
+        let id = SubscriptionId(self.next_id);
+        self.next_id += 1;
+        self.subscribers.push((id, subscriber));
+        id
+    }
+
+    pub fn unsubscribe(&mut self, id: SubscriptionId) {
+        self.subscribers
+            .retain(|(subscriber_id, _)| *subscriber_id != id);
+    }
+
+    pub fn publish(&mut self, event: GraphEvent) {
+        match &mut self.pending {
+            Some(pending) => pending.push(event),
+            None => {
+                for (_, subscriber) in &self.subscribers {
+                    subscriber(&event);
+                }
+            }
+        }
+    }
+
+    pub fn begin_transaction(&mut self) {
+        self.pending = Some(vec![]);
+    }
+
+    pub fn commit_transaction(&mut self) {
+        for event in self.pending.take().unwrap_or_default() {
+            self.publish(event);
+        }
+    }
+
+    pub fn rollback_transaction(&mut self) {
+        self.pending = None;
+    }
+}
+
+/// Holds on to a graph's latest snapshot until the graph changes.
+#[derive(Default)]
+pub(crate) struct SnapshotCache(RefCell<Option<Arc<GraphSnapshot>>>);
+
+impl SnapshotCache {
+    pub(crate) fn get_or_load(
+        &self,
+        load: impl FnOnce() -> anyhow::Result<GraphSnapshot>,
+    ) -> anyhow::Result<Arc<GraphSnapshot>> {
+        if let Some(snapshot) = &*self.0.borrow() {
+            return Ok(snapshot.clone());
+        }
+        let snapshot = Arc::new(load()?);
+        *self.0.borrow_mut() = Some(snapshot.clone());
+        Ok(snapshot)
+    }
+
+    /// Must be called whenever the graph changes.
+    pub(crate) fn invalidate(&mut self) {
+        self.0.get_mut().take();
+    }
+}
 
 pub trait Graph {
     fn add_edge(&mut self, from: NodeIndex, to: NodeIndex) -> anyhow::Result<()>;
-    fn add_node(&mut self, node: Node) -> anyhow::Result<NodeIndex>;
+    /// Adds `node` under an index it had before, e.g. to bring back a node which was removed.
+    /// It comes back without any edges. Fails if there's already a node at `index`.
+    fn insert_node(&mut self, index: NodeIndex, node: Node) -> anyhow::Result<()>;
+    fn remove_edge(&mut self, from: NodeIndex, to: NodeIndex) -> anyhow::Result<()>;
     fn get_node(&self, index: NodeIndex) -> anyhow::Result<Node>;
     // TODO: make these some kind of iterator that won't need us to do heap allocation all the time
     fn neighbors(&self, index: NodeIndex) -> anyhow::Result<Vec<NodeIndex>>;
+    /// Returns the nodes which have an edge pointing to `index`,
+    /// i.e. the nodes which `index` blocks.
+    fn parents(&self, index: NodeIndex) -> anyhow::Result<Vec<NodeIndex>>;
     fn node_indices(&self) -> anyhow::Result<Vec<NodeIndex>>;
     fn remove_node(&mut self, index: NodeIndex) -> anyhow::Result<()>;
     fn set_node(&mut self, index: NodeIndex, node: Node) -> anyhow::Result<()>;
+    /// Returns when the edge from `from` to `to` was added,
+    /// or `None` if that wasn't recorded.
+    fn edge_added_at(
+        &self,
+        from: NodeIndex,
+        to: NodeIndex,
+    ) -> anyhow::Result<Option<DateTime<Utc>>>;
+    /// Takes the edge from `from` to `to` out of the graph, but remembers it
+    /// so that it can still be shown. Adding the edge again brings it back.
+    fn set_aside_edge(&mut self, from: NodeIndex, to: NodeIndex) -> anyhow::Result<()>;
+    /// Returns the edges between existing nodes which were taken out by `set_aside_edge`.
+    fn set_aside_edges(&self) -> anyhow::Result<Vec<(NodeIndex, NodeIndex)>>;
+    /// Starts a transaction: the changes made until `commit_transaction` are kept all together,
+    /// or thrown away all together by `rollback_transaction`. Transactions can't be nested.
+    fn begin_transaction(&mut self) -> anyhow::Result<()>;
+    fn commit_transaction(&mut self) -> anyhow::Result<()>;
+    fn rollback_transaction(&mut self) -> anyhow::Result<()>;
+    /// Calls `subscriber` with every change made to the graph from now on,
+    /// until it's unsubscribed. Changes made in a transaction are published once it's committed.
+    ///
+    /// Subscribers are called in the middle of changing the graph, so they mustn't use it.
+    fn subscribe(&mut self, subscriber: Subscriber) -> SubscriptionId;
+    fn unsubscribe(&mut self, id: SubscriptionId);
+
+    /// Returns every node and edge at once,
+    /// which is much cheaper than asking for them one by one for some graphs.
+    fn snapshot(&self) -> anyhow::Result<Arc<GraphSnapshot>> {
+        Ok(Arc::new(GraphSnapshot::load(self)?))
+    }
+
+    /// Picks up changes which something else, like another process, wrote to where the graph
+    /// is stored, and publishes them to subscribers. Returns whether anything changed.
+    fn refresh(&mut self) -> anyhow::Result<bool> {
+        Ok(false)
+    }
+
+    /// Runs `f` in a transaction, which is committed if `f` succeeds and rolled back if it fails.
+    fn transaction<T>(
+        &mut self,
+        f: impl FnOnce(&mut Self) -> anyhow::Result<T>,
+    ) -> anyhow::Result<T>
+    where
+        Self: Sized,
+    {
+        self.begin_transaction()?;
+        match f(self) {
+            Ok(value) => {
+                self.commit_transaction()?;
+                Ok(value)
+            }
+            Err(e) => {
+                self.rollback_transaction()?;
+                Err(e)
+            }
+        }
+    }
+
+    fn add_node(&mut self, node: Node) -> anyhow::Result<NodeIndex> {
+        let index = Uuid::now_v7();
+        self.insert_node(index, node)?;
+        Ok(index)
+    }
+
+    /// Marks the node at `index` as done.
+    /// Nodes which are already done keep their original completion time.
+    fn mark_completed(&mut self, index: NodeIndex) -> anyhow::Result<()> {
+        let mut node = self.get_node(index)?;
+        if node.completed_at.is_none() {
+            node.completed_at = Some(Utc::now());
+            self.set_node(index, node)?;
+        }
+        Ok(())
+    }
+
+    /// Marks the node at `index` as not done.
+    fn mark_incomplete(&mut self, index: NodeIndex) -> anyhow::Result<()> {
+        let mut node = self.get_node(index)?;
+        if node.completed_at.is_some() {
+            node.completed_at = None;
+            self.set_node(index, node)?;
+        }
+        Ok(())
+    }
+
+    /// Flips the node at `index` between done and not done.
+    fn toggle_completed(&mut self, index: NodeIndex) -> anyhow::Result<()> {
+        if self.get_node(index)?.is_completed() {
+            self.mark_incomplete(index)
+        } else {
+            self.mark_completed(index)
+        }
+    }
+
+    /// Returns every node reachable by following edges backwards from `index`,
+    /// in no particular order.
+    fn ancestors(&self, index: NodeIndex) -> anyhow::Result<Vec<NodeIndex>> {
+        let mut visited = std::collections::HashSet::new();
+        let mut ancestors = vec![];
+        let mut stack = self.parents(index)?;
+        while let Some(current) = stack.pop() {
+            if visited.insert(current) {
+                ancestors.push(current);
+                stack.extend(self.parents(current)?);
+            }
+        }
+        Ok(ancestors)
+    }
+
+    /// Returns every node reachable by following edges forwards from `index`,
+    /// in no particular order.
+    fn descendants(&self, index: NodeIndex) -> anyhow::Result<Vec<NodeIndex>> {
+        let mut visited = std::collections::HashSet::new();
+        let mut descendants = vec![];
+        let mut stack = self.neighbors(index)?;
+        while let Some(current) = stack.pop() {
+            if visited.insert(current) {
+                descendants.push(current);
+                stack.extend(self.neighbors(current)?);
+            }
+        }
+        Ok(descendants)
+    }
 
     /// Returns true if adding an edge from `from` to `to` would create a cycle.
     fn would_create_cycle(&self, from: NodeIndex, to: NodeIndex) -> anyhow::Result<bool> {
@@ -38,224 +462,654 @@ pub trait Graph {
         }
         Ok(false)
     }
+
+    /// Returns every cycle in the graph, each as the list of nodes along it:
+    /// every node has an edge to the next one, and the last node has an edge to the first.
+    /// Each cycle starts at its smallest index, and the cycles are sorted.
+    ///
+    /// `add_edge` never creates cycles, but merging replicas or importing data can.
+    fn find_cycles(&self) -> anyhow::Result<Vec<Vec<NodeIndex>>> {
+        let snapshot = self.snapshot()?;
+        let mut graph = DiGraphMap::<NodeIndex, ()>::new();
+        for (index, _) in snapshot.nodes() {
+            graph.add_node(index);
+        }
+        for (from, to) in snapshot.edges() {
+            graph.add_edge(*from, *to, ());
+        }
+        // Every cycle stays inside one strongly connected component, so only those which have
+        // a cycle in them need searching, and the rest of the graph, however big, doesn't.
+        let mut cycles = vec![];
+        for mut component in tarjan_scc(&graph) {
+            if component.len() == 1 && !graph.contains_edge(component[0], component[0]) {
+                continue;
+            }
+            let members: HashSet<NodeIndex> = component.iter().copied().collect();
+            component.sort();
+            for start in component {
+                collect_cycles(&snapshot, &members, &mut vec![start], &mut cycles);
+            }
+        }
+        cycles.sort();
+        Ok(cycles)
+    }
+
+    /// Breaks every cycle in the graph by setting aside the most recently added edge in it.
+    /// Returns the edges which were set aside.
+    ///
+    /// Edges are compared by `edge_added_at` and then by their indices,
+    /// so replicas with the same edges set aside the same ones.
+    fn repair_cycles(&mut self) -> anyhow::Result<Vec<(NodeIndex, NodeIndex)>> {
+        let mut set_aside: Vec<(NodeIndex, NodeIndex)> = vec![];
+        for cycle in self.find_cycles()? {
+            let edges: Vec<(NodeIndex, NodeIndex)> = cycle
+                .iter()
+                .zip(cycle.iter().cycle().skip(1))
+                .map(|(from, to)| (*from, *to))
+                .collect();
+            if edges.iter().any(|edge| set_aside.contains(edge)) {
+                continue;
+            }
+            let mut newest = None;
+            for (from, to) in edges {
+                let key = (self.edge_added_at(from, to)?, from, to);
+                if newest.as_ref().is_none_or(|newest| key > *newest) {
+                    newest = Some(key);
+                }
+            }
+            if let Some((_, from, to)) = newest {
+                self.set_aside_edge(from, to)?;
+                set_aside.push((from, to));
+            }
+        }
+        Ok(set_aside)
+    }
+}
+
+/// Adds every cycle which starts with `path` to `cycles`, only going through `members` of
+/// its strongly connected component with larger indices than the first one, so each cycle is
+/// found once.
+fn collect_cycles(
+    snapshot: &GraphSnapshot,
+    members: &HashSet<NodeIndex>,
+    path: &mut Vec<NodeIndex>,
+    cycles: &mut Vec<Vec<NodeIndex>>,
+) {
+    let start = path[0];
+    let current = path[path.len() - 1];
+    for next in snapshot.neighbors(current) {
+        if next == start {
+            cycles.push(path.clone());
+        } else if next > start && members.contains(&next) && !path.contains(&next) {
+            path.push(next);
+            collect_cycles(snapshot, members, path, cycles);
+            path.pop();
+        }
+    }
 }
 
 #[derive(Default)]
-pub struct PetgraphGraph(DiGraph<Node, (), NodeIndex>);
+pub struct PetgraphGraph {
+    /// Edges are weighted by when they were added.
+    graph: StableDiGraph<(NodeIndex, Node), DateTime<Utc>>,
+    indices: HashMap<NodeIndex, PetgraphNodeIndex>,
+    set_aside: Vec<(NodeIndex, NodeIndex)>,
+    /// The graph as it was when the current transaction began.
+    /// The changes made since then are only staged until the transaction is committed,
+    /// and rolling back puts this back in place.
+    committed: Option<Box<PetgraphGraph>>,
+    subscribers: Subscribers,
+}
+
+impl PetgraphGraph {
+    fn petgraph_index(&self, index: NodeIndex) -> anyhow::Result<PetgraphNodeIndex> {
+        match self.indices.get(&index) {
+            Some(petgraph_index) => Ok(*petgraph_index),
+            None => anyhow::bail!("Node {} does not exist", index),
+        }
+    }
+
+    fn node_indices_directed(
+        &self,
+        index: NodeIndex,
+        direction: Direction,
+    ) -> anyhow::Result<Vec<NodeIndex>> {
+        Ok(self
+            .graph
+            .neighbors_directed(self.petgraph_index(index)?, direction)
+            .map(|petgraph_index| self.graph[petgraph_index].0)
+            .collect())
+    }
+}
 
 impl Graph for PetgraphGraph {
     fn add_edge(&mut self, from: NodeIndex, to: NodeIndex) -> anyhow::Result<()> {
         if self.would_create_cycle(from, to)? {
             anyhow::bail!("Adding edge would create a cycle");
         }
-        self.0.update_edge(from.into(), to.into(), ());
+        self.set_aside.retain(|edge| *edge != (from, to));
+        let from_index = self.petgraph_index(from)?;
+        let to_index = self.petgraph_index(to)?;
+        if self.graph.find_edge(from_index, to_index).is_none() {
+            self.graph.add_edge(from_index, to_index, Utc::now());
+            self.subscribers.publish(GraphEvent::EdgeAdded { from, to });
+        }
         Ok(())
     }
 
-    fn add_node(&mut self, node: Node) -> anyhow::Result<NodeIndex> {
-        let index = self.0.add_node(node);
-        Ok(index.index())
+    fn insert_node(&mut self, index: NodeIndex, node: Node) -> anyhow::Result<()> {
+        if self.indices.contains_key(&index) {
+            anyhow::bail!("Node {} already exists", index);
+        }
+        let petgraph_index = self.graph.add_node((index, node));
+        self.indices.insert(index, petgraph_index);
+        self.subscribers.publish(GraphEvent::NodeAdded(index));
+        Ok(())
+    }
+
+    fn remove_edge(&mut self, from: NodeIndex, to: NodeIndex) -> anyhow::Result<()> {
+        self.set_aside.retain(|edge| *edge != (from, to));
+        let from_index = self.petgraph_index(from)?;
+        let to_index = self.petgraph_index(to)?;
+        if let Some(edge) = self.graph.find_edge(from_index, to_index) {
+            self.graph.remove_edge(edge);
+            self.subscribers
+                .publish(GraphEvent::EdgeRemoved { from, to });
+        }
+        Ok(())
     }
 
     fn get_node(&self, index: NodeIndex) -> anyhow::Result<Node> {
-        let node: Node = self.0[PetgraphNodeIndex::from(index)].clone();
+        let node: Node = self.graph[self.petgraph_index(index)?].1.clone();
         Ok(node)
     }
 
     fn neighbors(&self, index: NodeIndex) -> anyhow::Result<Vec<NodeIndex>> {
-        Ok(self
-            .0
-            .neighbors(index.into())
-            .map(PetgraphNodeIndex::index)
-            .collect())
+        self.node_indices_directed(index, Direction::Outgoing)
+    }
+
+    fn parents(&self, index: NodeIndex) -> anyhow::Result<Vec<NodeIndex>> {
+        self.node_indices_directed(index, Direction::Incoming)
     }
 
     fn node_indices(&self) -> anyhow::Result<Vec<NodeIndex>> {
-        Ok(self
-            .0
-            .node_indices()
-            .map(PetgraphNodeIndex::index)
-            .collect())
+        Ok(self.graph.node_weights().map(|(index, _)| *index).collect())
     }
 
     fn remove_node(&mut self, index: NodeIndex) -> anyhow::Result<()> {
-        self.0.remove_node(index.into());
+        if let Some(petgraph_index) = self.indices.remove(&index) {
+            self.graph.remove_node(petgraph_index);
+            self.subscribers.publish(GraphEvent::NodeRemoved(index));
+        }
         Ok(())
     }
 
     fn set_node(&mut self, index: NodeIndex, node: Node) -> anyhow::Result<()> {
-        self.0[PetgraphNodeIndex::from(index)] = node;
+        let petgraph_index = self.petgraph_index(index)?;
+        self.graph[petgraph_index].1 = node;
+        self.subscribers.publish(GraphEvent::NodeUpdated(index));
+        Ok(())
+    }
+
+    fn edge_added_at(
+        &self,
+        from: NodeIndex,
+        to: NodeIndex,
+    ) -> anyhow::Result<Option<DateTime<Utc>>> {
+        let edge = self
+            .graph
+            .find_edge(self.petgraph_index(from)?, self.petgraph_index(to)?);
+        Ok(edge.map(|edge| self.graph[edge]))
+    }
+
+    fn set_aside_edge(&mut self, from: NodeIndex, to: NodeIndex) -> anyhow::Result<()> {
+        let from_index = self.petgraph_index(from)?;
+        let to_index = self.petgraph_index(to)?;
+        let Some(edge) = self.graph.find_edge(from_index, to_index) else {
+            anyhow::bail!("Edge from {} to {} does not exist", from, to);
+        };
+        self.graph.remove_edge(edge);
+        self.set_aside.push((from, to));
+        self.subscribers
+            .publish(GraphEvent::EdgeRemoved { from, to });
+        Ok(())
+    }
+
+    fn set_aside_edges(&self) -> anyhow::Result<Vec<(NodeIndex, NodeIndex)>> {
+        Ok(self
+            .set_aside
+            .iter()
+            .filter(|(from, to)| self.indices.contains_key(from) && self.indices.contains_key(to))
+            .copied()
+            .collect())
+    }
+
+    fn begin_transaction(&mut self) -> anyhow::Result<()> {
+        if self.committed.is_some() {
+            anyhow::bail!("A transaction is already in progress");
+        }
+        self.committed = Some(Box::new(PetgraphGraph {
+            graph: self.graph.clone(),
+            indices: self.indices.clone(),
+            set_aside: self.set_aside.clone(),
+            committed: None,
+            subscribers: Subscribers::default(),
+        }));
+        self.subscribers.begin_transaction();
+        Ok(())
+    }
+
+    fn commit_transaction(&mut self) -> anyhow::Result<()> {
+        if self.committed.take().is_none() {
+            anyhow::bail!("No transaction is in progress");
+        }
+        self.subscribers.commit_transaction();
+        Ok(())
+    }
+
+    fn rollback_transaction(&mut self) -> anyhow::Result<()> {
+        let Some(committed) = self.committed.take() else {
+            anyhow::bail!("No transaction is in progress");
+        };
+        let mut subscribers = std::mem::take(&mut self.subscribers);
+        subscribers.rollback_transaction();
+        *self = *committed;
+        self.subscribers = subscribers;
         Ok(())
     }
+
+    fn subscribe(&mut self, subscriber: Subscriber) -> SubscriptionId {
+        self.subscribers.subscribe(subscriber)
+    }
+
+    fn unsubscribe(&mut self, id: SubscriptionId) {
+        self.subscribers.unsubscribe(id);
+    }
 }
 
 pub struct DatabaseGraph {
     conn: Connection,
+    snapshot: SnapshotCache,
+    subscribers: Subscribers,
+    /// The `data_version` when `seen` was taken, which changes whenever another
+    /// connection writes to the database.
+    data_version: i64,
+    /// The graph as of the last `refresh`.
+    seen: Arc<GraphSnapshot>,
 }
 
-impl Default for DatabaseGraph {
-    fn default() -> Self {
-        // Self::open_in_memory().expect("Failed to open DatabaseGraph in memory.")
-        Self::open(".tmp/db.sqlite").expect("Failed to open DatabaseGraph at .tmp/dbsqlite")
-    }
+/// A node which has been removed from a [`DatabaseGraph`]
+/// but which can still be restored until it is purged.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DeletedNode {
+    pub index: NodeIndex,
+    pub node: Node,
+    pub deleted_at: DateTime<Utc>,
 }
 
+/// How long to wait for another connection to finish writing before giving up.
+const BUSY_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
 impl DatabaseGraph {
     pub fn open(path: impl AsRef<Path>) -> anyhow::Result<Self> {
-        let conn = Connection::open(path.as_ref())?;
-        let mut db = Self { conn };
-        db.migrate()?;
-        Ok(db)
+        let path = path.as_ref();
+        let open = || {
+            let conn = Connection::open(path)?;
+            // Lets other processes keep reading while we write, and the other way around.
+            conn.pragma_update_and_check(None, "journal_mode", "WAL", |row| {
+                row.get::<_, String>(0)
+            })?;
+            conn.busy_timeout(BUSY_TIMEOUT)?;
+            Self::from_connection(conn)
+        };
+        open().with_context(|| format!("Failed to open database at {}", path.display()))
+    }
+
+    /// Opens an existing database without ever writing to it. Every change to the graph fails.
+    pub fn open_read_only(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let open = || {
+            let conn = Connection::open_with_flags(
+                path,
+                OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+            )?;
+            conn.busy_timeout(BUSY_TIMEOUT)?;
+            Self::from_connection(conn)
+        };
+        open().with_context(|| format!("Failed to open database at {} read-only", path.display()))
+    }
+
+    /// Where the database lives when nobody says otherwise: `ekad/db.sqlite`
+    /// in the user's data directory.
+    pub fn default_path() -> anyhow::Result<PathBuf> {
+        Ok(data_dir()?.join("ekad").join("db.sqlite"))
+    }
+
+    /// Opens the database at `default_path`, creating it if it doesn't exist yet.
+    pub fn open_default() -> anyhow::Result<Self> {
+        let path = Self::default_path()?;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("Failed to create {}", dir.display()))?;
+        }
+        Self::open(path)
     }
 
     pub fn open_in_memory() -> anyhow::Result<Self> {
-        let conn = Connection::open_in_memory()?;
-        let mut db = Self { conn };
+        Self::from_connection(Connection::open_in_memory()?)
+    }
+
+    fn from_connection(conn: Connection) -> anyhow::Result<Self> {
+        register_functions(&conn)?;
+        let mut db = Self {
+            conn,
+            snapshot: SnapshotCache::default(),
+            subscribers: Subscribers::default(),
+            data_version: 0,
+            seen: Arc::default(),
+        };
         db.migrate()?;
+        db.data_version = db.data_version()?;
+        db.seen = db.snapshot()?;
         Ok(db)
     }
 
-    fn migrate(&mut self) -> anyhow::Result<()> {
-        self.conn.execute_batch(
+    fn data_version(&self) -> anyhow::Result<i64> {
+        Ok(self
+            .conn
+            .pragma_query_value(None, "data_version", |row| row.get(0))?)
+    }
+
+    /// Lists every node which has been removed but not yet purged,
+    /// most recently deleted first.
+    pub fn deleted_nodes(&self) -> anyhow::Result<Vec<DeletedNode>> {
+        let mut stmt = self.conn.prepare(
             r#"
-            CREATE TABLE IF NOT EXISTS tasks (
-                id INTEGER PRIMARY KEY AUTOINCREMENT,
-                title VARCHAR NOT NULL,
-                x REAL NOT NULL,
-                y REAL NOT NULL,
-                radius REAL NOT NULL,
-                deleted_at DATETIME DEFAULT NULL,
-                completed_at DATETIME DEFAULT NULL,
-                description VARCHAR DEFAULT NULL
-            );
-            CREATE TABLE IF NOT EXISTS task_links (
-            	parent_id INTEGER NOT NULL,
-            	child_id INTEGER NOT NULL,
-            	PRIMARY KEY (parent_id, child_id),
-            	FOREIGN KEY (parent_id) REFERENCES tasks(id),
-            	FOREIGN KEY (child_id) REFERENCES tasks(id)
-            );
+            SELECT id, title, x, y, radius, completed_at, description, metadata, deleted_at
+            FROM tasks
+            WHERE deleted_at IS NOT NULL
+            ORDER BY deleted_at DESC
             "#,
         )?;
-        Ok(())
+        let mut rows = stmt.query(())?;
+        let mut deleted_nodes = vec![];
+        while let Some(row) = rows.next()? {
+            deleted_nodes.push(DeletedNode {
+                index: row.get("id")?,
+                node: node_from_row(row)?,
+                deleted_at: row.get("deleted_at")?,
+            });
+        }
+        Ok(deleted_nodes)
     }
-}
 
-impl Graph for DatabaseGraph {
-    fn add_edge(&mut self, from: NodeIndex, to: NodeIndex) -> anyhow::Result<()> {
-        if self.would_create_cycle(from, to)? {
-            anyhow::bail!("Adding edge would create a cycle");
+    /// Brings back a removed node along with the edges it had when it was removed.
+    /// Fails if the graph has changed in a way that restoring the node would create a cycle.
+    pub fn restore_node(&mut self, index: NodeIndex) -> anyhow::Result<()> {
+        self.snapshot.invalidate();
+        let parents = self.parents(index)?;
+        let children = self.neighbors(index)?;
+        for parent in &parents {
+            for child in &children {
+                if self.would_create_cycle(*parent, *child)? {
+                    anyhow::bail!("Restoring node would create a cycle");
+                }
+            }
         }
-        self.conn.execute(
+
+        let updated = self.conn.execute(
             r#"
-            INSERT OR REPLACE INTO task_links (
-                parent_id,
-                child_id
-            ) VALUES (
-                ?,
-                ?
-            )
+            UPDATE tasks
+            SET deleted_at = NULL
+            WHERE id = ?
+              AND deleted_at IS NOT NULL
             "#,
-            (from, to),
+            (index,),
         )?;
+        if updated == 0 {
+            anyhow::bail!("Node {} is not deleted", index);
+        }
+        self.subscribers.publish(GraphEvent::NodeAdded(index));
         Ok(())
     }
 
-    fn add_node(&mut self, node: Node) -> anyhow::Result<NodeIndex> {
-        self.conn.execute(
+    /// Permanently removes nodes (and their edges)
+    /// which were deleted more than `retention` ago.
+    /// Returns the number of nodes which were purged.
+    pub fn purge_deleted(&mut self, retention: Duration) -> anyhow::Result<usize> {
+        self.snapshot.invalidate();
+        let cutoff = Utc::now() - retention;
+        let tx = self.conn.transaction()?;
+        tx.execute(
             r#"
-            INSERT INTO tasks (
-                title,
-                x,
-                y,
-                radius
-            ) VALUES (
-                '',
-                ?,
-                ?,
-                ?
-            )
+            DELETE FROM task_links
+            WHERE parent_id IN (SELECT id FROM tasks WHERE deleted_at < ?1)
+               OR child_id IN (SELECT id FROM tasks WHERE deleted_at < ?1)
             "#,
-            (
-                node.circle.center.x,
-                node.circle.center.y,
-                node.circle.radius,
-            ),
+            (cutoff,),
         )?;
-        Ok(self.conn.last_insert_rowid() as usize)
+        let purged = tx.execute("DELETE FROM tasks WHERE deleted_at < ?", (cutoff,))?;
+        tx.commit()?;
+        Ok(purged)
     }
 
-    fn get_node(&self, index: NodeIndex) -> anyhow::Result<Node> {
-        let mut stmt = self
-            .conn
-            .prepare("SELECT title, x, y, radius FROM tasks WHERE id = ?")?;
-        let node: Node = stmt.query_row((index,), |row| {
-            Ok(Node {
-                title: row.get("title")?,
-                circle: Circle::new(Point::new(row.get("x")?, row.get("y")?), row.get("radius")?),
-            })
-        })?;
-        Ok(node)
-    }
-
-    fn neighbors(&self, index: NodeIndex) -> anyhow::Result<Vec<NodeIndex>> {
-        let mut stmt = self
-            .conn
-            .prepare("SELECT child_id FROM task_links WHERE parent_id = ?")?;
+    fn linked_ids(&self, query: &str, index: NodeIndex) -> anyhow::Result<Vec<NodeIndex>> {
+        let mut stmt = self.conn.prepare(query)?;
         let mut rows = stmt.query((index,))?;
         let mut indices = vec![];
         while let Some(row) = rows.next()? {
-            indices.push(row.get("child_id")?);
+            indices.push(row.get("id")?);
         }
         Ok(indices)
     }
 
-    fn node_indices(&self) -> anyhow::Result<Vec<NodeIndex>> {
-        let mut stmt = self.conn.prepare("SELECT id FROM tasks")?;
-        let mut rows = stmt.query(())?;
-        let mut indices = vec![];
-        while let Some(row) = rows.next()? {
-            indices.push(row.get("id")?);
+    fn migrate(&mut self) -> anyhow::Result<()> {
+        let version: usize = self
+            .conn
+            .pragma_query_value(None, "user_version", |row| row.get(0))?;
+        if version > MIGRATIONS.len() {
+            anyhow::bail!(
+                "Database has schema version {}, but this version of ekad only supports up to {}",
+                version,
+                MIGRATIONS.len(),
+            );
         }
-        Ok(indices)
+
+        if version < MIGRATIONS.len() && self.conn.is_readonly(DatabaseName::Main)? {
+            anyhow::bail!(
+                "Database has schema version {}, and needs upgrading to {} before it can be opened read-only",
+                version,
+                MIGRATIONS.len(),
+            );
+        }
+        for (i, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+            let tx = self.conn.transaction()?;
+            tx.execute_batch(migration)?;
+            tx.pragma_update(None, "user_version", i + 1)?;
+            tx.commit()?;
+        }
+        Ok(())
     }
+}
 
-    fn remove_node(&mut self, index: NodeIndex) -> anyhow::Result<()> {
-        let tx = self.conn.transaction()?;
-        tx.execute(
-            r#"
-            DELETE FROM task_links
-            WHERE parent_id = ?
-               OR child_id = ?
-            "#,
-            (index, index),
-        )?;
-        tx.execute(
+/// The per-user directory for application data, following each platform's conventions.
+fn data_dir() -> anyhow::Result<PathBuf> {
+    let var = |name| std::env::var_os(name).filter(|value| !value.is_empty());
+    let dir = if cfg!(windows) {
+        var("APPDATA").map(PathBuf::from)
+    } else if cfg!(target_os = "macos") {
+        var("HOME").map(|home| Path::new(&home).join("Library/Application Support"))
+    } else {
+        var("XDG_DATA_HOME")
+            .map(PathBuf::from)
+            .or_else(|| var("HOME").map(|home| Path::new(&home).join(".local/share")))
+    };
+    dir.context("Couldn't find a directory for user data: is $HOME set?")
+}
+
+/// Registers the SQL functions which migrations rely on.
+fn register_functions(conn: &Connection) -> rusqlite::Result<()> {
+    // Lets migrations mint IDs for rows which predate them.
+    conn.create_scalar_function("uuid7", 0, FunctionFlags::SQLITE_UTF8, |_| {
+        Ok(Uuid::now_v7())
+    })
+}
+
+/// Schema migrations for `DatabaseGraph`, applied in order.
+///
+/// A database's `user_version` records how many of these have been applied to it,
+/// so existing migrations must never be changed or reordered: only append new ones.
+const MIGRATIONS: &[&str] = &[
+    // Databases created before migrations were versioned already have these tables,
+    // hence the IF NOT EXISTS.
+    r#"
+    CREATE TABLE IF NOT EXISTS tasks (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        title VARCHAR NOT NULL,
+        x REAL NOT NULL,
+        y REAL NOT NULL,
+        radius REAL NOT NULL,
+        deleted_at DATETIME DEFAULT NULL,
+        completed_at DATETIME DEFAULT NULL,
+        description VARCHAR DEFAULT NULL
+    );
+    CREATE TABLE IF NOT EXISTS task_links (
+        parent_id INTEGER NOT NULL,
+        child_id INTEGER NOT NULL,
+        PRIMARY KEY (parent_id, child_id),
+        FOREIGN KEY (parent_id) REFERENCES tasks(id),
+        FOREIGN KEY (child_id) REFERENCES tasks(id)
+    );
+    "#,
+    r#"
+    CREATE INDEX IF NOT EXISTS task_links_child_id ON task_links (child_id);
+    "#,
+    // Replaces the AUTOINCREMENT ids with UUIDv7s.
+    r#"
+    ALTER TABLE tasks ADD COLUMN uuid BLOB;
+    -- Rows are visited in id order, so the new ids keep the same ordering as the old ones.
+    UPDATE tasks SET uuid = uuid7();
+
+    CREATE TABLE tasks_v3 (
+        id BLOB PRIMARY KEY NOT NULL,
+        title VARCHAR NOT NULL,
+        x REAL NOT NULL,
+        y REAL NOT NULL,
+        radius REAL NOT NULL,
+        deleted_at DATETIME DEFAULT NULL,
+        completed_at DATETIME DEFAULT NULL,
+        description VARCHAR DEFAULT NULL
+    );
+    INSERT INTO tasks_v3
+    SELECT uuid, title, x, y, radius, deleted_at, completed_at, description
+    FROM tasks;
+
+    CREATE TABLE task_links_v3 (
+        parent_id BLOB NOT NULL,
+        child_id BLOB NOT NULL,
+        PRIMARY KEY (parent_id, child_id),
+        FOREIGN KEY (parent_id) REFERENCES tasks(id),
+        FOREIGN KEY (child_id) REFERENCES tasks(id)
+    );
+    INSERT INTO task_links_v3
+    SELECT parents.uuid, children.uuid
+    FROM task_links
+    JOIN tasks AS parents ON parents.id = task_links.parent_id
+    JOIN tasks AS children ON children.id = task_links.child_id;
+
+    DROP TABLE task_links;
+    DROP TABLE tasks;
+    ALTER TABLE tasks_v3 RENAME TO tasks;
+    ALTER TABLE task_links_v3 RENAME TO task_links;
+    CREATE INDEX task_links_child_id ON task_links (child_id);
+    "#,
+    // Edges which already exist have no `created_at`, so they count as the oldest.
+    r#"
+    ALTER TABLE task_links ADD COLUMN created_at DATETIME DEFAULT NULL;
+    ALTER TABLE task_links ADD COLUMN set_aside_at DATETIME DEFAULT NULL;
+    "#,
+    // A JSON object of strings.
+    r#"
+    ALTER TABLE tasks ADD COLUMN metadata VARCHAR DEFAULT NULL;
+    "#,
+];
+
+impl Graph for DatabaseGraph {
+    fn add_edge(&mut self, from: NodeIndex, to: NodeIndex) -> anyhow::Result<()> {
+        self.snapshot.invalidate();
+        if self.would_create_cycle(from, to)? {
+            anyhow::bail!("Adding edge would create a cycle");
+        }
+        // Adding an edge which is already there keeps its `created_at`,
+        // but adding back one which was set aside counts as adding it anew.
+        let added = self.conn.execute(
             r#"
-            DELETE FROM tasks WHERE id == ?
+            INSERT INTO task_links (
+                parent_id,
+                child_id,
+                created_at
+            ) VALUES (
+                ?,
+                ?,
+                ?
+            )
+            ON CONFLICT (parent_id, child_id) DO UPDATE
+            SET created_at = excluded.created_at,
+                set_aside_at = NULL
+            WHERE set_aside_at IS NOT NULL
             "#,
-            (index,),
+            (from, to, Utc::now()),
         )?;
-        tx.commit()?;
+        if added > 0 {
+            self.subscribers.publish(GraphEvent::EdgeAdded { from, to });
+        }
         Ok(())
     }
 
-    fn set_node(&mut self, index: NodeIndex, node: Node) -> anyhow::Result<()> {
+    fn insert_node(&mut self, index: NodeIndex, node: Node) -> anyhow::Result<()> {
+        self.snapshot.invalidate();
+        // A node which was removed is still in the table, so bring it back instead, but
+        // without the edges it had, which could make cycles now. Only `restore_node` checks
+        // whether those can come back.
         self.conn.execute(
             r#"
-            INSERT OR REPLACE INTO tasks (
+            DELETE FROM task_links
+            WHERE (parent_id = ?1 OR child_id = ?1)
+              AND EXISTS (SELECT 1 FROM tasks WHERE id = ?1 AND deleted_at IS NOT NULL)
+            "#,
+            (index,),
+        )?;
+        let inserted = self.conn.execute(
+            r#"
+            INSERT INTO tasks (
                 id,
                 title,
                 x,
                 y,
-                radius
+                radius,
+                completed_at,
+                description,
+                metadata
             ) VALUES (
+                ?,
+                ?,
+                ?,
                 ?,
                 ?,
                 ?,
                 ?,
                 ?
             )
+            ON CONFLICT (id) DO UPDATE
+            SET title = excluded.title,
+                x = excluded.x,
+                y = excluded.y,
+                radius = excluded.radius,
+                completed_at = excluded.completed_at,
+                description = excluded.description,
+                metadata = excluded.metadata,
+                deleted_at = NULL
+            WHERE deleted_at IS NOT NULL
             "#,
             (
                 index,
@@ -263,8 +1117,1117 @@ impl Graph for DatabaseGraph {
                 node.circle.center.x,
                 node.circle.center.y,
                 node.circle.radius,
+                node.completed_at,
+                node.description,
+                metadata_to_json(&node.metadata).to_string(),
             ),
         )?;
+        if inserted == 0 {
+            anyhow::bail!("Node {} already exists", index);
+        }
+        self.subscribers.publish(GraphEvent::NodeAdded(index));
+        Ok(())
+    }
+
+    fn remove_edge(&mut self, from: NodeIndex, to: NodeIndex) -> anyhow::Result<()> {
+        self.snapshot.invalidate();
+        let removed = self.conn.execute(
+            r#"
+            DELETE FROM task_links
+            WHERE parent_id = ?
+              AND child_id = ?
+            "#,
+            (from, to),
+        )?;
+        if removed > 0 {
+            self.subscribers
+                .publish(GraphEvent::EdgeRemoved { from, to });
+        }
+        Ok(())
+    }
+
+    fn get_node(&self, index: NodeIndex) -> anyhow::Result<Node> {
+        let mut stmt = self.conn.prepare(
+            "SELECT title, x, y, radius, completed_at, description, metadata FROM tasks WHERE id = ?",
+        )?;
+        let node: Node = stmt.query_row((index,), node_from_row)?;
+        Ok(node)
+    }
+
+    fn neighbors(&self, index: NodeIndex) -> anyhow::Result<Vec<NodeIndex>> {
+        self.linked_ids(
+            r#"
+            SELECT child_id AS id
+            FROM task_links
+            JOIN tasks ON tasks.id = task_links.child_id
+            WHERE parent_id = ?
+              AND tasks.deleted_at IS NULL
+              AND task_links.set_aside_at IS NULL
+            "#,
+            index,
+        )
+    }
+
+    fn parents(&self, index: NodeIndex) -> anyhow::Result<Vec<NodeIndex>> {
+        self.linked_ids(
+            r#"
+            SELECT parent_id AS id
+            FROM task_links
+            JOIN tasks ON tasks.id = task_links.parent_id
+            WHERE child_id = ?
+              AND tasks.deleted_at IS NULL
+              AND task_links.set_aside_at IS NULL
+            "#,
+            index,
+        )
+    }
+
+    fn ancestors(&self, index: NodeIndex) -> anyhow::Result<Vec<NodeIndex>> {
+        self.linked_ids(
+            r#"
+            WITH RECURSIVE ancestors(id) AS (
+                SELECT ?1
+                UNION
+                SELECT task_links.parent_id
+                FROM task_links
+                JOIN ancestors ON ancestors.id = task_links.child_id
+                JOIN tasks ON tasks.id = task_links.parent_id
+                WHERE tasks.deleted_at IS NULL
+                  AND task_links.set_aside_at IS NULL
+            )
+            SELECT id FROM ancestors WHERE id != ?1
+            "#,
+            index,
+        )
+    }
+
+    fn descendants(&self, index: NodeIndex) -> anyhow::Result<Vec<NodeIndex>> {
+        self.linked_ids(
+            r#"
+            WITH RECURSIVE descendants(id) AS (
+                SELECT ?1
+                UNION
+                SELECT task_links.child_id
+                FROM task_links
+                JOIN descendants ON descendants.id = task_links.parent_id
+                JOIN tasks ON tasks.id = task_links.child_id
+                WHERE tasks.deleted_at IS NULL
+                  AND task_links.set_aside_at IS NULL
+            )
+            SELECT id FROM descendants WHERE id != ?1
+            "#,
+            index,
+        )
+    }
+
+    fn node_indices(&self) -> anyhow::Result<Vec<NodeIndex>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT id FROM tasks WHERE deleted_at IS NULL ORDER BY id")?;
+        let mut rows = stmt.query(())?;
+        let mut indices = vec![];
+        while let Some(row) = rows.next()? {
+            indices.push(row.get("id")?);
+        }
+        Ok(indices)
+    }
+
+    fn remove_node(&mut self, index: NodeIndex) -> anyhow::Result<()> {
+        self.snapshot.invalidate();
+        // Nodes are only marked as deleted, and their edges are kept around,
+        // so that they can be brought back with `restore_node`.
+        let removed = self.conn.execute(
+            r#"
+            UPDATE tasks
+            SET deleted_at = ?
+            WHERE id = ?
+              AND deleted_at IS NULL
+            "#,
+            (Utc::now(), index),
+        )?;
+        if removed > 0 {
+            self.subscribers.publish(GraphEvent::NodeRemoved(index));
+        }
+        Ok(())
+    }
+
+    fn set_node(&mut self, index: NodeIndex, node: Node) -> anyhow::Result<()> {
+        self.snapshot.invalidate();
+        // This is an UPDATE rather than an INSERT OR REPLACE
+        // so that columns which aren't part of `Node` (like `deleted_at`) are left alone.
+        let updated = self.conn.execute(
+            r#"
+            UPDATE tasks
+            SET title = ?,
+                x = ?,
+                y = ?,
+                radius = ?,
+                completed_at = ?,
+                description = ?,
+                metadata = ?
+            WHERE id = ?
+            "#,
+            (
+                node.title,
+                node.circle.center.x,
+                node.circle.center.y,
+                node.circle.radius,
+                node.completed_at,
+                node.description,
+                metadata_to_json(&node.metadata).to_string(),
+                index,
+            ),
+        )?;
+        if updated == 0 {
+            anyhow::bail!("Node {} does not exist", index);
+        }
+        self.subscribers.publish(GraphEvent::NodeUpdated(index));
+        Ok(())
+    }
+
+    fn edge_added_at(
+        &self,
+        from: NodeIndex,
+        to: NodeIndex,
+    ) -> anyhow::Result<Option<DateTime<Utc>>> {
+        let added_at = self.conn.query_row(
+            r#"
+            SELECT created_at
+            FROM task_links
+            WHERE parent_id = ?
+              AND child_id = ?
+              AND set_aside_at IS NULL
+            "#,
+            (from, to),
+            |row| row.get(0),
+        );
+        match added_at {
+            Ok(added_at) => Ok(added_at),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn set_aside_edge(&mut self, from: NodeIndex, to: NodeIndex) -> anyhow::Result<()> {
+        self.snapshot.invalidate();
+        let updated = self.conn.execute(
+            r#"
+            UPDATE task_links
+            SET set_aside_at = ?
+            WHERE parent_id = ?
+              AND child_id = ?
+              AND set_aside_at IS NULL
+            "#,
+            (Utc::now(), from, to),
+        )?;
+        if updated == 0 {
+            anyhow::bail!("Edge from {} to {} does not exist", from, to);
+        }
+        self.subscribers
+            .publish(GraphEvent::EdgeRemoved { from, to });
+        Ok(())
+    }
+
+    fn set_aside_edges(&self) -> anyhow::Result<Vec<(NodeIndex, NodeIndex)>> {
+        let mut stmt = self.conn.prepare(
+            r#"
+            SELECT parent_id, child_id
+            FROM task_links
+            JOIN tasks AS parents ON parents.id = task_links.parent_id
+            JOIN tasks AS children ON children.id = task_links.child_id
+            WHERE task_links.set_aside_at IS NOT NULL
+              AND parents.deleted_at IS NULL
+              AND children.deleted_at IS NULL
+            ORDER BY task_links.set_aside_at
+            "#,
+        )?;
+        let mut rows = stmt.query(())?;
+        let mut edges = vec![];
+        while let Some(row) = rows.next()? {
+            edges.push((row.get("parent_id")?, row.get("child_id")?));
+        }
+        Ok(edges)
+    }
+
+    fn snapshot(&self) -> anyhow::Result<Arc<GraphSnapshot>> {
+        self.snapshot.get_or_load(|| {
+            let mut stmt = self.conn.prepare(
+                r#"
+                SELECT id, title, x, y, radius, completed_at, description, metadata
+                FROM tasks
+                WHERE deleted_at IS NULL
+                "#,
+            )?;
+            let mut rows = stmt.query(())?;
+            let mut nodes = BTreeMap::new();
+            while let Some(row) = rows.next()? {
+                nodes.insert(row.get("id")?, node_from_row(row)?);
+            }
+
+            let mut stmt = self.conn.prepare(
+                r#"
+                SELECT parent_id, child_id
+                FROM task_links
+                JOIN tasks AS parents ON parents.id = task_links.parent_id
+                JOIN tasks AS children ON children.id = task_links.child_id
+                WHERE task_links.set_aside_at IS NULL
+                  AND parents.deleted_at IS NULL
+                  AND children.deleted_at IS NULL
+                "#,
+            )?;
+            let mut rows = stmt.query(())?;
+            let mut edges = vec![];
+            while let Some(row) = rows.next()? {
+                edges.push((row.get("parent_id")?, row.get("child_id")?));
+            }
+
+            Ok(GraphSnapshot::new(nodes, edges, self.set_aside_edges()?))
+        })
+    }
+
+    fn begin_transaction(&mut self) -> anyhow::Result<()> {
+        // IMMEDIATE takes the write lock up front,
+        // so the transaction can't fail halfway through because another connection wrote first.
+        self.conn.execute_batch("BEGIN IMMEDIATE")?;
+        self.subscribers.begin_transaction();
+        Ok(())
+    }
+
+    fn commit_transaction(&mut self) -> anyhow::Result<()> {
+        self.conn.execute_batch("COMMIT")?;
+        self.subscribers.commit_transaction();
+        Ok(())
+    }
+
+    fn rollback_transaction(&mut self) -> anyhow::Result<()> {
+        self.snapshot.invalidate();
+        self.conn.execute_batch("ROLLBACK")?;
+        self.subscribers.rollback_transaction();
         Ok(())
     }
+
+    fn subscribe(&mut self, subscriber: Subscriber) -> SubscriptionId {
+        self.subscribers.subscribe(subscriber)
+    }
+
+    fn unsubscribe(&mut self, id: SubscriptionId) {
+        self.subscribers.unsubscribe(id);
+    }
+
+    /// Changes we made ourselves since the last refresh may be published a second time
+    /// if another connection wrote to the database in the meantime.
+    fn refresh(&mut self) -> anyhow::Result<bool> {
+        // Nobody else can write while our own transaction is open.
+        if !self.conn.is_autocommit() {
+            return Ok(false);
+        }
+        let data_version = self.data_version()?;
+        if data_version == self.data_version {
+            self.seen = self.snapshot()?;
+            return Ok(false);
+        }
+        self.data_version = data_version;
+        self.snapshot.invalidate();
+        let after = self.snapshot()?;
+        let before = std::mem::replace(&mut self.seen, after.clone());
+        let events = after.changes_since(&before);
+        for &event in &events {
+            self.subscribers.publish(event);
+        }
+        Ok(!events.is_empty())
+    }
+}
+
+fn node_from_row(row: &Row) -> rusqlite::Result<Node> {
+    Ok(Node {
+        title: row.get("title")?,
+        circle: Circle::new(Point::new(row.get("x")?, row.get("y")?), row.get("radius")?),
+        completed_at: row.get("completed_at")?,
+        description: row
+            .get::<_, Option<String>>("description")?
+            .unwrap_or_default(),
+        metadata: match row.get::<_, Option<String>>("metadata")? {
+            Some(metadata) => serde_json::from_str(&metadata)
+                .map_err(anyhow::Error::from)
+                .and_then(|json| metadata_from_json(&json))
+                .map_err(|e| {
+                    rusqlite::Error::FromSqlConversionFailure(
+                        row.as_ref().column_index("metadata").unwrap_or_default(),
+                        rusqlite::types::Type::Text,
+                        e.into(),
+                    )
+                })?,
+            None => BTreeMap::new(),
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+
+    fn test_node(title: &str) -> Node {
+        Node {
+            title: title.to_owned(),
+            circle: Circle::new(Point::new(1.0, 2.0), 40.0),
+            ..Default::default()
+        }
+    }
+
+    fn check_completion(graph: &mut impl Graph) {
+        let index = graph.add_node(test_node("a")).unwrap();
+        assert!(!graph.get_node(index).unwrap().is_completed());
+
+        graph.mark_completed(index).unwrap();
+        let completed_at = graph.get_node(index).unwrap().completed_at;
+        assert!(completed_at.is_some());
+
+        graph.mark_completed(index).unwrap();
+        assert_eq!(graph.get_node(index).unwrap().completed_at, completed_at);
+
+        graph.toggle_completed(index).unwrap();
+        assert!(!graph.get_node(index).unwrap().is_completed());
+        graph.toggle_completed(index).unwrap();
+        assert!(graph.get_node(index).unwrap().is_completed());
+
+        graph.mark_incomplete(index).unwrap();
+        assert!(!graph.get_node(index).unwrap().is_completed());
+    }
+
+    #[test]
+    fn test_petgraph_completion() {
+        check_completion(&mut PetgraphGraph::default());
+    }
+
+    #[test]
+    fn test_database_completion() {
+        check_completion(&mut DatabaseGraph::open_in_memory().unwrap());
+    }
+
+    #[test]
+    fn test_database_add_node_roundtrip() {
+        let mut graph = DatabaseGraph::open_in_memory().unwrap();
+        let node = Node {
+            completed_at: Some(Utc::now()),
+            ..test_node("hello")
+        };
+        let index = graph.add_node(node.clone()).unwrap();
+        assert_eq!(graph.get_node(index).unwrap(), node);
+    }
+
+    #[test]
+    fn test_database_remove_and_restore_node() {
+        let mut graph = DatabaseGraph::open_in_memory().unwrap();
+        let a = graph.add_node(test_node("a")).unwrap();
+        let b = graph.add_node(test_node("b")).unwrap();
+        let c = graph.add_node(test_node("c")).unwrap();
+        graph.add_edge(a, b).unwrap();
+        graph.add_edge(b, c).unwrap();
+
+        graph.remove_node(b).unwrap();
+        assert_eq!(graph.node_indices().unwrap(), vec![a, c]);
+        assert_eq!(graph.neighbors(a).unwrap(), Vec::<NodeIndex>::new());
+
+        let deleted_nodes = graph.deleted_nodes().unwrap();
+        assert_eq!(deleted_nodes.len(), 1);
+        assert_eq!(deleted_nodes[0].index, b);
+        assert_eq!(deleted_nodes[0].node.title, "b");
+
+        graph.restore_node(b).unwrap();
+        assert_eq!(graph.node_indices().unwrap(), vec![a, b, c]);
+        assert_eq!(graph.neighbors(a).unwrap(), vec![b]);
+        assert_eq!(graph.neighbors(b).unwrap(), vec![c]);
+        assert!(graph.deleted_nodes().unwrap().is_empty());
+        assert!(graph.restore_node(b).is_err());
+    }
+
+    #[test]
+    fn test_database_restore_node_rejects_cycle() {
+        let mut graph = DatabaseGraph::open_in_memory().unwrap();
+        let a = graph.add_node(test_node("a")).unwrap();
+        let b = graph.add_node(test_node("b")).unwrap();
+        let c = graph.add_node(test_node("c")).unwrap();
+        graph.add_edge(a, b).unwrap();
+        graph.add_edge(b, c).unwrap();
+
+        graph.remove_node(b).unwrap();
+        graph.add_edge(c, a).unwrap();
+        assert!(graph.restore_node(b).is_err());
+        assert!(graph
+            .deleted_nodes()
+            .unwrap()
+            .iter()
+            .any(|deleted| deleted.index == b));
+    }
+
+    #[test]
+    fn test_database_insert_node_drops_old_edges() {
+        let mut graph = DatabaseGraph::open_in_memory().unwrap();
+        let a = graph.add_node(test_node("a")).unwrap();
+        let b = graph.add_node(test_node("b")).unwrap();
+        let c = graph.add_node(test_node("c")).unwrap();
+        graph.add_edge(a, b).unwrap();
+        graph.add_edge(b, c).unwrap();
+
+        // Bringing b back with its edges would make a cycle a -> b -> c -> a.
+        graph.remove_node(b).unwrap();
+        graph.add_edge(c, a).unwrap();
+        graph.insert_node(b, test_node("b")).unwrap();
+        assert_eq!(graph.neighbors(b).unwrap(), Vec::<NodeIndex>::new());
+        assert_eq!(graph.parents(b).unwrap(), Vec::<NodeIndex>::new());
+        assert_eq!(graph.neighbors(a).unwrap(), Vec::<NodeIndex>::new());
+        assert!(graph.find_cycles().unwrap().is_empty());
+        assert!(graph.insert_node(b, test_node("b")).is_err());
+    }
+
+    #[test]
+    fn test_database_purge_deleted() {
+        let mut graph = DatabaseGraph::open_in_memory().unwrap();
+        let a = graph.add_node(test_node("a")).unwrap();
+        let b = graph.add_node(test_node("b")).unwrap();
+        graph.add_edge(a, b).unwrap();
+        graph.remove_node(b).unwrap();
+
+        assert_eq!(graph.purge_deleted(Duration::days(30)).unwrap(), 0);
+        assert_eq!(graph.deleted_nodes().unwrap().len(), 1);
+
+        assert_eq!(graph.purge_deleted(Duration::zero()).unwrap(), 1);
+        assert!(graph.deleted_nodes().unwrap().is_empty());
+        assert!(graph.restore_node(b).is_err());
+    }
+
+    #[test]
+    fn test_database_set_node_keeps_description() {
+        let mut graph = DatabaseGraph::open_in_memory().unwrap();
+        let index = graph
+            .add_node(Node {
+                description: "first line\nsecond line".to_owned(),
+                ..test_node("a")
+            })
+            .unwrap();
+
+        let mut node = graph.get_node(index).unwrap();
+        node.title = "renamed".to_owned();
+        node.circle.center = Point::new(10.0, 20.0);
+        graph.set_node(index, node).unwrap();
+
+        let node = graph.get_node(index).unwrap();
+        assert_eq!(node.title, "renamed");
+        assert_eq!(node.description, "first line\nsecond line");
+    }
+
+    fn check_metadata(graph: &mut impl Graph) {
+        let metadata = |entries: &[(&str, &str)]| -> BTreeMap<String, String> {
+            entries
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect()
+        };
+        let index = graph
+            .add_node(Node {
+                metadata: metadata(&[("tags", "home garden"), ("due", "2024-07-02T17:00:00Z")]),
+                ..test_node("a")
+            })
+            .unwrap();
+        assert_eq!(
+            graph.get_node(index).unwrap().metadata,
+            metadata(&[("tags", "home garden"), ("due", "2024-07-02T17:00:00Z")])
+        );
+
+        let mut node = graph.get_node(index).unwrap();
+        node.metadata.remove("due");
+        node.metadata
+            .insert("scheduled".to_owned(), "\"quoted\"".to_owned());
+        graph.set_node(index, node).unwrap();
+        let expected = metadata(&[("tags", "home garden"), ("scheduled", "\"quoted\"")]);
+        assert_eq!(graph.get_node(index).unwrap().metadata, expected);
+        assert_eq!(
+            graph.snapshot().unwrap().node(index).unwrap().metadata,
+            expected
+        );
+    }
+
+    #[test]
+    fn test_petgraph_metadata() {
+        check_metadata(&mut PetgraphGraph::default());
+    }
+
+    #[test]
+    fn test_database_metadata() {
+        check_metadata(&mut DatabaseGraph::open_in_memory().unwrap());
+    }
+
+    #[test]
+    fn test_database_set_node_keeps_deleted_at() {
+        let mut graph = DatabaseGraph::open_in_memory().unwrap();
+        let index = graph.add_node(test_node("a")).unwrap();
+        graph.remove_node(index).unwrap();
+        graph.set_node(index, test_node("b")).unwrap();
+        assert!(graph.node_indices().unwrap().is_empty());
+        assert!(graph.set_node(Uuid::now_v7(), test_node("c")).is_err());
+    }
+
+    fn check_remove_edge(graph: &mut impl Graph) {
+        let a = graph.add_node(test_node("a")).unwrap();
+        let b = graph.add_node(test_node("b")).unwrap();
+        let c = graph.add_node(test_node("c")).unwrap();
+        graph.add_edge(a, b).unwrap();
+        graph.add_edge(a, c).unwrap();
+        assert!(graph.would_create_cycle(b, a).unwrap());
+
+        graph.remove_edge(a, b).unwrap();
+        assert_eq!(graph.neighbors(a).unwrap(), vec![c]);
+        assert!(!graph.would_create_cycle(b, a).unwrap());
+
+        // Removing an edge which doesn't exist is a no-op.
+        graph.remove_edge(a, b).unwrap();
+        graph.remove_edge(c, a).unwrap();
+        assert_eq!(graph.neighbors(a).unwrap(), vec![c]);
+    }
+
+    #[test]
+    fn test_petgraph_remove_edge() {
+        check_remove_edge(&mut PetgraphGraph::default());
+    }
+
+    #[test]
+    fn test_database_remove_edge() {
+        check_remove_edge(&mut DatabaseGraph::open_in_memory().unwrap());
+    }
+
+    fn check_ancestry(graph: &mut impl Graph) {
+        // a -> b -> d
+        //   \-> c -/
+        let a = graph.add_node(test_node("a")).unwrap();
+        let b = graph.add_node(test_node("b")).unwrap();
+        let c = graph.add_node(test_node("c")).unwrap();
+        let d = graph.add_node(test_node("d")).unwrap();
+        graph.add_edge(a, b).unwrap();
+        graph.add_edge(a, c).unwrap();
+        graph.add_edge(b, d).unwrap();
+        graph.add_edge(c, d).unwrap();
+
+        let sorted = |mut indices: Vec<NodeIndex>| {
+            indices.sort();
+            indices
+        };
+        assert_eq!(sorted(graph.parents(d).unwrap()), vec![b, c]);
+        assert_eq!(graph.parents(a).unwrap(), Vec::<NodeIndex>::new());
+        assert_eq!(sorted(graph.ancestors(d).unwrap()), vec![a, b, c]);
+        assert_eq!(sorted(graph.ancestors(b).unwrap()), vec![a]);
+        assert_eq!(sorted(graph.descendants(a).unwrap()), vec![b, c, d]);
+        assert_eq!(graph.descendants(d).unwrap(), Vec::<NodeIndex>::new());
+    }
+
+    #[test]
+    fn test_petgraph_ancestry() {
+        check_ancestry(&mut PetgraphGraph::default());
+    }
+
+    #[test]
+    fn test_database_ancestry() {
+        check_ancestry(&mut DatabaseGraph::open_in_memory().unwrap());
+    }
+
+    #[test]
+    fn test_database_ancestry_skips_deleted_nodes() {
+        let mut graph = DatabaseGraph::open_in_memory().unwrap();
+        let a = graph.add_node(test_node("a")).unwrap();
+        let b = graph.add_node(test_node("b")).unwrap();
+        let c = graph.add_node(test_node("c")).unwrap();
+        graph.add_edge(a, b).unwrap();
+        graph.add_edge(b, c).unwrap();
+        graph.remove_node(b).unwrap();
+
+        assert_eq!(graph.parents(c).unwrap(), Vec::<NodeIndex>::new());
+        assert_eq!(graph.ancestors(c).unwrap(), Vec::<NodeIndex>::new());
+        assert_eq!(graph.descendants(a).unwrap(), Vec::<NodeIndex>::new());
+    }
+
+    /// Dumps of databases at each `user_version`, from before versioning up to the latest,
+    /// frozen as they were so that changes to old migrations show up here.
+    /// Each new migration needs a new dump.
+    const FIXTURES: &[&str] = &[
+        include_str!("../tests/fixtures/schema-v0.sql"),
+        include_str!("../tests/fixtures/schema-v1.sql"),
+        include_str!("../tests/fixtures/schema-v2.sql"),
+        include_str!("../tests/fixtures/schema-v3.sql"),
+        include_str!("../tests/fixtures/schema-v4.sql"),
+        include_str!("../tests/fixtures/schema-v5.sql"),
+    ];
+
+    fn open_fixture(dir: &Path, fixture: &str) -> anyhow::Result<DatabaseGraph> {
+        let path = dir.join("db.sqlite");
+        let conn = Connection::open(&path)?;
+        conn.execute_batch(fixture)?;
+        drop(conn);
+        DatabaseGraph::open(&path)
+    }
+
+    fn user_version(graph: &DatabaseGraph) -> usize {
+        graph
+            .conn
+            .pragma_query_value(None, "user_version", |row| row.get(0))
+            .unwrap()
+    }
+
+    #[test]
+    fn test_migrate_new_database() {
+        let graph = DatabaseGraph::open_in_memory().unwrap();
+        assert_eq!(user_version(&graph), MIGRATIONS.len());
+    }
+
+    #[test]
+    fn test_migrate_from_each_version() {
+        assert_eq!(FIXTURES.len(), MIGRATIONS.len() + 1);
+        for (version, fixture) in FIXTURES.iter().enumerate() {
+            let dir = tempfile::tempdir().unwrap();
+            let graph = open_fixture(dir.path(), fixture).unwrap();
+            assert_eq!(user_version(&graph), MIGRATIONS.len());
+
+            let indices = graph.node_indices().unwrap();
+            assert_eq!(indices.len(), 2, "version {}", version);
+            let a = graph.get_node(indices[0]).unwrap();
+            assert_eq!(a.title, "a");
+            assert_eq!(a.description, "First task");
+            assert_eq!(
+                a.completed_at,
+                Some("2024-07-02T17:00:00Z".parse().unwrap())
+            );
+            assert_eq!(a.circle, Circle::new((1.0, 2.0), 40.0));
+            assert_eq!(graph.get_node(indices[1]).unwrap().title, "b");
+            assert_eq!(graph.neighbors(indices[0]).unwrap(), vec![indices[1]]);
+            assert_eq!(graph.deleted_nodes().unwrap().len(), 1);
+            if version >= 5 {
+                assert_eq!(a.metadata["due"], "2024-07-05T00:00:00+00:00");
+            }
+        }
+    }
+
+    #[test]
+    fn test_migrate_rejects_newer_database() {
+        let dir = tempfile::tempdir().unwrap();
+        let fixture = format!(
+            "{}PRAGMA user_version = {};",
+            FIXTURES[0],
+            MIGRATIONS.len() + 1
+        );
+        let result = open_fixture(dir.path(), &fixture);
+        let error = format!("{:#}", result.err().unwrap());
+        assert!(error.contains("only supports up to"), "{}", error);
+    }
+
+    fn check_remove_node_keeps_indices(graph: &mut impl Graph) {
+        let a = graph.add_node(test_node("a")).unwrap();
+        let b = graph.add_node(test_node("b")).unwrap();
+        let c = graph.add_node(test_node("c")).unwrap();
+        graph.add_edge(b, c).unwrap();
+
+        graph.remove_node(a).unwrap();
+        assert_eq!(graph.node_indices().unwrap(), vec![b, c]);
+        assert_eq!(graph.get_node(c).unwrap().title, "c");
+        assert_eq!(graph.neighbors(b).unwrap(), vec![c]);
+    }
+
+    #[test]
+    fn test_petgraph_remove_node_keeps_indices() {
+        check_remove_node_keeps_indices(&mut PetgraphGraph::default());
+        assert!(PetgraphGraph::default().get_node(Uuid::now_v7()).is_err());
+    }
+
+    #[test]
+    fn test_database_remove_node_keeps_indices() {
+        check_remove_node_keeps_indices(&mut DatabaseGraph::open_in_memory().unwrap());
+    }
+
+    fn check_set_aside_edge(graph: &mut impl Graph) {
+        let a = graph.add_node(test_node("a")).unwrap();
+        let b = graph.add_node(test_node("b")).unwrap();
+        graph.add_edge(a, b).unwrap();
+        assert!(graph.edge_added_at(a, b).unwrap().is_some());
+
+        graph.set_aside_edge(a, b).unwrap();
+        assert!(graph.set_aside_edge(a, b).is_err());
+        assert_eq!(graph.neighbors(a).unwrap(), Vec::<NodeIndex>::new());
+        assert_eq!(graph.parents(b).unwrap(), Vec::<NodeIndex>::new());
+        assert_eq!(graph.set_aside_edges().unwrap(), vec![(a, b)]);
+        assert!(!graph.would_create_cycle(b, a).unwrap());
+
+        graph.add_edge(a, b).unwrap();
+        assert_eq!(graph.neighbors(a).unwrap(), vec![b]);
+        assert_eq!(graph.set_aside_edges().unwrap(), vec![]);
+    }
+
+    #[test]
+    fn test_petgraph_set_aside_edge() {
+        check_set_aside_edge(&mut PetgraphGraph::default());
+    }
+
+    #[test]
+    fn test_database_set_aside_edge() {
+        check_set_aside_edge(&mut DatabaseGraph::open_in_memory().unwrap());
+    }
+
+    /// Adds an edge without checking for cycles, like a hand-edited database might have.
+    fn insert_edge(graph: &DatabaseGraph, from: NodeIndex, to: NodeIndex) {
+        graph
+            .conn
+            .execute(
+                "INSERT INTO task_links (parent_id, child_id, created_at) VALUES (?, ?, ?)",
+                (from, to, Utc::now()),
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn test_database_find_and_repair_cycles() {
+        let mut graph = DatabaseGraph::open_in_memory().unwrap();
+        let a = graph.add_node(test_node("a")).unwrap();
+        let b = graph.add_node(test_node("b")).unwrap();
+        let c = graph.add_node(test_node("c")).unwrap();
+        let d = graph.add_node(test_node("d")).unwrap();
+        graph.add_edge(a, b).unwrap();
+        graph.add_edge(b, c).unwrap();
+        insert_edge(&graph, c, a);
+        insert_edge(&graph, d, d);
+        assert_eq!(graph.find_cycles().unwrap(), vec![vec![a, b, c], vec![d]]);
+
+        let mut set_aside = graph.repair_cycles().unwrap();
+        set_aside.sort();
+        assert_eq!(set_aside, vec![(c, a), (d, d)]);
+        assert_eq!(graph.find_cycles().unwrap(), Vec::<Vec<NodeIndex>>::new());
+        assert_eq!(graph.neighbors(a).unwrap(), vec![b]);
+        assert_eq!(graph.neighbors(c).unwrap(), Vec::<NodeIndex>::new());
+
+        let mut edges = graph.set_aside_edges().unwrap();
+        edges.sort();
+        assert_eq!(edges, vec![(c, a), (d, d)]);
+        graph.remove_node(d).unwrap();
+        assert_eq!(graph.set_aside_edges().unwrap(), vec![(c, a)]);
+    }
+
+    #[test]
+    fn test_find_cycles_in_a_large_acyclic_graph() {
+        // Every task depends on every task in the layer below, so there are 4^29 paths from
+        // the top to the bottom, which searching each of them would never get through.
+        let mut graph = PetgraphGraph::default();
+        let mut above: Vec<NodeIndex> = vec![];
+        for _ in 0..30 {
+            let layer: Vec<NodeIndex> = (0..4)
+                .map(|_| graph.add_node(test_node("x")).unwrap())
+                .collect();
+            for from in &above {
+                for to in &layer {
+                    graph.add_edge(*from, *to).unwrap();
+                }
+            }
+            above = layer;
+        }
+        assert!(graph.find_cycles().unwrap().is_empty());
+        assert!(graph.repair_cycles().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_find_cycles_reports_overlapping_cycles() {
+        let mut graph = PetgraphGraph::default();
+        let a = graph.add_node(test_node("a")).unwrap();
+        let b = graph.add_node(test_node("b")).unwrap();
+        let c = graph.add_node(test_node("c")).unwrap();
+        for (from, to) in [(a, b), (b, a), (b, c), (c, a)] {
+            let from = graph.petgraph_index(from).unwrap();
+            let to = graph.petgraph_index(to).unwrap();
+            graph.graph.add_edge(from, to, Utc::now());
+        }
+
+        let mut cycles = graph.find_cycles().unwrap();
+        cycles.sort();
+        assert_eq!(cycles, vec![vec![a, b], vec![a, b, c]]);
+
+        // Both cycles go through a -> b, which is older than b -> a and c -> a.
+        let mut set_aside = graph.repair_cycles().unwrap();
+        set_aside.sort();
+        assert!(graph.find_cycles().unwrap().is_empty());
+        assert_eq!(set_aside.len(), 2);
+        assert!(!set_aside.contains(&(a, b)));
+    }
+
+    fn check_transactions(graph: &mut impl Graph) {
+        let a = graph.add_node(test_node("a")).unwrap();
+
+        let b = graph
+            .transaction(|graph| {
+                let b = graph.add_node(test_node("b"))?;
+                graph.add_edge(a, b)?;
+                Ok(b)
+            })
+            .unwrap();
+        assert_eq!(graph.node_indices().unwrap(), vec![a, b]);
+        assert_eq!(graph.neighbors(a).unwrap(), vec![b]);
+
+        let result = graph.transaction(|graph| {
+            let c = graph.add_node(test_node("c"))?;
+            graph.add_edge(b, c)?;
+            graph.remove_edge(a, b)?;
+            graph.mark_completed(a)?;
+            // Changes are visible inside the transaction.
+            assert_eq!(graph.neighbors(b)?, vec![c]);
+            graph.add_edge(c, b)
+        });
+        assert!(result.is_err());
+        assert_eq!(graph.node_indices().unwrap(), vec![a, b]);
+        assert_eq!(graph.neighbors(a).unwrap(), vec![b]);
+        assert_eq!(graph.neighbors(b).unwrap(), Vec::<NodeIndex>::new());
+        assert!(!graph.get_node(a).unwrap().is_completed());
+
+        graph.begin_transaction().unwrap();
+        assert!(graph.begin_transaction().is_err());
+        graph.commit_transaction().unwrap();
+        assert!(graph.commit_transaction().is_err());
+        assert!(graph.rollback_transaction().is_err());
+    }
+
+    #[test]
+    fn test_petgraph_transactions() {
+        check_transactions(&mut PetgraphGraph::default());
+    }
+
+    #[test]
+    fn test_database_transactions() {
+        check_transactions(&mut DatabaseGraph::open_in_memory().unwrap());
+    }
+
+    #[test]
+    fn test_database_transaction_is_atomic_on_disk() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("db.sqlite");
+        let mut graph = DatabaseGraph::open(&path).unwrap();
+        let a = graph.add_node(test_node("a")).unwrap();
+
+        graph.begin_transaction().unwrap();
+        let b = graph.add_node(test_node("b")).unwrap();
+        // Until the transaction is committed, nothing has been written for anyone else to see,
+        // just as if the app had crashed at this point.
+        assert_eq!(
+            DatabaseGraph::open(&path).unwrap().node_indices().unwrap(),
+            vec![a]
+        );
+        graph.add_edge(a, b).unwrap();
+        graph.commit_transaction().unwrap();
+
+        let graph = DatabaseGraph::open(&path).unwrap();
+        assert_eq!(graph.neighbors(a).unwrap(), vec![b]);
+    }
+
+    fn check_snapshot(graph: &mut impl Graph) {
+        let a = graph.add_node(test_node("a")).unwrap();
+        let b = graph.add_node(test_node("b")).unwrap();
+        let c = graph.add_node(test_node("c")).unwrap();
+        let d = graph.add_node(test_node("d")).unwrap();
+        graph.add_edge(a, b).unwrap();
+        graph.add_edge(a, c).unwrap();
+        graph.add_edge(c, d).unwrap();
+        graph.add_edge(b, d).unwrap();
+        graph.set_aside_edge(b, d).unwrap();
+        graph.remove_node(c).unwrap();
+
+        let snapshot = graph.snapshot().unwrap();
+        assert_eq!(*snapshot, GraphSnapshot::load(graph).unwrap());
+        assert_eq!(
+            snapshot.nodes().map(|(index, _)| index).collect::<Vec<_>>(),
+            vec![a, b, d]
+        );
+        assert_eq!(snapshot.node(a).unwrap().title, "a");
+        assert_eq!(snapshot.node(c), None);
+        assert_eq!(snapshot.edges(), [(a, b)]);
+        assert_eq!(snapshot.neighbors(a).collect::<Vec<_>>(), vec![b]);
+        assert_eq!(snapshot.neighbors(b).count(), 0);
+        assert_eq!(snapshot.set_aside_edges(), [(b, d)]);
+
+        // Every change shows up in the next snapshot.
+        let mut node = graph.get_node(a).unwrap();
+        node.title = "renamed".to_owned();
+        graph.set_node(a, node).unwrap();
+        assert_eq!(graph.snapshot().unwrap().node(a).unwrap().title, "renamed");
+        graph.add_edge(a, d).unwrap();
+        assert_eq!(graph.snapshot().unwrap().edges(), [(a, b), (a, d)]);
+        graph.remove_edge(a, b).unwrap();
+        assert_eq!(graph.snapshot().unwrap().edges(), [(a, d)]);
+        graph.transaction(|graph| graph.remove_node(d)).unwrap();
+        assert_eq!(graph.snapshot().unwrap().node(d), None);
+        let result: anyhow::Result<()> = graph.transaction(|graph| {
+            graph.remove_node(a)?;
+            assert_eq!(graph.snapshot()?.node(a), None);
+            anyhow::bail!("rolled back")
+        });
+        assert!(result.is_err());
+        assert!(graph.snapshot().unwrap().node(a).is_some());
+    }
+
+    #[test]
+    fn test_petgraph_snapshot() {
+        check_snapshot(&mut PetgraphGraph::default());
+    }
+
+    #[test]
+    fn test_database_snapshot() {
+        check_snapshot(&mut DatabaseGraph::open_in_memory().unwrap());
+    }
+
+    #[test]
+    fn test_database_snapshot_is_cached() {
+        let mut graph = DatabaseGraph::open_in_memory().unwrap();
+        graph.add_node(test_node("a")).unwrap();
+        let snapshot = graph.snapshot().unwrap();
+        assert!(Arc::ptr_eq(&snapshot, &graph.snapshot().unwrap()));
+        graph.add_node(test_node("b")).unwrap();
+        assert!(!Arc::ptr_eq(&snapshot, &graph.snapshot().unwrap()));
+    }
+
+    /// Subscribes to `graph`, returning the events it has published so far.
+    fn record_events(graph: &mut impl Graph) -> (SubscriptionId, Arc<Mutex<Vec<GraphEvent>>>) {
+        let events = Arc::new(Mutex::new(vec![]));
+        let id = {
+            let events = events.clone();
+            graph.subscribe(Box::new(move |event| events.lock().unwrap().push(*event)))
+        };
+        (id, events)
+    }
+
+    fn check_subscribers(graph: &mut impl Graph) {
+        let (id, events) = record_events(graph);
+        let a = graph.add_node(test_node("a")).unwrap();
+        let b = graph.add_node(test_node("b")).unwrap();
+        graph.add_edge(a, b).unwrap();
+        // Adding an edge which already exists changes nothing.
+        graph.add_edge(a, b).unwrap();
+        graph.mark_completed(b).unwrap();
+        graph.remove_edge(a, b).unwrap();
+        graph.remove_edge(a, b).unwrap();
+        graph.remove_node(b).unwrap();
+        assert_eq!(
+            events.lock().unwrap().drain(..).collect::<Vec<_>>(),
+            vec![
+                GraphEvent::NodeAdded(a),
+                GraphEvent::NodeAdded(b),
+                GraphEvent::EdgeAdded { from: a, to: b },
+                GraphEvent::NodeUpdated(b),
+                GraphEvent::EdgeRemoved { from: a, to: b },
+                GraphEvent::NodeRemoved(b),
+            ]
+        );
+
+        // Events are held back until a transaction commits, and dropped if it's rolled back.
+        let c = graph
+            .transaction(|graph| {
+                let c = graph.add_node(test_node("c"))?;
+                assert!(events.lock().unwrap().is_empty());
+                Ok(c)
+            })
+            .unwrap();
+        assert_eq!(*events.lock().unwrap(), vec![GraphEvent::NodeAdded(c)]);
+        let result: anyhow::Result<()> = graph.transaction(|graph| {
+            graph.add_edge(a, c)?;
+            anyhow::bail!("rolled back")
+        });
+        assert!(result.is_err());
+        assert_eq!(*events.lock().unwrap(), vec![GraphEvent::NodeAdded(c)]);
+
+        graph.unsubscribe(id);
+        graph.add_node(test_node("d")).unwrap();
+        assert_eq!(events.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_petgraph_subscribers() {
+        check_subscribers(&mut PetgraphGraph::default());
+    }
+
+    #[test]
+    fn test_database_subscribers() {
+        check_subscribers(&mut DatabaseGraph::open_in_memory().unwrap());
+    }
+
+    #[test]
+    fn test_snapshot_changes_since() {
+        let mut graph = PetgraphGraph::default();
+        let a = graph.add_node(test_node("a")).unwrap();
+        let b = graph.add_node(test_node("b")).unwrap();
+        let c = graph.add_node(test_node("c")).unwrap();
+        graph.add_edge(a, b).unwrap();
+        let before = graph.snapshot().unwrap();
+        assert!(graph.snapshot().unwrap().changes_since(&before).is_empty());
+
+        let d = graph.add_node(test_node("d")).unwrap();
+        graph.mark_completed(a).unwrap();
+        graph.remove_node(c).unwrap();
+        graph.remove_edge(a, b).unwrap();
+        graph.add_edge(b, d).unwrap();
+        assert_eq!(
+            graph.snapshot().unwrap().changes_since(&before),
+            vec![
+                GraphEvent::NodeUpdated(a),
+                GraphEvent::NodeAdded(d),
+                GraphEvent::NodeRemoved(c),
+                GraphEvent::EdgeAdded { from: b, to: d },
+                GraphEvent::EdgeRemoved { from: a, to: b },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_database_refresh_picks_up_other_connections_writes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("db.sqlite");
+        let mut graph = DatabaseGraph::open(&path).unwrap();
+        let mut other = DatabaseGraph::open(&path).unwrap();
+        let (_, events) = record_events(&mut graph);
+        let a = graph.add_node(test_node("a")).unwrap();
+        events.lock().unwrap().clear();
+        // Our own writes aren't news.
+        assert!(!graph.refresh().unwrap());
+
+        other.mark_completed(a).unwrap();
+        let b = other.add_node(test_node("b")).unwrap();
+        other.add_edge(a, b).unwrap();
+        // The cached snapshot is stale until the graph is refreshed.
+        assert_eq!(graph.snapshot().unwrap().edges(), []);
+        assert!(graph.refresh().unwrap());
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec![
+                GraphEvent::NodeUpdated(a),
+                GraphEvent::NodeAdded(b),
+                GraphEvent::EdgeAdded { from: a, to: b },
+            ]
+        );
+        assert_eq!(graph.snapshot().unwrap().edges(), [(a, b)]);
+        assert!(!graph.refresh().unwrap());
+    }
+
+    #[test]
+    fn test_database_waits_for_other_connections_to_finish_writing() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("db.sqlite");
+        let mut graph = DatabaseGraph::open(&path).unwrap();
+        let a = graph.add_node(test_node("a")).unwrap();
+
+        graph.begin_transaction().unwrap();
+        graph.mark_completed(a).unwrap();
+        let writer = {
+            let path = path.clone();
+            std::thread::spawn(move || {
+                let mut other = DatabaseGraph::open(path).unwrap();
+                // Readers aren't blocked by the open transaction.
+                assert!(!other.get_node(a).unwrap().is_completed());
+                other.add_node(test_node("b")).unwrap()
+            })
+        };
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        graph.commit_transaction().unwrap();
+
+        let b = writer.join().unwrap();
+        assert_eq!(graph.node_indices().unwrap(), vec![a, b]);
+    }
 }
@@ -1,3 +1,5 @@
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
 
 use enum_map::{Enum, EnumMap};
@@ -6,50 +8,64 @@ use masonry::accesskit::Role;
 use masonry::core::{
     keyboard::{Code, Key, KeyState, NamedKey},
     AccessCtx, AccessEvent, BoxConstraints, CursorIcon, EventCtx, KeyboardEvent, LayoutCtx,
-    NoAction, PaintCtx, PointerEvent, PropertiesMut, PropertiesRef, QueryCtx, RegisterCtx,
-    ScrollDelta, TextEvent, Widget, WidgetId,
+    PaintCtx, PointerEvent, PropertiesMut, PropertiesRef, QueryCtx, RegisterCtx, ScrollDelta,
+    TextEvent, Widget, WidgetId,
 };
-use masonry::kurbo::{Affine, Circle, Point, Rect, Size, Stroke, Vec2};
+use masonry::kurbo::{Affine, Circle, Line, Point, Rect, Size, Stroke, Vec2};
 use masonry::peniko::Color;
 use masonry::vello::Scene;
 use smallvec::SmallVec;
-use xilem::core::{MessageResult, Mut, View, ViewMarker};
+use xilem::core::{
+    MessageContext, MessageProxy, MessageResult, Mut, View, ViewMarker, ViewPathTracker,
+};
 use xilem::{Pod, ViewCtx};
 
+use crate::history::History;
 use crate::shapes;
 use crate::text::{TextConfig, TextConfigBuilder, TextRenderer};
 use crate::{
-    graph::{DatabaseGraph, Graph, Node, NodeIndex},
+    graph::{Graph, GraphEvent, GraphSnapshot, Node, NodeIndex, SubscriptionId},
     text::HorizontalAlignment,
 };
 
-const CIRCLE_RADIUS: f64 = 40.0;
+pub const CIRCLE_RADIUS: f64 = 40.0;
+/// The gap left between a node from `new_node_position` and the nodes above it.
+const NEW_NODE_SPACING: f64 = CIRCLE_RADIUS;
 
 const BASE_COLOR: Color = Color::from_rgba8(113, 64, 237, 255);
 const LIGHT_COLOR: Color = Color::from_rgba8(158, 133, 222, 255);
+const COMPLETED_COLOR: Color = Color::from_rgba8(86, 80, 102, 255);
+const COMPLETED_LIGHT_COLOR: Color = Color::from_rgba8(128, 122, 145, 255);
 const PREVIEW_COLOR: Color = Color::from_rgba8(113, 64, 237, 127);
+const SET_ASIDE_COLOR: Color = Color::from_rgba8(227, 140, 64, 255);
+
+const SET_ASIDE_CROSS_SIZE: f64 = 8.0;
 
 lazy_static! {
     static ref LINE_STROKE: Stroke = Stroke::new(4.0);
+    static ref SET_ASIDE_STROKE: Stroke = Stroke::new(4.0).with_dashes(0.0, [12.0, 8.0]);
 }
 
 pub struct GraphViewerWidget<G> {
     gesture: Gesture,
-    graph: Arc<Mutex<G>>,
+    graph: Arc<Mutex<History<G>>>,
     hotkey_state: EnumMap<Hotkey, bool>,
     raw_mouse_position: Option<Point>,
+    /// Only lets the user look around and select nodes, without changing the graph.
+    read_only: bool,
     text_config: TextConfig,
     text_renderer: TextRenderer,
     transform: Affine,
 }
 
 impl<G: Graph> GraphViewerWidget<G> {
-    fn new(graph: Arc<Mutex<G>>) -> Self {
+    fn new(graph: Arc<Mutex<History<G>>>, read_only: bool) -> Self {
         Self {
             gesture: Default::default(),
             graph,
             hotkey_state: Default::default(),
             raw_mouse_position: Default::default(),
+            read_only,
             text_config: TextConfigBuilder::default()
                 .set_horizontal_alignment(HorizontalAlignment::Middle)
                 .build(),
@@ -60,14 +76,10 @@ impl<G: Graph> GraphViewerWidget<G> {
 }
 
 impl<G: Graph> GraphViewerWidget<G> {
-    fn hovered_circle(&self, graph: &G) -> Option<NodeIndex> {
+    fn hovered_circle(&self, snapshot: &GraphSnapshot) -> Option<NodeIndex> {
         // TODO: replace with something like kdtree: https://crates.io/crates/kdtree
-        let Some(mouse_position) = self.mouse_position() else {
-            return None;
-        };
-
-        for node_id in graph.node_indices().unwrap() {
-            let node = graph.get_node(node_id).unwrap();
+        let mouse_position = self.mouse_position()?;
+        for (node_id, node) in snapshot.nodes() {
             if shapes::in_circle(&mouse_position, &node.circle) {
                 return Some(node_id);
             }
@@ -75,20 +87,73 @@ impl<G: Graph> GraphViewerWidget<G> {
         None
     }
 
+    fn hovered_edge(&self, snapshot: &GraphSnapshot) -> Option<(NodeIndex, NodeIndex)> {
+        let mouse_position = self.mouse_position()?;
+        for &(from, to) in snapshot.edges() {
+            let (Some(from_node), Some(to_node)) = (snapshot.node(from), snapshot.node(to)) else {
+                continue;
+            };
+            let line = arrow_line_between(&from_node.circle, &to_node.circle);
+            if shapes::near_line(&mouse_position, &line, LINE_STROKE.width * 2.0) {
+                return Some((from, to));
+            }
+        }
+        None
+    }
+
+    fn set_gesture(&mut self, ctx: &mut EventCtx<'_>, gesture: Gesture) {
+        let selected_node = gesture.selected_node();
+        if selected_node != self.gesture.selected_node() {
+            ctx.submit_action::<GraphViewerAction>(GraphViewerAction::SelectionChanged(
+                selected_node,
+            ));
+        }
+
+        // A whole drag is undone at once.
+        let was_moving = matches!(self.gesture, Gesture::MovingNode { .. });
+        let is_moving = matches!(gesture, Gesture::MovingNode { .. });
+        if !was_moving && is_moving {
+            self.graph.lock().unwrap().begin_step();
+        } else if was_moving && !is_moving {
+            self.graph.lock().unwrap().end_step();
+        }
+
+        self.gesture = gesture;
+    }
+
+    /// Undoes the last change to the graph, or redoes the last undone one.
+    fn undo(&mut self, ctx: &mut EventCtx<'_>, redo: bool) {
+        let mut graph = self.graph.lock().unwrap();
+        let result = if redo { graph.redo() } else { graph.undo() };
+        if let Err(e) = result {
+            log::error!("Failed to undo or redo: {}", e);
+        }
+        // Stop editing a node which was just undone out of existence.
+        let selected_node_exists = match self.gesture.selected_node() {
+            Some(node_id) => graph
+                .node_indices()
+                .is_ok_and(|indices| indices.contains(&node_id)),
+            None => true,
+        };
+        drop(graph);
+        if !selected_node_exists {
+            self.set_gesture(ctx, Gesture::Inactive);
+        }
+        ctx.request_paint_only();
+    }
+
     /// Returns the in-GraphViewer position of the mouse.
     /// This should return a Point such that,
     /// if it were rendered into the scene,
     /// it would appear directly below the mouse at all times.
     fn mouse_position(&self) -> Option<Point> {
-        let Some(raw_mouse_position) = self.raw_mouse_position else {
-            return None;
-        };
+        let raw_mouse_position = self.raw_mouse_position?;
         Some(self.transform.inverse() * raw_mouse_position)
     }
 }
 
 impl<G: Graph + 'static> Widget for GraphViewerWidget<G> {
-    type Action = NoAction;
+    type Action = GraphViewerAction;
 
     fn on_pointer_event(
         &mut self,
@@ -130,9 +195,17 @@ impl<G: Graph + 'static> Widget for GraphViewerWidget<G> {
             } = self.gesture
             {
                 let mut graph = self.graph.lock().unwrap();
-                let mut node = graph.get_node(node_id).unwrap();
-                node.circle.center = (self.transform.inverse() * new_position) + initial_distance;
-                graph.set_node(node_id, node).unwrap();
+                let moved = graph.get_node(node_id).and_then(|mut node| {
+                    node.circle.center =
+                        (self.transform.inverse() * new_position) + initial_distance;
+                    graph.set_node(node_id, node)
+                });
+                drop(graph);
+                // Something else, like a sync peer, may have removed the node mid-drag.
+                if let Err(e) = moved {
+                    log::error!("Failed to move node {}: {}", node_id, e);
+                    self.set_gesture(ctx, Gesture::Inactive);
+                }
             }
 
             self.raw_mouse_position = Some(new_position);
@@ -150,54 +223,75 @@ impl<G: Graph + 'static> Widget for GraphViewerWidget<G> {
                 _ => return,
             }
 
-            let graph = self.graph.lock().unwrap();
-            self.gesture = match self.hovered_circle(&graph) {
+            let snapshot = match self.graph.lock().unwrap().snapshot() {
+                Ok(snapshot) => snapshot,
+                Err(e) => {
+                    log::error!("Failed to load the graph: {}", e);
+                    return;
+                }
+            };
+            let gesture = match self.hovered_circle(&snapshot) {
                 None if self.hotkey_state[Hotkey::Space] => Gesture::Panning,
-                None if self.hotkey_state[Hotkey::Control] => self.gesture,
+                Some(node_id) if self.read_only => Gesture::Editing { node_id },
+                None if self.read_only => Gesture::Inactive,
+                None if self.hotkey_state[Hotkey::Control] => match self.hovered_edge(&snapshot) {
+                    Some((from, to)) => Gesture::DeletingEdge { from, to },
+                    None => self.gesture,
+                },
                 None => Gesture::AddingNode,
 
                 Some(circle) if self.hotkey_state[Hotkey::Space] => {
-                    let mouse_position = self
-                        .mouse_position()
-                        .expect("Must have mouse_position() if you also have hovered_circle()");
-                    Gesture::MovingNode {
-                        node_id: circle,
-                        initial_distance: graph.get_node(circle).unwrap().circle.center
-                            - mouse_position,
+                    match (snapshot.node(circle), self.mouse_position()) {
+                        (Some(node), Some(mouse_position)) => Gesture::MovingNode {
+                            node_id: circle,
+                            initial_distance: node.circle.center - mouse_position,
+                        },
+                        _ => Gesture::Inactive,
                     }
                 }
-                Some(circle) if self.hotkey_state[Hotkey::Control] => Gesture::Deleting,
+                Some(_) if self.hotkey_state[Hotkey::Control] => Gesture::Deleting,
+                Some(_) if self.hotkey_state[Hotkey::Shift] => Gesture::TogglingCompletion,
                 Some(circle) => Gesture::AddingEdge { from: circle },
             };
+            self.set_gesture(ctx, gesture);
             ctx.request_paint_only();
         }
 
         if let PointerEvent::Up(_) = event {
             let mut graph = self.graph.lock().unwrap();
-            let hovered_circle = self.hovered_circle(&graph);
+            let snapshot = match graph.snapshot() {
+                Ok(snapshot) => snapshot,
+                Err(e) => {
+                    log::error!("Failed to load the graph: {}", e);
+                    drop(graph);
+                    self.set_gesture(ctx, Gesture::Inactive);
+                    return;
+                }
+            };
+            let hovered_circle = self.hovered_circle(&snapshot);
             let mouse_position = self.mouse_position();
 
-            self.gesture = match (self.gesture, hovered_circle) {
+            let gesture = match (self.gesture, hovered_circle) {
                 (Gesture::AddingNode, None) => {
                     if let Some(mouse_position) = mouse_position {
-                        graph
-                            .add_node(Node {
-                                title: "".to_owned(),
-                                circle: Circle::new(mouse_position, CIRCLE_RADIUS),
-                            })
-                            .unwrap();
+                        let added = graph.add_node(Node {
+                            circle: Circle::new(mouse_position, CIRCLE_RADIUS),
+                            ..Default::default()
+                        });
+                        log_failure("add a task", added);
                     }
                     Gesture::Inactive
                 }
                 (Gesture::AddingEdge { from }, None) => {
                     if let Some(mouse_position) = mouse_position {
-                        let to = graph
-                            .add_node(Node {
-                                title: "".to_owned(),
+                        let added = graph.transaction(|graph| {
+                            let to = graph.add_node(Node {
                                 circle: Circle::new(mouse_position, CIRCLE_RADIUS),
-                            })
-                            .unwrap();
-                        graph.add_edge(from, to).unwrap();
+                                ..Default::default()
+                            })?;
+                            graph.add_edge(from, to)
+                        });
+                        log_failure("add a task", added);
                     }
                     Gesture::Inactive
                 }
@@ -206,16 +300,28 @@ impl<G: Graph + 'static> Widget for GraphViewerWidget<G> {
                 }
                 (Gesture::AddingEdge { from }, Some(to)) => {
                     if !graph.would_create_cycle(from, to).unwrap_or(true) {
-                        graph.add_edge(from, to).unwrap();
+                        log_failure("add an edge", graph.add_edge(from, to));
                     }
                     Gesture::Inactive
                 }
                 (Gesture::Deleting, Some(node_id)) => {
-                    graph.remove_node(node_id).unwrap();
+                    log_failure("remove a task", graph.remove_node(node_id));
+                    Gesture::Inactive
+                }
+                (Gesture::DeletingEdge { from, to }, None)
+                    if self.hovered_edge(&snapshot) == Some((from, to)) =>
+                {
+                    log_failure("remove an edge", graph.remove_edge(from, to));
+                    Gesture::Inactive
+                }
+                (Gesture::TogglingCompletion, Some(node_id)) => {
+                    log_failure("toggle completion", graph.toggle_completed(node_id));
                     Gesture::Inactive
                 }
                 _ => Gesture::Inactive,
             };
+            drop(graph);
+            self.set_gesture(ctx, gesture);
             ctx.request_paint_only();
         }
 
@@ -259,13 +365,28 @@ impl<G: Graph + 'static> Widget for GraphViewerWidget<G> {
             return;
         };
 
-        if let Gesture::Editing { node_id } = self.gesture {
+        if key.state == KeyState::Down
+            && key.code == Code::KeyZ
+            && self.hotkey_state[Hotkey::Control]
+            && !self.read_only
+        {
+            self.undo(ctx, self.hotkey_state[Hotkey::Shift]);
+            return;
+        }
+
+        if let (Gesture::Editing { node_id }, false) = (self.gesture, self.read_only) {
             let mut graph = self.graph.lock().unwrap();
-            let mut node = graph.get_node(node_id).unwrap();
-            if let Some(new_title) = update_title(&node.title, key) {
-                node.title = new_title;
-                graph.set_node(node_id, node).unwrap();
-                ctx.request_paint_only();
+            let edited = graph.get_node(node_id).and_then(|mut node| {
+                if let Some(new_title) = update_title(&node.title, key) {
+                    node.title = new_title;
+                    graph.set_node(node_id, node)?;
+                }
+                Ok(())
+            });
+            drop(graph);
+            if let Err(e) = edited {
+                log::error!("Failed to edit node {}: {}", node_id, e);
+                self.set_gesture(ctx, Gesture::Inactive);
             }
             ctx.request_paint_only();
         }
@@ -275,7 +396,7 @@ impl<G: Graph + 'static> Widget for GraphViewerWidget<G> {
         }
 
         if key.code == Code::Escape {
-            self.gesture = Gesture::Inactive;
+            self.set_gesture(ctx, Gesture::Inactive);
             ctx.request_paint_only();
             return;
         }
@@ -332,29 +453,57 @@ impl<G: Graph + 'static> Widget for GraphViewerWidget<G> {
         );
 
         let graph = self.graph.lock().unwrap();
-        for circle_id in graph.node_indices().unwrap() {
-            let node = graph.get_node(circle_id).unwrap();
+        let snapshot = graph.snapshot().unwrap_or_else(|e| {
+            log::error!("Failed to load the graph: {}", e);
+            Default::default()
+        });
+        let hovered_circle = self.hovered_circle(&snapshot);
+        let hovered_edge = match hovered_circle {
+            None => self.hovered_edge(&snapshot),
+            Some(_) => None,
+        };
+        // Adding an edge from `from` to any of these would create a cycle.
+        let cycle_targets: HashSet<NodeIndex> = match self.gesture {
+            Gesture::AddingEdge { from } => {
+                let mut targets: HashSet<NodeIndex> = graph
+                    .ancestors(from)
+                    .unwrap_or_default()
+                    .into_iter()
+                    .collect();
+                targets.insert(from);
+                targets
+            }
+            _ => HashSet::new(),
+        };
+        drop(graph);
 
+        for &(from, to) in snapshot.set_aside_edges() {
+            if let (Some(from_node), Some(to_node)) = (snapshot.node(from), snapshot.node(to)) {
+                draw_set_aside_arrow_between(&mut scene, &from_node.circle, &to_node.circle);
+            }
+        }
+        for (circle_id, node) in snapshot.nodes() {
             let is_in_circle = match self.mouse_position() {
                 None => false,
                 Some(mouse_position) => shapes::in_circle(&mouse_position, &node.circle),
             };
 
-            let would_create_cycle = match self.gesture {
-                Gesture::AddingEdge { from } => {
-                    graph.would_create_cycle(from, circle_id).unwrap_or(true)
-                }
-                _ => false,
+            let would_create_cycle = cycle_targets.contains(&circle_id);
+
+            let (base_color, light_color) = if node.is_completed() {
+                (COMPLETED_COLOR, COMPLETED_LIGHT_COLOR)
+            } else {
+                (BASE_COLOR, LIGHT_COLOR)
             };
 
             let circle_fill_color = if is_in_circle && self.gesture == Gesture::Deleting {
-                BASE_COLOR.with_alpha(0.25)
+                base_color.with_alpha(0.25)
             } else if is_in_circle & self.hotkey_state[Hotkey::Control] {
-                BASE_COLOR.with_alpha(0.5)
+                base_color.with_alpha(0.5)
             } else if is_in_circle && !would_create_cycle {
-                LIGHT_COLOR
+                light_color
             } else {
-                BASE_COLOR
+                base_color
             };
 
             let mut is_editing = false;
@@ -364,7 +513,7 @@ impl<G: Graph + 'static> Widget for GraphViewerWidget<G> {
                     scene.fill(
                         vello::peniko::Fill::NonZero,
                         Affine::IDENTITY,
-                        LIGHT_COLOR,
+                        light_color,
                         None,
                         &Circle::new(node.circle.center, node.circle.radius),
                     );
@@ -387,9 +536,27 @@ impl<G: Graph + 'static> Widget for GraphViewerWidget<G> {
                 }
             }
 
-            for neighbor_circle_id in graph.neighbors(circle_id).unwrap() {
-                let neighbor_node = &graph.get_node(neighbor_circle_id).unwrap();
-                draw_arrow_between(&mut scene, &BASE_COLOR, &node.circle, &neighbor_node.circle);
+            for neighbor_circle_id in snapshot.neighbors(circle_id) {
+                let Some(neighbor_node) = snapshot.node(neighbor_circle_id) else {
+                    continue;
+                };
+                let is_hovered = hovered_edge == Some((circle_id, neighbor_circle_id));
+                let arrow_color =
+                    if is_hovered && matches!(self.gesture, Gesture::DeletingEdge { .. }) {
+                        BASE_COLOR.with_alpha(0.25)
+                    } else if is_hovered && self.hotkey_state[Hotkey::Control] {
+                        BASE_COLOR.with_alpha(0.5)
+                    } else if is_hovered {
+                        LIGHT_COLOR
+                    } else {
+                        BASE_COLOR
+                    };
+                draw_arrow_between(
+                    &mut scene,
+                    &arrow_color,
+                    &node.circle,
+                    &neighbor_node.circle,
+                );
             }
 
             self.text_renderer.render_node_text(
@@ -402,11 +569,7 @@ impl<G: Graph + 'static> Widget for GraphViewerWidget<G> {
             );
         }
 
-        match (
-            self.mouse_position(),
-            self.gesture,
-            self.hovered_circle(&graph),
-        ) {
+        match (self.mouse_position(), self.gesture, hovered_circle) {
             (Some(mouse_position), Gesture::AddingNode, None) => {
                 scene.fill(
                     vello::peniko::Fill::NonZero,
@@ -425,20 +588,23 @@ impl<G: Graph + 'static> Widget for GraphViewerWidget<G> {
                     None,
                     &preview_circle,
                 );
-                draw_arrow_between(
-                    &mut scene,
-                    &PREVIEW_COLOR,
-                    &graph.get_node(from).unwrap().circle,
-                    &preview_circle,
-                );
+                if let Some(from_node) = snapshot.node(from) {
+                    draw_arrow_between(
+                        &mut scene,
+                        &PREVIEW_COLOR,
+                        &from_node.circle,
+                        &preview_circle,
+                    );
+                }
             }
-            (_, Gesture::AddingEdge { from }, Some(to)) => {
-                if !graph.would_create_cycle(from, to).unwrap_or(true) {
+            (_, Gesture::AddingEdge { from }, Some(to)) if !cycle_targets.contains(&to) => {
+                // Either end may have just been removed by something else.
+                if let (Some(from_node), Some(to_node)) = (snapshot.node(from), snapshot.node(to)) {
                     draw_arrow_between(
                         &mut scene,
                         &PREVIEW_COLOR,
-                        &graph.get_node(from).unwrap().circle,
-                        &graph.get_node(to).unwrap().circle,
+                        &from_node.circle,
+                        &to_node.circle,
                     );
                 }
             }
@@ -475,22 +641,95 @@ impl<G: Graph + 'static> Widget for GraphViewerWidget<G> {
     }
 }
 
+/// Logs why a change to the graph failed. Sync peers and RPC clients can change the graph at
+/// any moment, so a task being gone by the time the user lets go isn't worth crashing over.
+fn log_failure<T>(action: &str, result: anyhow::Result<T>) {
+    if let Err(e) = result {
+        log::error!("Failed to {}: {}", action, e);
+    }
+}
+
+/// Where to put a node added from outside the viewer, like from the command line:
+/// underneath every other node, so it doesn't cover any of them.
+pub fn new_node_position(snapshot: &GraphSnapshot) -> Point {
+    snapshot
+        .nodes()
+        .map(|(_, node)| node.circle)
+        .reduce(|lowest, circle| {
+            if circle.center.y + circle.radius > lowest.center.y + lowest.radius {
+                circle
+            } else {
+                lowest
+            }
+        })
+        .map_or(Point::ORIGIN, |lowest| {
+            Point::new(
+                lowest.center.x,
+                lowest.center.y + lowest.radius + NEW_NODE_SPACING + CIRCLE_RADIUS,
+            )
+        })
+}
+
 pub fn draw_arrow_between(
     scene: &mut Scene,
     color: &Color,
     from_circle: &Circle,
     to_circle: &Circle,
 ) {
+    let line = arrow_line_between(from_circle, to_circle);
+    for line in shapes::arrow(line.p0, line.p1) {
+        scene.stroke(&LINE_STROKE, Affine::IDENTITY, color, None, &line);
+    }
+}
+
+/// Draws an edge which was set aside to break a cycle:
+/// a dashed arrow with a cross through the middle of it.
+fn draw_set_aside_arrow_between(scene: &mut Scene, from_circle: &Circle, to_circle: &Circle) {
+    let line = arrow_line_between(from_circle, to_circle);
+    for line in shapes::arrow(line.p0, line.p1) {
+        scene.stroke(
+            &SET_ASIDE_STROKE,
+            Affine::IDENTITY,
+            SET_ASIDE_COLOR,
+            None,
+            &line,
+        );
+    }
+
+    let middle = line.p0.midpoint(line.p1);
+    let size = SET_ASIDE_CROSS_SIZE;
+    for cross_line in [
+        Line::new(
+            middle + Vec2::new(-size, -size),
+            middle + Vec2::new(size, size),
+        ),
+        Line::new(
+            middle + Vec2::new(-size, size),
+            middle + Vec2::new(size, -size),
+        ),
+    ] {
+        scene.stroke(
+            &LINE_STROKE,
+            Affine::IDENTITY,
+            SET_ASIDE_COLOR,
+            None,
+            &cross_line,
+        );
+    }
+}
+
+/// Returns the shaft of the arrow drawn by `draw_arrow_between`,
+/// which runs from the edge of `from_circle` to the edge of `to_circle`.
+fn arrow_line_between(from_circle: &Circle, to_circle: &Circle) -> Line {
     let direction = (to_circle.center - from_circle.center).normalize();
     let from = from_circle.center + direction * from_circle.radius + direction * LINE_STROKE.width;
     let to = to_circle.center - direction * to_circle.radius - direction * LINE_STROKE.width;
-    for line in shapes::arrow(from, to) {
-        scene.stroke(&LINE_STROKE, Affine::IDENTITY, color, None, &line);
-    }
+    Line::new(from, to)
 }
 
-#[derive(Clone, Copy, Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
 enum Gesture {
+    #[default]
     Inactive,
     AddingNode,
     AddingEdge {
@@ -502,20 +741,35 @@ enum Gesture {
         initial_distance: Vec2,
     },
     Deleting,
+    DeletingEdge {
+        from: NodeIndex,
+        to: NodeIndex,
+    },
+    TogglingCompletion,
     Editing {
         node_id: NodeIndex,
     },
 }
 
-impl Default for Gesture {
-    fn default() -> Self {
-        Gesture::Inactive
+impl Gesture {
+    /// The node whose details are being shown and edited, if any.
+    fn selected_node(&self) -> Option<NodeIndex> {
+        match self {
+            Gesture::Editing { node_id } => Some(*node_id),
+            _ => None,
+        }
     }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GraphViewerAction {
+    SelectionChanged(Option<NodeIndex>),
+}
+
 #[derive(Clone, Copy, Debug, Enum, Eq, PartialEq)]
 enum Hotkey {
     Control,
+    Shift,
     Space,
 }
 
@@ -524,6 +778,8 @@ impl Hotkey {
         match code {
             Code::ControlLeft => Some(Self::Control),
             Code::ControlRight => Some(Self::Control),
+            Code::ShiftLeft => Some(Self::Shift),
+            Code::ShiftRight => Some(Self::Shift),
             Code::Space => Some(Self::Space),
             _ => None,
         }
@@ -546,21 +802,59 @@ fn update_title(title: &str, key: &KeyboardEvent) -> Option<String> {
     }
 }
 
-pub struct GraphViewer {
-    graph: Arc<Mutex<DatabaseGraph>>,
+pub struct GraphViewer<G, F, C> {
+    graph: Arc<Mutex<History<G>>>,
+    on_select: F,
+    on_change: C,
+    read_only: bool,
 }
 
-impl ViewMarker for GraphViewer {}
-
-impl<AppState: 'static, Action: 'static> View<AppState, Action, ViewCtx> for GraphViewer {
-    type Element = Pod<GraphViewerWidget<DatabaseGraph>>;
-    type ViewState = ();
+impl<G, F, C> GraphViewer<G, F, C> {
+    /// Stops the user from changing the graph, while still letting them look around
+    /// and select nodes.
+    pub fn read_only(mut self, read_only: bool) -> Self {
+        self.read_only = read_only;
+        self
+    }
+}
 
-    fn build(&self, ctx: &mut ViewCtx, _: &mut AppState) -> (Self::Element, Self::ViewState) {
-        (
-            ctx.create_pod(GraphViewerWidget::new(self.graph.clone())),
-            (),
-        )
+/// Sent to the view by its graph subscription whenever the graph changes.
+#[derive(Debug)]
+struct GraphChanged;
+
+impl<G, F, C> ViewMarker for GraphViewer<G, F, C> {}
+
+impl<G, F, C, State, Action> View<State, Action, ViewCtx> for GraphViewer<G, F, C>
+where
+    G: Graph + Send + 'static,
+    State: 'static,
+    Action: 'static,
+    F: Fn(&mut State, Option<NodeIndex>) -> Action + Send + Sync + 'static,
+    C: Fn(&mut State) -> Action + Send + Sync + 'static,
+{
+    type Element = Pod<GraphViewerWidget<G>>;
+    /// The graph subscription, and whether a `GraphChanged` message is already on its way.
+    type ViewState = (SubscriptionId, Arc<AtomicBool>);
+
+    fn build(&self, ctx: &mut ViewCtx, _: &mut State) -> (Self::Element, Self::ViewState) {
+        let proxy = MessageProxy::new(ctx.proxy(), ctx.view_path().into());
+        let pending = Arc::new(AtomicBool::new(false));
+        let subscription = {
+            let pending = pending.clone();
+            self.graph
+                .lock()
+                .unwrap()
+                .subscribe(Box::new(move |_: &GraphEvent| {
+                    // A burst of events, like a transaction, only needs one repaint.
+                    if !pending.swap(true, Ordering::AcqRel) {
+                        let _ = proxy.message(GraphChanged);
+                    }
+                }))
+        };
+        let element = ctx.with_action_widget(|ctx| {
+            ctx.create_pod(GraphViewerWidget::new(self.graph.clone(), self.read_only))
+        });
+        (element, (subscription, pending))
     }
 
     fn rebuild(
@@ -568,25 +862,58 @@ impl<AppState: 'static, Action: 'static> View<AppState, Action, ViewCtx> for Gra
         _prev: &Self,
         _: &mut Self::ViewState,
         _: &mut ViewCtx,
-        _: Mut<'_, Self::Element>,
-        _: &mut AppState,
+        mut element: Mut<'_, Self::Element>,
+        _: &mut State,
     ) {
-        // Nothing to rebuild
+        element.widget.read_only = self.read_only;
+        // Other views may have changed the graph out from under us.
+        element.ctx.request_paint_only();
     }
 
-    fn teardown(&self, _: &mut Self::ViewState, _: &mut ViewCtx, _: Mut<'_, Self::Element>) {}
+    fn teardown(
+        &self,
+        (subscription, _): &mut Self::ViewState,
+        ctx: &mut ViewCtx,
+        element: Mut<'_, Self::Element>,
+    ) {
+        self.graph.lock().unwrap().unsubscribe(*subscription);
+        ctx.teardown_leaf(element);
+    }
 
     fn message(
         &self,
-        _: &mut Self::ViewState,
-        _: &mut xilem::core::MessageContext,
-        _: Mut<'_, Self::Element>,
-        _: &mut AppState,
+        (_, pending): &mut Self::ViewState,
+        message: &mut MessageContext,
+        mut element: Mut<'_, Self::Element>,
+        app_state: &mut State,
     ) -> MessageResult<Action> {
-        MessageResult::Stale
+        if message.take_message::<GraphChanged>().is_some() {
+            pending.store(false, Ordering::Release);
+            element.ctx.request_paint_only();
+            return MessageResult::Action((self.on_change)(app_state));
+        }
+        match message.take_message::<GraphViewerAction>() {
+            Some(action) => match *action {
+                GraphViewerAction::SelectionChanged(node_id) => {
+                    MessageResult::Action((self.on_select)(app_state, node_id))
+                }
+            },
+            None => MessageResult::Stale,
+        }
     }
 }
 
-pub fn graph_viewer(graph: Arc<Mutex<DatabaseGraph>>) -> GraphViewer {
-    GraphViewer { graph }
+/// Shows `graph` and lets the user edit it. `on_select` is called when the selected node changes,
+/// and `on_change` whenever the graph changes, whether from this view or anywhere else.
+pub fn graph_viewer<G, F, C>(
+    graph: Arc<Mutex<History<G>>>,
+    on_select: F,
+    on_change: C,
+) -> GraphViewer<G, F, C> {
+    GraphViewer {
+        graph,
+        on_select,
+        on_change,
+        read_only: false,
+    }
 }
@@ -0,0 +1,630 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+
+use crate::graph::{Graph, GraphEvent, GraphSnapshot, Node, NodeIndex, Subscriber, SubscriptionId};
+
+/// `set_node`s which change the same fields of the same node closer together than this are
+/// undone as one step, so that typing a title or dragging a node doesn't take one undo per
+/// keystroke or pixel.
+const MERGE_WINDOW: Duration = Duration::from_secs(1);
+
+/// A reversible change to a graph.
+#[derive(Clone, Debug, PartialEq)]
+enum Command {
+    AddNode {
+        index: NodeIndex,
+        node: Node,
+    },
+    RemoveNode {
+        index: NodeIndex,
+        node: Node,
+        parents: Vec<NodeIndex>,
+        children: Vec<NodeIndex>,
+    },
+    AddEdge {
+        from: NodeIndex,
+        to: NodeIndex,
+    },
+    RemoveEdge {
+        from: NodeIndex,
+        to: NodeIndex,
+    },
+    SetNode {
+        index: NodeIndex,
+        before: Node,
+        after: Node,
+    },
+}
+
+impl Command {
+    fn apply(&self, graph: &mut impl Graph) -> anyhow::Result<()> {
+        match self {
+            Command::AddNode { index, node } => graph.insert_node(*index, node.clone()),
+            Command::RemoveNode { index, .. } => graph.remove_node(*index),
+            Command::AddEdge { from, to } => graph.add_edge(*from, *to),
+            Command::RemoveEdge { from, to } => graph.remove_edge(*from, *to),
+            Command::SetNode { index, after, .. } => graph.set_node(*index, after.clone()),
+        }
+    }
+
+    fn revert(&self, graph: &mut impl Graph) -> anyhow::Result<()> {
+        match self {
+            Command::AddNode { index, .. } => graph.remove_node(*index),
+            Command::RemoveNode {
+                index,
+                node,
+                parents,
+                children,
+            } => {
+                graph.insert_node(*index, node.clone())?;
+                for parent in parents {
+                    graph.add_edge(*parent, *index)?;
+                }
+                for child in children {
+                    graph.add_edge(*index, *child)?;
+                }
+                Ok(())
+            }
+            Command::AddEdge { from, to } => graph.remove_edge(*from, *to),
+            Command::RemoveEdge { from, to } => graph.add_edge(*from, *to),
+            Command::SetNode { index, before, .. } => graph.set_node(*index, before.clone()),
+        }
+    }
+
+    /// Every node whose state reverting or reapplying the command depends on.
+    fn nodes(&self) -> Vec<NodeIndex> {
+        match self {
+            Command::AddNode { index, .. } | Command::SetNode { index, .. } => vec![*index],
+            Command::RemoveNode {
+                index,
+                parents,
+                children,
+                ..
+            } => [*index]
+                .into_iter()
+                .chain(parents.iter().copied())
+                .chain(children.iter().copied())
+                .collect(),
+            Command::AddEdge { from, to } | Command::RemoveEdge { from, to } => vec![*from, *to],
+        }
+    }
+}
+
+/// A `Graph` which records every change made through it, so that they can be undone and redone.
+///
+/// Each change is its own undo step, except that:
+/// - changes made between `begin_step` and `end_step`, or in a transaction, are undone together, and
+/// - `set_node`s changing the same fields of the same node within `MERGE_WINDOW` of each other
+///   are undone together, so finishing typing a title and then completing the task are two steps.
+///
+/// Edges set aside by `repair_cycles` aren't recorded, since undoing that would bring back a cycle.
+/// Neither are changes from elsewhere, which come in through `apply_external`.
+pub struct History<G> {
+    graph: G,
+    undo_stack: Vec<Vec<Command>>,
+    redo_stack: Vec<Vec<Command>>,
+    /// How many `begin_step`s haven't been ended yet.
+    open_steps: usize,
+    /// The node and time of the last `set_node`, while it can still be merged into.
+    last_set_node: Option<(NodeIndex, Instant)>,
+    /// The length of the undo stack, and of its last step, when the current transaction began,
+    /// so that its commands can be forgotten if it's rolled back.
+    transaction_start: Option<(usize, usize)>,
+}
+
+impl<G: Graph> History<G> {
+    pub fn new(graph: G) -> Self {
+        Self {
+            graph,
+            undo_stack: vec![],
+            redo_stack: vec![],
+            open_steps: 0,
+            last_set_node: None,
+            transaction_start: None,
+        }
+    }
+
+    /// The graph being recorded. Changes made directly to it aren't recorded.
+    pub fn inner(&self) -> &G {
+        &self.graph
+    }
+
+    pub fn inner_mut(&mut self) -> &mut G {
+        &mut self.graph
+    }
+
+    /// Groups every change until the matching `end_step` into one undo step.
+    /// Steps can be nested, in which case the outermost one wins.
+    pub fn begin_step(&mut self) {
+        if self.open_steps == 0 {
+            self.undo_stack.push(vec![]);
+            self.last_set_node = None;
+        }
+        self.open_steps += 1;
+    }
+
+    pub fn end_step(&mut self) {
+        self.open_steps = self.open_steps.saturating_sub(1);
+        if self.open_steps == 0 && self.undo_stack.last().is_some_and(Vec::is_empty) {
+            self.undo_stack.pop();
+        }
+    }
+
+    pub fn can_undo(&self) -> bool {
+        self.undo_stack.iter().any(|step| !step.is_empty())
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo_stack.is_empty()
+    }
+
+    /// Reverts the most recent step, all at once or not at all.
+    /// Returns false if there was nothing to undo.
+    pub fn undo(&mut self) -> anyhow::Result<bool> {
+        self.open_steps = 0;
+        self.last_set_node = None;
+        let Some(step) = self.undo_stack.last() else {
+            return Ok(false);
+        };
+        // Skip over a step which was begun but had nothing recorded in it yet.
+        if step.is_empty() {
+            self.undo_stack.pop();
+            return Ok(false);
+        }
+        self.graph.transaction(|graph| {
+            step.iter()
+                .rev()
+                .try_for_each(|command| command.revert(graph))
+        })?;
+        self.redo_stack.extend(self.undo_stack.pop());
+        Ok(true)
+    }
+
+    /// Applies the most recently undone step again, all at once or not at all.
+    /// Returns false if there was nothing to redo.
+    pub fn redo(&mut self) -> anyhow::Result<bool> {
+        self.open_steps = 0;
+        self.last_set_node = None;
+        let Some(step) = self.redo_stack.last() else {
+            return Ok(false);
+        };
+        self.graph
+            .transaction(|graph| step.iter().try_for_each(|command| command.apply(graph)))?;
+        self.undo_stack.extend(self.redo_stack.pop());
+        Ok(true)
+    }
+
+    /// Makes changes which come from elsewhere, like another process or a sync peer, without
+    /// recording them.
+    ///
+    /// Steps which touch a node those changes touched are forgotten, along with every step
+    /// before them on the undo stack and after them on the redo stack, so that undoing never
+    /// overwrites someone else's edits.
+    pub fn apply_external<T>(
+        &mut self,
+        f: impl FnOnce(&mut G) -> anyhow::Result<T>,
+    ) -> anyhow::Result<T> {
+        let before = self.graph.snapshot()?;
+        let result = f(&mut self.graph)?;
+        let changed: HashSet<NodeIndex> = self
+            .graph
+            .snapshot()?
+            .changes_since(&before)
+            .into_iter()
+            .flat_map(|event| match event {
+                GraphEvent::NodeAdded(index)
+                | GraphEvent::NodeUpdated(index)
+                | GraphEvent::NodeRemoved(index) => vec![index],
+                GraphEvent::EdgeAdded { from, to } | GraphEvent::EdgeRemoved { from, to } => {
+                    vec![from, to]
+                }
+            })
+            .collect();
+        if !changed.is_empty() {
+            self.forget_steps_touching(&changed);
+        }
+        Ok(result)
+    }
+
+    fn forget_steps_touching(&mut self, changed: &HashSet<NodeIndex>) {
+        let touches = |step: &Vec<Command>| {
+            step.iter()
+                .any(|command| command.nodes().iter().any(|node| changed.contains(node)))
+        };
+        // Both stacks have the step which would be undone or redone next on top.
+        for stack in [&mut self.undo_stack, &mut self.redo_stack] {
+            if let Some(last) = stack.iter().rposition(touches) {
+                stack.drain(..=last);
+            }
+        }
+        // Changes still need somewhere to go until the open step is ended.
+        if self.open_steps > 0 && self.undo_stack.is_empty() {
+            self.undo_stack.push(vec![]);
+        }
+        self.last_set_node = None;
+    }
+
+    fn record(&mut self, command: Command) {
+        self.redo_stack.clear();
+        if let Command::SetNode { index, .. } = command {
+            let now = Instant::now();
+            let merges = self.last_set_node.is_some_and(|(last_index, last_time)| {
+                last_index == index && now - last_time < MERGE_WINDOW
+            });
+            self.last_set_node = Some((index, now));
+            if merges && self.merge_set_node(&command) {
+                return;
+            }
+        } else {
+            self.last_set_node = None;
+        }
+
+        match self.undo_stack.last_mut() {
+            Some(step) if self.open_steps > 0 => step.push(command),
+            _ => self.undo_stack.push(vec![command]),
+        }
+    }
+
+    /// Folds `command` into an earlier `set_node` of the same node in the latest step,
+    /// if they changed the same fields.
+    fn merge_set_node(&mut self, command: &Command) -> bool {
+        let Command::SetNode {
+            index,
+            before,
+            after,
+        } = command
+        else {
+            return false;
+        };
+        let Some(step) = self.undo_stack.last_mut() else {
+            return false;
+        };
+        match step.last_mut() {
+            Some(Command::SetNode {
+                index: last_index,
+                before: last_before,
+                after: last_after,
+            }) if last_index == index
+                && changed_fields(last_before, last_after) == changed_fields(before, after) =>
+            {
+                *last_after = after.clone();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn is_present(&self, index: NodeIndex) -> anyhow::Result<bool> {
+        Ok(self.graph.node_indices()?.contains(&index))
+    }
+}
+
+/// Which of a node's fields differ between `before` and `after`.
+fn changed_fields(before: &Node, after: &Node) -> [bool; 5] {
+    [
+        before.title != after.title,
+        before.description != after.description,
+        before.circle != after.circle,
+        before.completed_at != after.completed_at,
+        before.metadata != after.metadata,
+    ]
+}
+
+impl<G: Graph> Graph for History<G> {
+    fn add_edge(&mut self, from: NodeIndex, to: NodeIndex) -> anyhow::Result<()> {
+        let existed = self.graph.neighbors(from)?.contains(&to);
+        self.graph.add_edge(from, to)?;
+        if !existed {
+            self.record(Command::AddEdge { from, to });
+        }
+        Ok(())
+    }
+
+    fn insert_node(&mut self, index: NodeIndex, node: Node) -> anyhow::Result<()> {
+        self.graph.insert_node(index, node.clone())?;
+        self.record(Command::AddNode { index, node });
+        Ok(())
+    }
+
+    fn remove_edge(&mut self, from: NodeIndex, to: NodeIndex) -> anyhow::Result<()> {
+        let existed = self.graph.neighbors(from)?.contains(&to);
+        self.graph.remove_edge(from, to)?;
+        if existed {
+            self.record(Command::RemoveEdge { from, to });
+        }
+        Ok(())
+    }
+
+    fn get_node(&self, index: NodeIndex) -> anyhow::Result<Node> {
+        self.graph.get_node(index)
+    }
+
+    fn neighbors(&self, index: NodeIndex) -> anyhow::Result<Vec<NodeIndex>> {
+        self.graph.neighbors(index)
+    }
+
+    fn parents(&self, index: NodeIndex) -> anyhow::Result<Vec<NodeIndex>> {
+        self.graph.parents(index)
+    }
+
+    fn node_indices(&self) -> anyhow::Result<Vec<NodeIndex>> {
+        self.graph.node_indices()
+    }
+
+    fn remove_node(&mut self, index: NodeIndex) -> anyhow::Result<()> {
+        if !self.is_present(index)? {
+            return self.graph.remove_node(index);
+        }
+        let command = Command::RemoveNode {
+            index,
+            node: self.graph.get_node(index)?,
+            parents: self.graph.parents(index)?,
+            children: self.graph.neighbors(index)?,
+        };
+        self.graph.remove_node(index)?;
+        self.record(command);
+        Ok(())
+    }
+
+    fn set_node(&mut self, index: NodeIndex, node: Node) -> anyhow::Result<()> {
+        let before = self.graph.get_node(index)?;
+        self.graph.set_node(index, node.clone())?;
+        self.record(Command::SetNode {
+            index,
+            before,
+            after: node,
+        });
+        Ok(())
+    }
+
+    fn edge_added_at(
+        &self,
+        from: NodeIndex,
+        to: NodeIndex,
+    ) -> anyhow::Result<Option<DateTime<Utc>>> {
+        self.graph.edge_added_at(from, to)
+    }
+
+    fn set_aside_edge(&mut self, from: NodeIndex, to: NodeIndex) -> anyhow::Result<()> {
+        self.graph.set_aside_edge(from, to)
+    }
+
+    fn set_aside_edges(&self) -> anyhow::Result<Vec<(NodeIndex, NodeIndex)>> {
+        self.graph.set_aside_edges()
+    }
+
+    fn begin_transaction(&mut self) -> anyhow::Result<()> {
+        self.graph.begin_transaction()?;
+        self.begin_step();
+        let step_len = self.undo_stack.last().map_or(0, Vec::len);
+        self.transaction_start = Some((self.undo_stack.len(), step_len));
+        Ok(())
+    }
+
+    fn commit_transaction(&mut self) -> anyhow::Result<()> {
+        self.graph.commit_transaction()?;
+        self.transaction_start = None;
+        self.end_step();
+        Ok(())
+    }
+
+    fn rollback_transaction(&mut self) -> anyhow::Result<()> {
+        self.graph.rollback_transaction()?;
+        if let Some((len, step_len)) = self.transaction_start.take() {
+            self.undo_stack.truncate(len);
+            if let Some(step) = self.undo_stack.last_mut() {
+                step.truncate(step_len);
+            }
+        }
+        self.last_set_node = None;
+        self.end_step();
+        Ok(())
+    }
+
+    fn snapshot(&self) -> anyhow::Result<Arc<GraphSnapshot>> {
+        self.graph.snapshot()
+    }
+
+    fn refresh(&mut self) -> anyhow::Result<bool> {
+        self.apply_external(|graph| graph.refresh())
+    }
+
+    fn subscribe(&mut self, subscriber: Subscriber) -> SubscriptionId {
+        self.graph.subscribe(subscriber)
+    }
+
+    fn unsubscribe(&mut self, id: SubscriptionId) {
+        self.graph.unsubscribe(id);
+    }
+
+    fn ancestors(&self, index: NodeIndex) -> anyhow::Result<Vec<NodeIndex>> {
+        self.graph.ancestors(index)
+    }
+
+    fn descendants(&self, index: NodeIndex) -> anyhow::Result<Vec<NodeIndex>> {
+        self.graph.descendants(index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use masonry::kurbo::{Circle, Point};
+
+    use super::*;
+    use crate::graph::{DatabaseGraph, PetgraphGraph};
+
+    fn test_node(title: &str) -> Node {
+        Node {
+            title: title.to_owned(),
+            circle: Circle::new(Point::new(1.0, 2.0), 40.0),
+            ..Default::default()
+        }
+    }
+
+    fn check_undo_redo(graph: impl Graph) {
+        let mut history = History::new(graph);
+        assert!(!history.can_undo());
+
+        let a = history.add_node(test_node("a")).unwrap();
+        let b = history.add_node(test_node("b")).unwrap();
+        history.add_edge(a, b).unwrap();
+        history.remove_node(b).unwrap();
+        assert_eq!(history.node_indices().unwrap(), vec![a]);
+
+        // Bringing back a removed node brings back its edges too.
+        assert!(history.undo().unwrap());
+        assert_eq!(history.node_indices().unwrap(), vec![a, b]);
+        assert_eq!(history.neighbors(a).unwrap(), vec![b]);
+
+        assert!(history.undo().unwrap());
+        assert_eq!(history.neighbors(a).unwrap(), Vec::<NodeIndex>::new());
+        assert!(history.undo().unwrap());
+        assert!(history.undo().unwrap());
+        assert_eq!(history.node_indices().unwrap(), Vec::<NodeIndex>::new());
+        assert!(!history.undo().unwrap());
+
+        // Redone nodes keep their indices.
+        assert!(history.redo().unwrap());
+        assert!(history.redo().unwrap());
+        assert!(history.redo().unwrap());
+        assert_eq!(history.node_indices().unwrap(), vec![a, b]);
+        assert_eq!(history.neighbors(a).unwrap(), vec![b]);
+
+        // Making a change forgets everything which could have been redone.
+        history.remove_edge(a, b).unwrap();
+        assert!(!history.can_redo());
+        assert!(!history.redo().unwrap());
+        assert!(history.undo().unwrap());
+        assert_eq!(history.neighbors(a).unwrap(), vec![b]);
+    }
+
+    #[test]
+    fn test_petgraph_undo_redo() {
+        check_undo_redo(PetgraphGraph::default());
+    }
+
+    #[test]
+    fn test_database_undo_redo() {
+        check_undo_redo(DatabaseGraph::open_in_memory().unwrap());
+    }
+
+    #[test]
+    fn test_set_node_bursts_merge() {
+        let mut history = History::new(PetgraphGraph::default());
+        let a = history.add_node(test_node("a")).unwrap();
+        for title in ["t", "ti", "tit", "title"] {
+            let mut node = history.get_node(a).unwrap();
+            node.title = title.to_owned();
+            history.set_node(a, node).unwrap();
+        }
+        history.mark_completed(a).unwrap();
+        assert!(history.get_node(a).unwrap().is_completed());
+
+        // Completing the task is a different kind of edit, so it's its own step.
+        assert!(history.undo().unwrap());
+        assert_eq!(history.get_node(a).unwrap().title, "title");
+        assert!(!history.get_node(a).unwrap().is_completed());
+        assert!(history.undo().unwrap());
+        assert_eq!(history.get_node(a).unwrap(), test_node("a"));
+        assert!(history.redo().unwrap());
+        assert!(history.redo().unwrap());
+        assert_eq!(history.get_node(a).unwrap().title, "title");
+        assert!(history.get_node(a).unwrap().is_completed());
+    }
+
+    #[test]
+    fn test_external_changes_forget_conflicting_steps() {
+        let mut history = History::new(PetgraphGraph::default());
+        let a = history.add_node(test_node("a")).unwrap();
+        let b = history.add_node(test_node("b")).unwrap();
+        let c = history.add_node(test_node("c")).unwrap();
+        history.add_edge(a, c).unwrap();
+
+        history
+            .apply_external(|graph| {
+                let mut node = graph.get_node(b)?;
+                node.title = "theirs".to_owned();
+                graph.set_node(b, node)
+            })
+            .unwrap();
+        assert_eq!(history.get_node(b).unwrap().title, "theirs");
+
+        // Later steps can still be undone, but undoing adding b would lose their edit.
+        assert!(history.undo().unwrap());
+        assert_eq!(history.neighbors(a).unwrap(), Vec::<NodeIndex>::new());
+        assert!(history.undo().unwrap());
+        assert!(!history.can_undo());
+        assert_eq!(history.node_indices().unwrap(), vec![a, b]);
+    }
+
+    #[test]
+    fn test_failed_undo_changes_nothing() {
+        let mut history = History::new(DatabaseGraph::open_in_memory().unwrap());
+        let a = history.add_node(test_node("a")).unwrap();
+        let b = history.add_node(test_node("b")).unwrap();
+        history.add_edge(a, b).unwrap();
+        history.begin_step();
+        history.remove_edge(a, b).unwrap();
+        let c = history.add_node(test_node("c")).unwrap();
+        history.end_step();
+        // Bringing back a to b would now make a cycle.
+        history.inner_mut().add_edge(b, a).unwrap();
+
+        assert!(history.undo().is_err());
+        assert_eq!(history.node_indices().unwrap(), vec![a, b, c]);
+        assert!(history.can_undo());
+        assert!(!history.can_redo());
+    }
+
+    #[test]
+    fn test_steps_group_changes() {
+        let mut history = History::new(PetgraphGraph::default());
+        let a = history.add_node(test_node("a")).unwrap();
+
+        history.begin_step();
+        let b = history.add_node(test_node("b")).unwrap();
+        history.add_edge(a, b).unwrap();
+        history.end_step();
+        // Empty steps don't count.
+        history.begin_step();
+        history.end_step();
+
+        assert!(history.undo().unwrap());
+        assert_eq!(history.node_indices().unwrap(), vec![a]);
+        assert!(history.can_undo());
+        assert!(history.redo().unwrap());
+        assert_eq!(history.neighbors(a).unwrap(), vec![b]);
+    }
+
+    #[test]
+    fn test_rolled_back_transactions_are_forgotten() {
+        let mut history = History::new(DatabaseGraph::open_in_memory().unwrap());
+        let a = history.add_node(test_node("a")).unwrap();
+
+        let result = history.transaction(|history| {
+            let b = history.add_node(test_node("b"))?;
+            history.add_edge(a, b)?;
+            history.add_edge(b, a)
+        });
+        assert!(result.is_err());
+        assert_eq!(history.node_indices().unwrap(), vec![a]);
+
+        let b = history
+            .transaction(|history| {
+                let b = history.add_node(test_node("b"))?;
+                history.add_edge(a, b)?;
+                Ok(b)
+            })
+            .unwrap();
+        assert!(history.undo().unwrap());
+        assert_eq!(history.node_indices().unwrap(), vec![a]);
+        assert!(history.undo().unwrap());
+        assert!(!history.can_undo());
+        assert!(history.redo().unwrap());
+        assert!(history.redo().unwrap());
+        assert_eq!(history.neighbors(a).unwrap(), vec![b]);
+    }
+}
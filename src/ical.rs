@@ -0,0 +1,642 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use anyhow::Context;
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, Utc};
+use chrono_tz::Tz;
+
+use crate::graph::{from_local_time, Graph, MetadataDate, Node, NodeIndex};
+use crate::import::Import;
+
+/// The metadata in which a task imported from iCalendar keeps its UID from there,
+/// so that exporting it again updates the same to-do in calendar apps.
+pub const UID_KEY: &str = "ical_uid";
+
+/// How iCalendar writes times in UTC, like `20240702T170000Z`.
+const DATE_FORMAT: &str = "%Y%m%dT%H%M%SZ";
+
+/// Lines longer than this many bytes are folded onto the next line.
+const LINE_LENGTH: usize = 75;
+
+/// Writes every task in `graph` as an iCalendar file, with one VTODO per task.
+///
+/// Titles become `SUMMARY`, descriptions `DESCRIPTION`, and done tasks are `STATUS:COMPLETED`
+/// with the time they were done as `COMPLETED`. The `scheduled` and `due` metadata become
+/// `DTSTART` and `DUE`, as `VALUE=DATE` for whole days, and `tags` become `CATEGORIES`. Each task
+/// has a `RELATED-TO;RELTYPE=DEPENDS-ON` for every task it has an edge to.
+/// Set-aside edges are left out, since they'd bring back the cycles they broke.
+pub fn export<G: Graph + ?Sized>(graph: &G) -> anyhow::Result<String> {
+    let snapshot = graph.snapshot()?;
+    let uids = snapshot.external_ids(UID_KEY);
+    let now = format_date(Utc::now());
+    let mut lines = vec![
+        "BEGIN:VCALENDAR".to_owned(),
+        "VERSION:2.0".to_owned(),
+        "PRODID:-//ekad//ekad//EN".to_owned(),
+    ];
+    for (index, node) in snapshot.nodes() {
+        lines.push("BEGIN:VTODO".to_owned());
+        lines.push(format!("UID:{}", escape(&uids[&index])));
+        lines.push(format!("DTSTAMP:{}", now));
+        lines.push(format!("SUMMARY:{}", escape(&node.title)));
+        if !node.description.is_empty() {
+            lines.push(format!("DESCRIPTION:{}", escape(&node.description)));
+        }
+        match node.completed_at {
+            Some(completed_at) => {
+                lines.push("STATUS:COMPLETED".to_owned());
+                lines.push(format!("COMPLETED:{}", format_date(completed_at)));
+            }
+            None => lines.push("STATUS:NEEDS-ACTION".to_owned()),
+        }
+        for (key, property) in [("scheduled", "DTSTART"), ("due", "DUE")] {
+            if let Some(date) = node.metadata.get(key) {
+                let date = MetadataDate::parse(date).with_context(|| {
+                    format!("Invalid {} date for {:?}: {}", key, node.title, date)
+                })?;
+                lines.push(match date {
+                    MetadataDate::Day(day) => {
+                        format!("{};VALUE=DATE:{}", property, day.format("%Y%m%d"))
+                    }
+                    MetadataDate::Time(time) => {
+                        format!("{}:{}", property, format_date(time.to_utc()))
+                    }
+                });
+            }
+        }
+        if let Some(tags) = node.metadata.get("tags") {
+            let tags: Vec<String> = tags.split_whitespace().map(escape).collect();
+            if !tags.is_empty() {
+                lines.push(format!("CATEGORIES:{}", tags.join(",")));
+            }
+        }
+        for child in snapshot.neighbors(index) {
+            lines.push(format!(
+                "RELATED-TO;RELTYPE=DEPENDS-ON:{}",
+                escape(&uids[&child])
+            ));
+        }
+        lines.push("END:VTODO".to_owned());
+    }
+    lines.push("END:VCALENDAR".to_owned());
+
+    let mut ics = String::new();
+    for line in lines {
+        fold(&mut ics, &line)?;
+    }
+    Ok(ics)
+}
+
+/// Writes `line` with a CRLF after it, folded so that no line is longer than `LINE_LENGTH`
+/// bytes, without splitting any characters.
+fn fold(ics: &mut String, line: &str) -> anyhow::Result<()> {
+    let mut rest = line;
+    // Folded lines start with a space, which counts towards their length.
+    let mut length = LINE_LENGTH;
+    while rest.len() > length {
+        let mut end = length;
+        while !rest.is_char_boundary(end) {
+            end -= 1;
+        }
+        write!(ics, "{}\r\n ", &rest[..end])?;
+        rest = &rest[end..];
+        length = LINE_LENGTH - 1;
+    }
+    write!(ics, "{}\r\n", rest)?;
+    Ok(())
+}
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace("\r\n", "\\n")
+        .replace('\n', "\\n")
+}
+
+fn unescape(text: &str) -> String {
+    let mut unescaped = String::new();
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n' | 'N') => unescaped.push('\n'),
+            Some(c) => unescaped.push(c),
+            None => unescaped.push('\\'),
+        }
+    }
+    unescaped
+}
+
+/// Splits a text value on the commas which aren't escaped, and unescapes each part.
+fn split_list(text: &str) -> Vec<String> {
+    let mut parts = vec![];
+    let mut start = 0;
+    let mut escaped = false;
+    for (i, c) in text.char_indices() {
+        if escaped {
+            escaped = false;
+        } else if c == '\\' {
+            escaped = true;
+        } else if c == ',' {
+            parts.push(unescape(&text[start..i]));
+            start = i + 1;
+        }
+    }
+    parts.push(unescape(&text[start..]));
+    parts
+}
+
+fn format_date(date: DateTime<Utc>) -> String {
+    date.format(DATE_FORMAT).to_string()
+}
+
+/// Reads a date or date-time value. Times are in the time zone their `TZID` names, if it's
+/// one from the IANA database, or else in local time unless they end in `Z` for UTC.
+fn parse_date(property: &Property) -> anyhow::Result<MetadataDate> {
+    let value = property.value;
+    let invalid = || format!("Invalid date {:?}", value);
+    let is_date = property
+        .parameter("VALUE")
+        .is_some_and(|kind| kind.eq_ignore_ascii_case("DATE"));
+    if is_date || !value.contains('T') {
+        let day = NaiveDate::parse_from_str(value, "%Y%m%d").with_context(invalid)?;
+        return Ok(MetadataDate::Day(day));
+    }
+    let time = NaiveDateTime::parse_from_str(value.trim_end_matches('Z'), "%Y%m%dT%H%M%S")
+        .with_context(invalid)?;
+    let time = if value.ends_with('Z') {
+        Some(time.and_utc())
+    } else {
+        match property.parameter("TZID") {
+            // A leading slash only says the name is a global one.
+            Some(tzid) => match tzid.trim_start_matches('/').parse::<Tz>() {
+                Ok(zone) => from_local_time(&zone, time),
+                Err(_) => {
+                    log::warn!("Unknown time zone {:?}, using local time instead", tzid);
+                    from_local_time(&Local, time)
+                }
+            },
+            None => from_local_time(&Local, time),
+        }
+    };
+    Ok(MetadataDate::Time(time.with_context(invalid)?.into()))
+}
+
+/// Adds the to-dos in an iCalendar file to `graph`, and returns the new tasks in order.
+///
+/// Each VTODO's `SUMMARY` becomes its title and its `DESCRIPTION` its description, and
+/// to-dos which are `STATUS:COMPLETED` or have a `COMPLETED` time are marked as done.
+/// `RELATED-TO` with `RELTYPE=DEPENDS-ON` or `CHILD` makes the task depend on the related one,
+/// and with `RELTYPE=PARENT`, which is the default, makes the related one depend on it.
+/// Relations to to-dos which aren't in the file are dropped, and everything else is skipped.
+pub fn import<G: Graph>(graph: &mut G, ics: &str) -> anyhow::Result<Vec<NodeIndex>> {
+    parse(ics)?.apply(graph)
+}
+
+/// A to-do which is still being read.
+#[derive(Default)]
+struct Todo {
+    node: Node,
+    uid: Option<String>,
+    done: bool,
+    /// The `RELTYPE` and UID of each `RELATED-TO`.
+    relations: Vec<(String, String)>,
+}
+
+/// Reads the to-dos in an iCalendar file, without adding them to a graph yet.
+pub fn parse(ics: &str) -> anyhow::Result<Import> {
+    let mut todos = vec![];
+    // The components the current line is in, like `VCALENDAR` and `VTODO`.
+    let mut components: Vec<String> = vec![];
+    let mut todo: Option<Todo> = None;
+    let mut found = false;
+    for (number, line) in unfold(ics) {
+        let property = split_property(&line)
+            .with_context(|| format!("Line {}: Invalid property {:?}", number + 1, line))?;
+        let value = property.value;
+        match property.name.as_str() {
+            "BEGIN" => {
+                let component = value.to_ascii_uppercase();
+                if components.is_empty() && component != "VCALENDAR" {
+                    anyhow::bail!("Not an iCalendar file");
+                }
+                found = true;
+                if component == "VTODO" && components.len() == 1 {
+                    todo = Some(Todo::default());
+                }
+                components.push(component);
+            }
+            "END" => {
+                if components.pop() != Some(value.to_ascii_uppercase()) {
+                    anyhow::bail!("Line {}: Unexpected END:{}", number + 1, value);
+                }
+                if components.len() == 1 {
+                    todos.extend(todo.take());
+                }
+            }
+            // Properties of the calendar, and of alarms and the like in to-dos, are skipped.
+            _ if components.len() == 2 => {
+                if let Some(todo) = &mut todo {
+                    read_property(todo, &property)
+                        .with_context(|| format!("Line {}", number + 1))?;
+                }
+            }
+            _ => {}
+        }
+    }
+    if let Some(component) = components.last() {
+        anyhow::bail!("The file ends in the middle of a {}", component);
+    }
+    if !found {
+        anyhow::bail!("Not an iCalendar file");
+    }
+
+    let mut import = Import::default();
+    let mut indices = HashMap::new();
+    let mut relations = vec![];
+    for todo in todos {
+        let mut node = todo.node;
+        if todo.done {
+            node.completed_at.get_or_insert_with(Utc::now);
+        }
+        let task = import.add_node(node, None);
+        if let Some(uid) = todo.uid {
+            if indices.insert(uid.clone(), task).is_some() {
+                anyhow::bail!("More than one to-do has the UID {}", uid);
+            }
+        }
+        relations.extend(todo.relations.into_iter().map(|relation| (task, relation)));
+    }
+    for (task, (reltype, uid)) in relations {
+        let Some(&related) = indices.get(&uid) else {
+            continue;
+        };
+        match reltype.as_str() {
+            "DEPENDS-ON" | "CHILD" => import.add_edge(task, related),
+            "PARENT" => import.add_edge(related, task),
+            _ => {}
+        }
+    }
+    Ok(import)
+}
+
+fn read_property(todo: &mut Todo, property: &Property) -> anyhow::Result<()> {
+    let node = &mut todo.node;
+    let value = property.value;
+    match property.name.as_str() {
+        "UID" => {
+            let uid = unescape(value);
+            node.metadata.insert(UID_KEY.to_owned(), uid.clone());
+            todo.uid = Some(uid);
+        }
+        "SUMMARY" => node.title = unescape(value),
+        "DESCRIPTION" => node.description = unescape(value),
+        "STATUS" => todo.done = value.eq_ignore_ascii_case("COMPLETED"),
+        "COMPLETED" => node.completed_at = Some(parse_date(property)?.to_utc()),
+        "DTSTART" => {
+            node.metadata
+                .insert("scheduled".to_owned(), parse_date(property)?.to_string());
+        }
+        "DUE" => {
+            node.metadata
+                .insert("due".to_owned(), parse_date(property)?.to_string());
+        }
+        "CATEGORIES" => {
+            // Tags are separated by spaces, so they can't have any of their own.
+            let mut tags: Vec<String> = node
+                .metadata
+                .get("tags")
+                .map(|tags| tags.split_whitespace().map(str::to_owned).collect())
+                .unwrap_or_default();
+            for tag in split_list(value) {
+                let tag = tag.split_whitespace().collect::<Vec<_>>().join("-");
+                if !tag.is_empty() && !tags.contains(&tag) {
+                    tags.push(tag);
+                }
+            }
+            if !tags.is_empty() {
+                node.metadata.insert("tags".to_owned(), tags.join(" "));
+            }
+        }
+        "RELATED-TO" => {
+            let reltype = property.parameter("RELTYPE").unwrap_or("PARENT");
+            let reltype = reltype.to_ascii_uppercase();
+            todo.relations.push((reltype, unescape(value)));
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Joins folded lines back together, and returns each one with its line number.
+fn unfold(ics: &str) -> Vec<(usize, String)> {
+    let mut lines: Vec<(usize, String)> = vec![];
+    for (number, line) in ics.lines().enumerate() {
+        match (line.strip_prefix([' ', '\t']), lines.last_mut()) {
+            (Some(rest), Some((_, last))) => last.push_str(rest),
+            _ if line.trim().is_empty() => {}
+            _ => lines.push((number, line.to_owned())),
+        }
+    }
+    lines
+}
+
+/// A line like `RELATED-TO;RELTYPE=DEPENDS-ON:uid`. Names of properties and parameters are
+/// made uppercase, since they aren't case-sensitive.
+struct Property<'a> {
+    name: String,
+    parameters: Vec<(String, &'a str)>,
+    value: &'a str,
+}
+
+impl Property<'_> {
+    fn parameter(&self, name: &str) -> Option<&str> {
+        self.parameters
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| *value)
+    }
+}
+
+fn split_property(line: &str) -> Option<Property<'_>> {
+    // Parameter values can have colons and semicolons in them, in quotes.
+    let mut quoted = false;
+    let mut parts = vec![];
+    let mut start = 0;
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            ';' if !quoted => {
+                parts.push(&line[start..i]);
+                start = i + 1;
+            }
+            ':' if !quoted => {
+                parts.push(&line[start..i]);
+                let name = parts[0].to_ascii_uppercase();
+                let parameters = parts[1..]
+                    .iter()
+                    .map(|parameter| {
+                        let (key, value) = parameter.split_once('=')?;
+                        Some((key.to_ascii_uppercase(), value.trim_matches('"')))
+                    })
+                    .collect::<Option<Vec<_>>>()?;
+                if name.is_empty() {
+                    return None;
+                }
+                return Some(Property {
+                    name,
+                    parameters,
+                    value: &line[i + 1..],
+                });
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graph::{DatabaseGraph, PetgraphGraph};
+    use crate::import::test_helpers::{date, sorted, task};
+
+    #[test]
+    fn test_export() {
+        let mut graph = PetgraphGraph::default();
+        let root = graph
+            .add_node(Node {
+                description: "Cheese, bread; and\nmore \\ things".to_owned(),
+                ..task(
+                    "Picnic",
+                    &[
+                        ("scheduled", "2024-07-03"),
+                        ("due", "2024-07-05T12:00:00+02:00"),
+                        ("tags", "food fun"),
+                    ],
+                )
+            })
+            .unwrap();
+        let done = graph
+            .add_node(Node {
+                completed_at: Some(date("2024-07-02T17:00:00Z")),
+                ..task(&"é".repeat(40), &[(UID_KEY, "abc@example.com")])
+            })
+            .unwrap();
+        graph.add_edge(root, done).unwrap();
+
+        let ics = export(&graph).unwrap();
+        assert!(ics.lines().all(|line| line.len() <= LINE_LENGTH + 1));
+        let ics: Vec<&str> = ics
+            .split("\r\n")
+            .filter(|line| !line.starts_with("DTSTAMP:"))
+            .collect();
+        assert_eq!(
+            ics,
+            [
+                "BEGIN:VCALENDAR".to_owned(),
+                "VERSION:2.0".to_owned(),
+                "PRODID:-//ekad//ekad//EN".to_owned(),
+                "BEGIN:VTODO".to_owned(),
+                format!("UID:{}", root),
+                "SUMMARY:Picnic".to_owned(),
+                r"DESCRIPTION:Cheese\, bread\; and\nmore \\ things".to_owned(),
+                "STATUS:NEEDS-ACTION".to_owned(),
+                "DTSTART;VALUE=DATE:20240703".to_owned(),
+                "DUE:20240705T100000Z".to_owned(),
+                "CATEGORIES:food,fun".to_owned(),
+                "RELATED-TO;RELTYPE=DEPENDS-ON:abc@example.com".to_owned(),
+                "END:VTODO".to_owned(),
+                "BEGIN:VTODO".to_owned(),
+                "UID:abc@example.com".to_owned(),
+                format!("SUMMARY:{}", "é".repeat(33)),
+                format!(" {}", "é".repeat(7)),
+                "STATUS:COMPLETED".to_owned(),
+                "COMPLETED:20240702T170000Z".to_owned(),
+                "END:VTODO".to_owned(),
+                "END:VCALENDAR".to_owned(),
+                String::new(),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let mut graph = PetgraphGraph::default();
+        let root = graph
+            .add_node(Node {
+                description: "Line 1\n\nLine 3, with a comma".to_owned(),
+                ..task(
+                    "root",
+                    &[
+                        ("scheduled", "2024-07-01"),
+                        ("due", "2024-07-05T12:15:00+00:00"),
+                        ("tags", "a b"),
+                    ],
+                )
+            })
+            .unwrap();
+        let a = graph
+            .add_node(Node {
+                completed_at: Some(date("2024-07-02T17:00:00Z")),
+                ..task("a", &[])
+            })
+            .unwrap();
+        let b = graph.add_node(task("b", &[])).unwrap();
+        graph.add_edge(root, a).unwrap();
+        graph.add_edge(root, b).unwrap();
+        graph.add_edge(a, b).unwrap();
+        let ics = export(&graph).unwrap();
+
+        let mut copy = DatabaseGraph::open_in_memory().unwrap();
+        let [root2, a2, b2] = import(&mut copy, &ics).unwrap()[..] else {
+            panic!();
+        };
+        assert_eq!(sorted(copy.neighbors(root2).unwrap()), sorted(vec![a2, b2]));
+        assert_eq!(copy.neighbors(a2).unwrap(), vec![b2]);
+        for (from, to) in [(root, root2), (a, a2), (b, b2)] {
+            let from = graph.get_node(from).unwrap();
+            let mut to = copy.get_node(to).unwrap();
+            assert_eq!(to.title, from.title);
+            assert_eq!(to.description, from.description);
+            assert_eq!(to.completed_at, from.completed_at);
+            assert!(to.metadata.remove(UID_KEY).is_some());
+            assert_eq!(to.metadata, from.metadata);
+        }
+
+        // The UIDs are kept, so exporting again updates the same to-dos.
+        assert_eq!(
+            export(&copy)
+                .unwrap()
+                .lines()
+                .filter(|line| line.starts_with("UID:"))
+                .collect::<Vec<_>>(),
+            [root, a, b].map(|index| format!("UID:{}", index)).to_vec()
+        );
+    }
+
+    #[test]
+    fn test_export_duplicate_uids() {
+        // Importing the same file twice leaves two tasks with each UID.
+        let mut graph = PetgraphGraph::default();
+        let a = graph.add_node(task("a", &[(UID_KEY, "x")])).unwrap();
+        let b = graph.add_node(task("b", &[(UID_KEY, "x")])).unwrap();
+        graph.add_edge(a, b).unwrap();
+
+        let ics = export(&graph).unwrap();
+        let lines: Vec<&str> = ics
+            .lines()
+            .filter(|line| line.starts_with("UID:") || line.starts_with("RELATED-TO"))
+            .collect();
+        assert_eq!(
+            lines,
+            [
+                "UID:x".to_owned(),
+                format!("RELATED-TO;RELTYPE=DEPENDS-ON:{}", b),
+                format!("UID:{}", b),
+            ]
+        );
+        let mut copy = PetgraphGraph::default();
+        let [a2, b2] = import(&mut copy, &ics).unwrap()[..] else {
+            panic!();
+        };
+        assert_eq!(copy.neighbors(a2).unwrap(), vec![b2]);
+    }
+
+    #[test]
+    fn test_import() {
+        let ics = "BEGIN:VCALENDAR\n\
+                   VERSION:2.0\n\
+                   PRODID:-//Example//Tasks//EN\n\
+                   BEGIN:VEVENT\n\
+                   UID:event\n\
+                   SUMMARY:Not a task\n\
+                   END:VEVENT\n\
+                   BEGIN:VTODO\n\
+                   UID:project\n\
+                   SUMMARY:Paint the\n \x20fence\n\
+                   DUE;TZID=Europe/Berlin:20240706T100000\n\
+                   CATEGORIES:home,diy\n\
+                   CATEGORIES:garden work\n\
+                   BEGIN:VALARM\n\
+                   ACTION:DISPLAY\n\
+                   DESCRIPTION:Not the description\n\
+                   END:VALARM\n\
+                   END:VTODO\n\
+                   BEGIN:VTODO\n\
+                   UID:sand\n\
+                   SUMMARY:Sand it\n\
+                   STATUS:COMPLETED\n\
+                   DUE;TZID=\"/America/New_York\":20240110T090000\n\
+                   related-to;reltype=\"PARENT\":project\n\
+                   END:VTODO\n\
+                   BEGIN:VTODO\n\
+                   UID:buy\n\
+                   SUMMARY:Buy paint\n\
+                   DTSTART;VALUE=DATE:20240703\n\
+                   RELATED-TO:project\n\
+                   RELATED-TO;RELTYPE=DEPENDS-ON:elsewhere\n\
+                   RELATED-TO;RELTYPE=SIBLING:sand\n\
+                   END:VTODO\n\
+                   END:VCALENDAR\n";
+        let mut graph = DatabaseGraph::open_in_memory().unwrap();
+        let [project, sand, buy] = import(&mut graph, ics).unwrap()[..] else {
+            panic!();
+        };
+        assert_eq!(
+            sorted(graph.neighbors(project).unwrap()),
+            sorted(vec![sand, buy])
+        );
+        assert_eq!(graph.neighbors(buy).unwrap(), Vec::<NodeIndex>::new());
+
+        let project = graph.get_node(project).unwrap();
+        assert_eq!(project.title, "Paint the fence");
+        assert_eq!(project.description, "");
+        assert_eq!(project.metadata["due"], "2024-07-06T08:00:00+00:00");
+        assert_eq!(project.metadata["tags"], "home diy garden-work");
+        let sand = graph.get_node(sand).unwrap();
+        assert!(sand.is_completed());
+        assert_eq!(sand.metadata["due"], "2024-01-10T14:00:00+00:00");
+        let buy = graph.get_node(buy).unwrap();
+        assert!(!buy.is_completed());
+        assert_eq!(buy.metadata["scheduled"], "2024-07-03");
+        assert_eq!(buy.metadata[UID_KEY], "buy");
+    }
+
+    #[test]
+    fn test_invalid() {
+        for (ics, message) in [
+            ("", "Not an iCalendar file"),
+            ("BEGIN:VCARD\nEND:VCARD\n", "Not an iCalendar file"),
+            (
+                "BEGIN:VCALENDAR\nBEGIN:VTODO\n",
+                "The file ends in the middle of a VTODO",
+            ),
+            (
+                "BEGIN:VCALENDAR\nBEGIN:VTODO\nEND:VCALENDAR\n",
+                "Line 3: Unexpected END:VCALENDAR",
+            ),
+            (
+                "BEGIN:VCALENDAR\nBEGIN:VTODO\nSUMMARY\nEND:VTODO\nEND:VCALENDAR\n",
+                "Line 3: Invalid property \"SUMMARY\"",
+            ),
+            (
+                "BEGIN:VCALENDAR\nBEGIN:VTODO\nDUE:tomorrow\nEND:VTODO\nEND:VCALENDAR\n",
+                "Line 3: Invalid date \"tomorrow\"",
+            ),
+            (
+                "BEGIN:VCALENDAR\nBEGIN:VTODO\nUID:a\nEND:VTODO\n\
+                 BEGIN:VTODO\nUID:a\nEND:VTODO\nEND:VCALENDAR\n",
+                "More than one to-do has the UID a",
+            ),
+        ] {
+            let error = parse(ics).err().unwrap();
+            let error = format!("{:#}", error);
+            assert!(error.starts_with(message), "{:?}: {}", ics, error);
+        }
+    }
+}
@@ -0,0 +1,399 @@
+use masonry::kurbo::{Circle, Point};
+
+use crate::graph::{Graph, Node, NodeIndex};
+use crate::graph_viewer::{new_node_position, CIRCLE_RADIUS};
+
+/// The distance between neighbouring nodes laid out by `Import`, both across and down.
+const LAYOUT_SPACING: f64 = 3.0 * CIRCLE_RADIUS;
+
+/// Tasks and dependencies read from another format, which can then be added to a graph.
+///
+/// Tasks are referred to by the order they were added in.
+#[derive(Debug, Default)]
+pub struct Import {
+    /// Each node's circle is only filled in when it's added to a graph.
+    nodes: Vec<(Node, Option<Point>)>,
+    edges: Vec<(usize, usize)>,
+    /// Edges which are added but set aside straight away, from `set_aside_cycles`.
+    set_aside: Vec<(usize, usize)>,
+}
+
+impl Import {
+    /// Adds a task at `position`, or wherever it fits in with the rest if there is none.
+    pub fn add_node(&mut self, node: Node, position: Option<Point>) -> usize {
+        self.nodes.push((node, position));
+        self.nodes.len() - 1
+    }
+
+    /// Makes `from` depend on `to`, unless it does already.
+    pub fn add_edge(&mut self, from: usize, to: usize) {
+        if !self.edges.contains(&(from, to)) {
+            self.edges.push((from, to));
+        }
+    }
+
+    pub fn node_mut(&mut self, index: usize) -> &mut Node {
+        &mut self.nodes[index].0
+    }
+
+    /// Returns the tasks along a cycle of dependencies, if there is one:
+    /// every task depends on the next one, and the last one depends on the first.
+    pub fn find_cycle(&self) -> Option<Vec<usize>> {
+        let children = self.children();
+        let mut visited = vec![Visit::New; self.nodes.len()];
+        let mut path = vec![];
+        (0..self.nodes.len())
+            .find_map(|start| find_cycle_from(start, &children, &mut visited, &mut path))
+    }
+
+    /// Breaks every cycle of dependencies by setting aside the edge which closes it, and returns
+    /// the cycles. Edges from tasks to themselves are dropped, since they can't even be set aside.
+    pub fn set_aside_cycles(&mut self) -> Vec<Vec<usize>> {
+        let mut cycles = vec![];
+        while let Some(cycle) = self.find_cycle() {
+            let edge = (cycle[cycle.len() - 1], cycle[0]);
+            self.edges.retain(|other| *other != edge);
+            if edge.0 != edge.1 {
+                self.set_aside.push(edge);
+            }
+            cycles.push(cycle);
+        }
+        cycles
+    }
+
+    /// Adds the tasks to `graph` all together, and returns their new indices in order.
+    /// Fails without changing `graph` if the dependencies form a cycle.
+    pub fn apply<G: Graph>(self, graph: &mut G) -> anyhow::Result<Vec<NodeIndex>> {
+        if let Some(cycle) = self.find_cycle() {
+            anyhow::bail!(
+                "The tasks depend on each other in a cycle: {}",
+                self.describe(&cycle)
+            );
+        }
+        let snapshot = graph.snapshot()?;
+        let positions = self.layout(new_node_position(&snapshot));
+        graph.transaction(|graph| {
+            let indices = self
+                .nodes
+                .iter()
+                .zip(positions)
+                .map(|((node, _), position)| {
+                    graph.add_node(Node {
+                        circle: Circle::new(position, CIRCLE_RADIUS),
+                        ..node.clone()
+                    })
+                })
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            // These may well close cycles, so they go in while there are no other edges yet.
+            for &(from, to) in &self.set_aside {
+                graph.add_edge(indices[from], indices[to])?;
+                graph.set_aside_edge(indices[from], indices[to])?;
+            }
+            for &(from, to) in &self.edges {
+                graph.add_edge(indices[from], indices[to])?;
+            }
+            Ok(indices)
+        })
+    }
+
+    /// Describes a cycle from `find_cycle` by the tasks' titles, like "a -> b -> a".
+    pub fn describe(&self, cycle: &[usize]) -> String {
+        cycle
+            .iter()
+            .chain(&cycle[..1])
+            .map(|&index| format!("{:?}", self.nodes[index].0.title))
+            .collect::<Vec<_>>()
+            .join(" -> ")
+    }
+
+    fn children(&self) -> Vec<Vec<usize>> {
+        let mut children = vec![vec![]; self.nodes.len()];
+        for &(from, to) in &self.edges {
+            children[from].push(to);
+        }
+        children
+    }
+
+    /// Puts every task without a position in rows starting at `origin`, with each task below
+    /// everything which depends on it, and next to its siblings as far as possible.
+    /// The dependencies mustn't have any cycles.
+    fn layout(&self, origin: Point) -> Vec<Point> {
+        let children = self.children();
+        // Tasks go one row below the lowest task which depends on them.
+        let mut parent_counts = vec![0; self.nodes.len()];
+        for &(_, to) in &self.edges {
+            parent_counts[to] += 1;
+        }
+        let mut ready: Vec<usize> = (0..self.nodes.len())
+            .filter(|&index| parent_counts[index] == 0)
+            .rev()
+            .collect();
+        let mut rows = vec![0; self.nodes.len()];
+        let mut order = vec![];
+        while let Some(index) = ready.pop() {
+            order.push(index);
+            for &child in children[index].iter().rev() {
+                rows[child] = rows[child].max(rows[index] + 1);
+                parent_counts[child] -= 1;
+                if parent_counts[child] == 0 {
+                    ready.push(child);
+                }
+            }
+        }
+        order.sort_by_key(|&index| rows[index]);
+
+        let mut positions: Vec<Option<Point>> =
+            self.nodes.iter().map(|(_, position)| *position).collect();
+        let mut row_start = 0;
+        while row_start < order.len() {
+            let row = rows[order[row_start]];
+            let row_end = order[row_start..]
+                .iter()
+                .position(|&index| rows[index] != row)
+                .map_or(order.len(), |length| row_start + length);
+            let mut tasks: Vec<(f64, usize)> = order[row_start..row_end]
+                .iter()
+                .filter(|&&index| positions[index].is_none())
+                .map(|&index| {
+                    // Tasks are put under the middle of the tasks which depend on them.
+                    let parents: Vec<f64> = self
+                        .edges
+                        .iter()
+                        .filter(|&&(_, to)| to == index)
+                        .filter_map(|&(from, _)| positions[from].map(|position| position.x))
+                        .collect();
+                    let x = if parents.is_empty() {
+                        f64::INFINITY
+                    } else {
+                        parents.iter().sum::<f64>() / parents.len() as f64
+                    };
+                    (x, index)
+                })
+                .collect();
+            tasks.sort_by(|a, b| a.0.total_cmp(&b.0));
+            for (column, (_, index)) in tasks.into_iter().enumerate() {
+                positions[index] = Some(Point::new(
+                    origin.x + column as f64 * LAYOUT_SPACING,
+                    origin.y + row as f64 * LAYOUT_SPACING,
+                ));
+            }
+            row_start = row_end;
+        }
+        positions
+            .into_iter()
+            .map(|position| position.unwrap_or(origin))
+            .collect()
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Visit {
+    New,
+    OnPath,
+    Done,
+}
+
+/// Looks for a cycle through the tasks reachable from `index`, where `path` leads to `index`.
+fn find_cycle_from(
+    index: usize,
+    children: &[Vec<usize>],
+    visited: &mut [Visit],
+    path: &mut Vec<usize>,
+) -> Option<Vec<usize>> {
+    match visited[index] {
+        Visit::Done => return None,
+        Visit::OnPath => {
+            let start = path.iter().position(|&other| other == index).unwrap();
+            return Some(path[start..].to_vec());
+        }
+        Visit::New => {}
+    }
+    visited[index] = Visit::OnPath;
+    path.push(index);
+    for &child in &children[index] {
+        if let Some(cycle) = find_cycle_from(child, children, visited, path) {
+            return Some(cycle);
+        }
+    }
+    path.pop();
+    visited[index] = Visit::Done;
+    None
+}
+
+/// Helpers for the tests of the formats which import into an `Import`.
+#[cfg(test)]
+pub(crate) mod test_helpers {
+    use chrono::{DateTime, Utc};
+
+    use crate::graph::{Graph, Node, NodeIndex, PetgraphGraph};
+
+    /// For comparing neighbours, which don't come back in any particular order.
+    pub fn sorted(mut indices: Vec<NodeIndex>) -> Vec<NodeIndex> {
+        indices.sort();
+        indices
+    }
+
+    pub fn date(text: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(text).unwrap().to_utc()
+    }
+
+    pub fn task(title: &str, metadata: &[(&str, &str)]) -> Node {
+        Node {
+            title: title.to_owned(),
+            metadata: metadata
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    /// Exports `root`, a completed child `a`, a child `b` and a task `shared` which both
+    /// children depend on, and imports them into a new graph.
+    ///
+    /// Checks that the tasks depend on each other the same way afterwards, and returns each task
+    /// with what came back for it, so the caller can check whatever its format keeps.
+    pub fn roundtrip<G: Graph>(
+        mut graph: G,
+        root: Node,
+        export: impl Fn(&G) -> anyhow::Result<String>,
+        import: impl Fn(&mut PetgraphGraph, &str) -> anyhow::Result<Vec<NodeIndex>>,
+    ) -> Vec<(Node, Node)> {
+        let root = graph
+            .add_node(Node {
+                title: "root".to_owned(),
+                ..root
+            })
+            .unwrap();
+        let a = graph
+            .add_node(Node {
+                completed_at: Some(date("2024-07-02T17:00:00Z")),
+                ..task("a", &[])
+            })
+            .unwrap();
+        let b = graph.add_node(task("b", &[])).unwrap();
+        let shared = graph.add_node(task("shared", &[])).unwrap();
+        graph.add_edge(root, a).unwrap();
+        graph.add_edge(root, b).unwrap();
+        graph.add_edge(a, shared).unwrap();
+        graph.add_edge(b, shared).unwrap();
+        let text = export(&graph).unwrap();
+
+        let mut copy = PetgraphGraph::default();
+        let indices = import(&mut copy, &text).unwrap();
+        // Outlines write tasks depth first, which isn't the order they were added in.
+        let find = |title: &str| {
+            *indices
+                .iter()
+                .find(|&&index| copy.get_node(index).unwrap().title == title)
+                .unwrap()
+        };
+        let [root2, a2, b2, shared2] = ["root", "a", "b", "shared"].map(find);
+        assert_eq!(sorted(copy.neighbors(root2).unwrap()), sorted(vec![a2, b2]));
+        assert_eq!(copy.neighbors(a2).unwrap(), vec![shared2]);
+        assert_eq!(copy.neighbors(b2).unwrap(), vec![shared2]);
+        [(root, root2), (a, a2), (b, b2), (shared, shared2)]
+            .into_iter()
+            .map(|(from, to)| (graph.get_node(from).unwrap(), copy.get_node(to).unwrap()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::test_helpers::{sorted, task};
+    use super::*;
+    use crate::graph::PetgraphGraph;
+
+    #[test]
+    fn test_apply() {
+        let mut import = Import::default();
+        let root = import.add_node(task("root", &[]), None);
+        let a = import.add_node(task("a", &[]), None);
+        let b = import.add_node(task("b", &[]), None);
+        let shared = import.add_node(task("shared", &[]), None);
+        let placed = import.add_node(task("placed", &[]), Some(Point::new(-500.0, 20.0)));
+        import.add_edge(root, a);
+        import.add_edge(root, b);
+        import.add_edge(root, shared);
+        import.add_edge(a, shared);
+        import.add_edge(a, shared);
+
+        let mut graph = PetgraphGraph::default();
+        let indices = import.apply(&mut graph).unwrap();
+        let circle = |index: usize| graph.get_node(indices[index]).unwrap().circle;
+        assert_eq!(graph.get_node(indices[a]).unwrap().title, "a");
+        assert_eq!(
+            sorted(graph.neighbors(indices[root]).unwrap()),
+            vec![indices[a], indices[b], indices[shared]]
+        );
+        assert_eq!(
+            sorted(graph.neighbors(indices[a]).unwrap()),
+            vec![indices[shared]]
+        );
+
+        // Every task is below the tasks which depend on it, and they don't overlap.
+        assert_eq!(circle(root).center, Point::ORIGIN);
+        assert!(circle(a).center.y > circle(root).center.y);
+        assert_eq!(circle(b).center.y, circle(a).center.y);
+        assert!(circle(b).center.x > circle(a).center.x + 2.0 * CIRCLE_RADIUS);
+        assert!(circle(shared).center.y > circle(a).center.y);
+        assert_eq!(circle(placed).center, Point::new(-500.0, 20.0));
+        assert_eq!(circle(root).radius, CIRCLE_RADIUS);
+
+        // More tasks go below the ones already there.
+        let mut import = Import::default();
+        import.add_node(task("more", &[]), None);
+        let lowest = circle(shared).center.y;
+        let more = import.apply(&mut graph).unwrap()[0];
+        assert!(graph.get_node(more).unwrap().circle.center.y > lowest);
+    }
+
+    #[test]
+    fn test_apply_rejects_cycles() {
+        let mut import = Import::default();
+        let a = import.add_node(task("a", &[]), None);
+        let b = import.add_node(task("b", &[]), None);
+        let c = import.add_node(task("c", &[]), None);
+        import.add_edge(a, b);
+        import.add_edge(b, c);
+        assert_eq!(import.find_cycle(), None);
+        import.add_edge(c, b);
+        assert_eq!(import.find_cycle(), Some(vec![b, c]));
+
+        let mut graph = PetgraphGraph::default();
+        let error = import.apply(&mut graph).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "The tasks depend on each other in a cycle: \"b\" -> \"c\" -> \"b\""
+        );
+        assert_eq!(graph.node_indices().unwrap(), Vec::<NodeIndex>::new());
+    }
+
+    #[test]
+    fn test_set_aside_cycles() {
+        let mut import = Import::default();
+        let a = import.add_node(task("a", &[]), None);
+        let b = import.add_node(task("b", &[]), None);
+        let c = import.add_node(task("c", &[]), None);
+        import.add_edge(a, b);
+        import.add_edge(b, c);
+        import.add_edge(c, a);
+        import.add_edge(c, c);
+        assert_eq!(import.set_aside_cycles(), vec![vec![a, b, c], vec![c]]);
+        assert_eq!(import.find_cycle(), None);
+
+        let mut graph = PetgraphGraph::default();
+        let indices = import.apply(&mut graph).unwrap();
+        assert_eq!(graph.neighbors(indices[a]).unwrap(), vec![indices[b]]);
+        assert_eq!(graph.neighbors(indices[b]).unwrap(), vec![indices[c]]);
+        assert_eq!(
+            graph.neighbors(indices[c]).unwrap(),
+            Vec::<NodeIndex>::new()
+        );
+        assert_eq!(
+            graph.set_aside_edges().unwrap(),
+            vec![(indices[c], indices[a])]
+        );
+    }
+}
@@ -0,0 +1,19 @@
+pub mod automerge_graph;
+pub mod dot;
+pub mod export;
+pub mod graph;
+pub mod graph_viewer;
+pub mod history;
+pub mod ical;
+pub mod import;
+pub mod markdown;
+pub mod mermaid;
+pub mod opml;
+pub mod org;
+pub mod outline;
+pub mod rpc;
+pub mod shapes;
+pub mod sync;
+pub mod taskwarrior;
+pub mod text;
+pub mod watch;
@@ -1,33 +1,232 @@
-mod graph;
-mod graph_viewer;
-mod shapes;
-mod text;
-
+use std::net::{SocketAddr, TcpListener};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
 use std::sync::{Arc, Mutex};
 
-use crate::graph::DatabaseGraph;
-use crate::graph_viewer::graph_viewer;
-use winit::error::EventLoopError;
+use anyhow::Context;
+use ekad::automerge_graph::AutomergeGraph;
+use ekad::graph::{DatabaseGraph, Graph, Node, NodeIndex};
+use ekad::graph_viewer::graph_viewer;
+use ekad::history::History;
+use ekad::rpc::RpcServer;
+use ekad::sync::{SyncPeer, SyncServer};
+use ekad::watch::Watcher;
 use xilem::{
     style::Style,
-    view::{flex, grid, label, Axis, GridExt, GridParams},
-    Color, EventLoop, WidgetView, WindowOptions, Xilem,
+    view::{flex, grid, label, text_input, Axis, GridExt, GridParams},
+    Color, EventLoop, InsertNewline, WidgetView, WindowOptions, Xilem,
 };
 
-struct AppState {
-    graph: Arc<Mutex<DatabaseGraph>>,
+const USAGE: &str = "\
+Usage: ekad [OPTIONS] [DATABASE]
+
+Opens the task graph stored in DATABASE, which defaults to ekad/db.sqlite
+in your data directory, or ekad/db.automerge with --automerge.
+
+Options:
+      --listen ADDRESS  Let other programs change the graph over JSON-RPC, on a local
+                        address like 127.0.0.1:7878 or at a Unix socket path
+      --read-only       Look at the graph without changing it
+      --scratch         Start with an empty graph which is thrown away on exit
+      --automerge       Store the graph as an Automerge document, which can be synced
+      --sync ADDRESS    Sync the graph with ekads which connect to ADDRESS, like
+                        0.0.0.0:7879 (needs --automerge)
+      --peer ADDRESS    Sync the graph with the ekad syncing at ADDRESS; can be given
+                        more than once (needs --automerge)
+  -h, --help            Print this message";
+
+#[derive(Debug, Default, PartialEq)]
+enum Database {
+    #[default]
+    Default,
+    Path(PathBuf),
+    Scratch,
+}
+
+#[derive(Debug, Default, PartialEq)]
+struct Args {
+    database: Database,
+    listen: Option<String>,
+    read_only: bool,
+    automerge: bool,
+    sync: Option<String>,
+    peers: Vec<String>,
+}
+
+impl Args {
+    /// Returns `None` if the user only asked for help.
+    fn parse(args: impl IntoIterator<Item = String>) -> anyhow::Result<Option<Self>> {
+        let mut args = args.into_iter();
+        let mut database = Database::Default;
+        let mut listen = None;
+        let mut read_only = false;
+        let mut scratch = false;
+        let mut automerge = false;
+        let mut sync = None;
+        let mut peers = vec![];
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-h" | "--help" => return Ok(None),
+                "--listen" => listen = Some(args.next().context("--listen needs an address")?),
+                "--read-only" => read_only = true,
+                "--scratch" => scratch = true,
+                "--automerge" => automerge = true,
+                "--sync" => sync = Some(args.next().context("--sync needs an address")?),
+                "--peer" => peers.push(args.next().context("--peer needs an address")?),
+                _ if arg.starts_with('-') => anyhow::bail!("Unknown option {}, see --help", arg),
+                _ if database != Database::Default => {
+                    anyhow::bail!("Only one database can be opened at a time")
+                }
+                _ => database = Database::Path(arg.into()),
+            }
+        }
+        if scratch {
+            if database != Database::Default {
+                anyhow::bail!("--scratch can't be used with a database path");
+            }
+            if read_only {
+                anyhow::bail!("--scratch can't be used with --read-only");
+            }
+            database = Database::Scratch;
+        }
+        if automerge && read_only {
+            anyhow::bail!("--automerge can't be used with --read-only");
+        }
+        if !automerge && (sync.is_some() || !peers.is_empty()) {
+            anyhow::bail!("Only --automerge graphs can be synced");
+        }
+        Ok(Some(Self {
+            database,
+            listen,
+            read_only,
+            automerge,
+            sync,
+            peers,
+        }))
+    }
+
+    fn open(&self) -> anyhow::Result<DatabaseGraph> {
+        match (&self.database, self.read_only) {
+            (Database::Scratch, _) => DatabaseGraph::open_in_memory(),
+            (Database::Default, false) => DatabaseGraph::open_default(),
+            (Database::Default, true) => {
+                DatabaseGraph::open_read_only(DatabaseGraph::default_path()?)
+            }
+            (Database::Path(path), false) => DatabaseGraph::open(path),
+            (Database::Path(path), true) => DatabaseGraph::open_read_only(path),
+        }
+    }
+
+    fn open_automerge(&self) -> anyhow::Result<AutomergeGraph> {
+        match &self.database {
+            Database::Scratch => AutomergeGraph::open_in_memory(),
+            Database::Default => AutomergeGraph::open_default(),
+            Database::Path(path) => AutomergeGraph::open(path)
+                .with_context(|| format!("Failed to open {}", path.display())),
+        }
+    }
 }
 
-impl Default for AppState {
-    fn default() -> Self {
-        Self {
-            graph: Arc::new(Mutex::new(DatabaseGraph::default())),
+/// Starts serving JSON-RPC requests for `graph` at `address`.
+fn listen<G: Graph + Send + 'static>(
+    address: &str,
+    graph: Arc<Mutex<History<G>>>,
+) -> anyhow::Result<RpcServer> {
+    if let Ok(address) = address.parse::<SocketAddr>() {
+        // Anyone who can connect can change the graph.
+        if !address.ip().is_loopback() {
+            anyhow::bail!(
+                "Only local addresses like 127.0.0.1 can be listened on, not {}",
+                address
+            );
+        }
+        let listener = TcpListener::bind(address)
+            .with_context(|| format!("Failed to listen on {}", address))?;
+        return Ok(RpcServer::start(graph, listener)?);
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::net::{UnixListener, UnixStream};
+        let path = Path::new(address);
+        // A socket left behind by an ekad which has since exited gets in the way of binding.
+        if path.exists() && UnixStream::connect(path).is_err() {
+            std::fs::remove_file(path)
+                .with_context(|| format!("Failed to remove stale socket {}", path.display()))?;
         }
+        let listener = UnixListener::bind(path)
+            .with_context(|| format!("Failed to listen on {}", path.display()))?;
+        Ok(RpcServer::start(graph, listener)?)
     }
+    #[cfg(not(unix))]
+    anyhow::bail!("{} isn't an address like 127.0.0.1:7878", address)
+}
+
+/// Starts syncing `graph` with the peers in `args`.
+fn sync(
+    args: &Args,
+    graph: &Arc<Mutex<History<AutomergeGraph>>>,
+) -> anyhow::Result<(Option<SyncServer>, Vec<SyncPeer>)> {
+    let server = match &args.sync {
+        Some(address) => {
+            // Unlike --listen, peers are usually on other machines, so any address is allowed.
+            let listener = TcpListener::bind(address)
+                .with_context(|| format!("Failed to listen for peers on {}", address))?;
+            Some(SyncServer::start(graph.clone(), listener)?)
+        }
+        None => None,
+    };
+    let peers = args
+        .peers
+        .iter()
+        .map(|address| {
+            SyncPeer::connect(graph.clone(), address.as_str())
+                .with_context(|| format!("Failed to connect to peer {}", address))
+        })
+        .collect::<anyhow::Result<_>>()?;
+    Ok((server, peers))
 }
 
-impl AppState {
-    fn main(&mut self) -> impl WidgetView<AppState> {
+struct AppState<G> {
+    graph: Arc<Mutex<History<G>>>,
+    selected_node: Option<NodeIndex>,
+    read_only: bool,
+    /// Picks up changes which other processes make to the database.
+    _watcher: Watcher,
+    _server: Option<RpcServer>,
+    _sync_server: Option<SyncServer>,
+    _peers: Vec<SyncPeer>,
+}
+
+impl<G: Graph + Send + 'static> AppState<G> {
+    fn new(mut graph: G, args: &Args) -> anyhow::Result<Self> {
+        let read_only = args.read_only;
+        if !read_only {
+            match graph.repair_cycles() {
+                Ok(edges) => {
+                    for (from, to) in edges {
+                        log::warn!("Set aside edge from {} to {} to break a cycle", from, to);
+                    }
+                }
+                Err(e) => log::error!("Failed to repair cycles: {}", e),
+            }
+        }
+        let graph = Arc::new(Mutex::new(History::new(graph)));
+        let server = match &args.listen {
+            Some(address) => Some(listen(address, graph.clone())?),
+            None => None,
+        };
+        Ok(Self {
+            _watcher: Watcher::start(graph.clone()),
+            _server: server,
+            _sync_server: None,
+            _peers: vec![],
+            graph,
+            selected_node: None,
+            read_only,
+        })
+    }
+
+    fn main(&mut self) -> impl WidgetView<AppState<G>> {
         grid(
             (
                 self.menu_pane().grid_item(GridParams::new(0, 0, 1, 1)),
@@ -38,21 +237,257 @@ impl AppState {
         )
     }
 
-    fn menu_pane(&mut self) -> impl WidgetView<AppState> {
-        flex(Axis::Vertical, label("Menu pane")).background_color(Color::from_rgb8(32, 32, 32))
+    fn menu_pane(&mut self) -> impl WidgetView<AppState<G>> {
+        let selected_node = self.selected_node.and_then(|node_id| {
+            Some((node_id, self.graph.lock().unwrap().get_node(node_id).ok()?))
+        });
+
+        let placeholder = selected_node.is_none().then(|| label("No task selected"));
+        let (details, read_only_details) = match selected_node {
+            Some((_, node)) if self.read_only => (
+                None,
+                Some((
+                    label("Title"),
+                    label(node.title),
+                    label("Description"),
+                    label(node.description),
+                )),
+            ),
+            Some((node_id, node)) => (
+                Some((
+                    label("Title"),
+                    text_input(node.title, move |state: &mut AppState<G>, title| {
+                        state.update_node(node_id, |node| node.title = title);
+                    }),
+                    label("Description"),
+                    text_input(
+                        node.description,
+                        move |state: &mut AppState<G>, description| {
+                            state.update_node(node_id, |node| node.description = description);
+                        },
+                    )
+                    .insert_newline(InsertNewline::OnEnter),
+                )),
+                None,
+            ),
+            None => (None, None),
+        };
+
+        flex(Axis::Vertical, (details, read_only_details, placeholder))
+            .background_color(Color::from_rgb8(32, 32, 32))
+    }
+
+    fn content_pane(&mut self) -> impl WidgetView<AppState<G>> {
+        graph_viewer(
+            self.graph.clone(),
+            |state: &mut AppState<G>, node_id| {
+                state.selected_node = node_id;
+            },
+            // Rerunning the app logic refreshes the details of the selected node.
+            |_: &mut AppState<G>| {},
+        )
+        .read_only(self.read_only)
+    }
+
+    fn update_node(&mut self, node_id: NodeIndex, update: impl FnOnce(&mut Node)) {
+        let mut graph = self.graph.lock().unwrap();
+        // The node may have gone since it was selected, through undo, RPC or another process.
+        let result = graph.get_node(node_id).and_then(|mut node| {
+            update(&mut node);
+            graph.set_node(node_id, node)
+        });
+        if let Err(e) = result {
+            log::error!("Failed to update task {}: {:#}", node_id, e);
+            self.selected_node = None;
+        }
+    }
+}
+
+fn main() -> ExitCode {
+    match run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("ekad: {:#}", e);
+            ExitCode::FAILURE
+        }
     }
+}
 
-    fn content_pane(&mut self) -> impl WidgetView<AppState> {
-        graph_viewer(self.graph.clone())
+fn run() -> anyhow::Result<()> {
+    let Some(args) = Args::parse(std::env::args().skip(1))? else {
+        println!("{}", USAGE);
+        return Ok(());
+    };
+    if args.automerge {
+        let mut state = AppState::new(args.open_automerge()?, &args)?;
+        (state._sync_server, state._peers) = sync(&args, &state.graph)?;
+        run_app(state)
+    } else {
+        run_app(AppState::new(args.open()?, &args)?)
     }
 }
 
-fn main() -> Result<(), EventLoopError> {
-    let app = Xilem::new_simple(
-        AppState::default(),
-        AppState::main,
-        WindowOptions::new("ekad"),
-    );
+fn run_app<G: Graph + Send + 'static>(state: AppState<G>) -> anyhow::Result<()> {
+    let app = Xilem::new_simple(state, AppState::main, WindowOptions::new("ekad"));
     app.run_in(EventLoop::with_user_event())?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> anyhow::Result<Option<Args>> {
+        Args::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn test_parse_args() {
+        assert_eq!(
+            parse(&[]).unwrap(),
+            Some(Args {
+                database: Database::Default,
+                listen: None,
+                read_only: false,
+                ..Default::default()
+            })
+        );
+        assert_eq!(
+            parse(&["--read-only", "tasks.sqlite"]).unwrap(),
+            Some(Args {
+                database: Database::Path("tasks.sqlite".into()),
+                listen: None,
+                read_only: true,
+                ..Default::default()
+            })
+        );
+        assert_eq!(
+            parse(&["--scratch"]).unwrap(),
+            Some(Args {
+                database: Database::Scratch,
+                listen: None,
+                read_only: false,
+                ..Default::default()
+            })
+        );
+        assert_eq!(
+            parse(&["--listen", "127.0.0.1:7878"]).unwrap(),
+            Some(Args {
+                database: Database::Default,
+                listen: Some("127.0.0.1:7878".to_owned()),
+                read_only: false,
+                ..Default::default()
+            })
+        );
+        assert_eq!(
+            parse(&[
+                "--automerge",
+                "--sync",
+                "0.0.0.0:7879",
+                "--peer",
+                "a:7879",
+                "--peer",
+                "b:7879"
+            ])
+            .unwrap(),
+            Some(Args {
+                automerge: true,
+                sync: Some("0.0.0.0:7879".to_owned()),
+                peers: vec!["a:7879".to_owned(), "b:7879".to_owned()],
+                ..Default::default()
+            })
+        );
+        assert_eq!(parse(&["tasks.sqlite", "--help"]).unwrap(), None);
+
+        assert!(parse(&["--verbose"]).is_err());
+        assert!(parse(&["--listen"]).is_err());
+        assert!(parse(&["a.sqlite", "b.sqlite"]).is_err());
+        assert!(parse(&["--scratch", "tasks.sqlite"]).is_err());
+        assert!(parse(&["--scratch", "--read-only"]).is_err());
+        assert!(parse(&["--automerge", "--read-only"]).is_err());
+        assert!(parse(&["--sync", "0.0.0.0:7879"]).is_err());
+        assert!(parse(&["--peer", "a:7879"]).is_err());
+    }
+
+    #[test]
+    fn test_open_read_only() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("db.sqlite");
+        let read_only = Args {
+            database: Database::Path(path.clone()),
+            listen: None,
+            read_only: true,
+            ..Default::default()
+        };
+        // A read-only database has to exist already.
+        assert!(read_only.open().is_err());
+
+        let mut graph = DatabaseGraph::open(&path).unwrap();
+        let a = graph.add_node(Node::default()).unwrap();
+        let mut graph = read_only.open().unwrap();
+        assert_eq!(graph.node_indices().unwrap(), vec![a]);
+        assert!(graph.add_node(Node::default()).is_err());
+    }
+
+    #[test]
+    fn test_open_automerge_and_sync() {
+        let dir = tempfile::tempdir().unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        drop(listener);
+
+        let first = Args {
+            database: Database::Path(dir.path().join("db.automerge")),
+            automerge: true,
+            sync: Some(address.clone()),
+            ..Default::default()
+        };
+        let first_graph = Arc::new(Mutex::new(History::new(first.open_automerge().unwrap())));
+        let a = first_graph
+            .lock()
+            .unwrap()
+            .add_node(Node::default())
+            .unwrap();
+        let _first_sync = sync(&first, &first_graph).unwrap();
+
+        let second = Args {
+            database: Database::Scratch,
+            automerge: true,
+            peers: vec![address],
+            ..Default::default()
+        };
+        let second_graph = Arc::new(Mutex::new(History::new(second.open_automerge().unwrap())));
+        let _second_sync = sync(&second, &second_graph).unwrap();
+
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(10);
+        while second_graph.lock().unwrap().node_indices().unwrap() != [a] {
+            assert!(
+                std::time::Instant::now() < deadline,
+                "Timed out waiting for sync"
+            );
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn test_listen() {
+        let graph = Arc::new(Mutex::new(History::new(
+            DatabaseGraph::open_in_memory().unwrap(),
+        )));
+        assert!(listen("0.0.0.0:0", graph.clone()).is_err());
+        listen("127.0.0.1:0", graph.clone()).unwrap();
+
+        #[cfg(unix)]
+        {
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("ekad.sock");
+            let path = path.to_str().unwrap();
+            let server = listen(path, graph.clone()).unwrap();
+            // Only one ekad can listen on a socket at once...
+            assert!(listen(path, graph.clone()).is_err());
+            // ...but one which has gone away doesn't stop another from listening.
+            drop(server);
+            listen(path, graph).unwrap();
+        }
+    }
+}
@@ -0,0 +1,455 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+
+use chrono::Utc;
+
+use crate::graph::{Graph, GraphSnapshot, Node, NodeIndex};
+use crate::import::Import;
+
+/// Writes `graph` as a nested Markdown checklist, with the tasks each task depends on nested
+/// under it, and descriptions as indented text.
+///
+/// Tasks which more than one task depends on are written out in full the first time, marked
+/// like `(#1)`, and every other time as a reference like `(see #1)`, which `import` follows back.
+/// Tasks whose titles already end in something like a label get a label of their own too, so
+/// that it stays part of the title when read back.
+pub fn export<G: Graph + ?Sized>(graph: &G) -> anyhow::Result<String> {
+    let snapshot = graph.snapshot()?;
+    let mut parent_counts: HashMap<NodeIndex, usize> = HashMap::new();
+    for (_, to) in snapshot.edges() {
+        *parent_counts.entry(*to).or_default() += 1;
+    }
+    let roots: Vec<NodeIndex> = snapshot
+        .nodes()
+        .map(|(index, _)| index)
+        .filter(|index| !parent_counts.contains_key(index))
+        .collect();
+    let mut writer = Writer {
+        snapshot: &snapshot,
+        shared: parent_counts
+            .into_iter()
+            .filter(|(_, count)| *count > 1)
+            .map(|(index, _)| index)
+            .collect(),
+        labels: HashMap::new(),
+        markdown: String::new(),
+    };
+    for root in roots {
+        writer.item(root, 0)?;
+    }
+    Ok(writer.markdown)
+}
+
+struct Writer<'a> {
+    snapshot: &'a GraphSnapshot,
+    /// Tasks which have more than one parent, and so appear more than once.
+    shared: HashSet<NodeIndex>,
+    /// The numbers of labelled tasks which have been written out.
+    labels: HashMap<NodeIndex, usize>,
+    markdown: String,
+}
+
+impl Writer<'_> {
+    fn item(&mut self, index: NodeIndex, depth: usize) -> anyhow::Result<()> {
+        let node = self.snapshot.node(index).unwrap();
+        let indent = "  ".repeat(depth);
+        let check = if node.is_completed() { 'x' } else { ' ' };
+        // Titles have to fit on the line.
+        let title = node.title.split_whitespace().collect::<Vec<_>>().join(" ");
+        write!(self.markdown, "{}- [{}] {}", indent, check, title)?;
+        if let Some(label) = self.labels.get(&index) {
+            writeln!(self.markdown, " (see #{})", label)?;
+            return Ok(());
+        }
+        let looks_labelled = ["(see #", "(#"]
+            .into_iter()
+            .any(|prefix| split_label(&title, prefix).is_some());
+        if self.shared.contains(&index) || looks_labelled {
+            let label = self.labels.len() + 1;
+            self.labels.insert(index, label);
+            write!(self.markdown, " (#{})", label)?;
+        }
+        self.markdown.push('\n');
+        for line in node.description.lines() {
+            if line.trim().is_empty() {
+                self.markdown.push('\n');
+            } else {
+                writeln!(self.markdown, "{}  {}", indent, line)?;
+            }
+        }
+        let children: Vec<NodeIndex> = self.snapshot.neighbors(index).collect();
+        for child in children {
+            self.item(child, depth + 1)?;
+        }
+        Ok(())
+    }
+}
+
+/// Adds the items of nested Markdown lists to `graph`, and returns the new tasks in order.
+///
+/// Every item becomes a task which depends on the items nested under it. Items checked off with
+/// `[x]` are marked as done, and text indented under an item becomes its description. An item
+/// ending in `(see #1)` stands for the item ending in `(#1)`, so that a task can be shared.
+/// Anything which isn't in a list is skipped.
+pub fn import<G: Graph>(graph: &mut G, markdown: &str) -> anyhow::Result<Vec<NodeIndex>> {
+    parse(markdown)?.apply(graph)
+}
+
+/// An item, which is either a task or a reference to one by its label.
+#[derive(Clone)]
+enum Item<'a> {
+    Task(usize),
+    Reference { label: &'a str, line: usize },
+}
+
+/// Reads nested Markdown lists, without adding them to a graph yet.
+pub fn parse(markdown: &str) -> anyhow::Result<Import> {
+    let mut import = Import::default();
+    let mut labels = HashMap::new();
+    let mut edges = vec![];
+    // The items which contain the current line, and how far they're indented.
+    let mut open: Vec<(usize, Item)> = vec![];
+    // Blank lines are only part of a description if more of it follows.
+    let mut blank_lines = 0;
+    for (number, line) in markdown.lines().enumerate() {
+        let indent = indentation(line);
+        let text = line.trim();
+        if text.is_empty() {
+            blank_lines += 1;
+            continue;
+        }
+        while open.last().is_some_and(|(open, _)| *open >= indent) {
+            open.pop();
+        }
+        let Some((checked, title)) = list_item(text) else {
+            match open.last() {
+                Some((_, Item::Task(task))) => {
+                    let description = &mut import.node_mut(*task).description;
+                    if !description.is_empty() {
+                        description.push_str(&"\n".repeat(blank_lines + 1));
+                    }
+                    description.push_str(text);
+                }
+                Some((_, Item::Reference { .. })) => {}
+                // Headings, paragraphs and so on between the lists.
+                None => open.clear(),
+            }
+            blank_lines = 0;
+            continue;
+        };
+        blank_lines = 0;
+
+        let item = if let Some((_, label)) = split_label(title, "(see #") {
+            Item::Reference {
+                label,
+                line: number + 1,
+            }
+        } else {
+            let (title, label) = split_label(title, "(#").unwrap_or((title, ""));
+            let task = import.add_node(
+                Node {
+                    title: title.to_owned(),
+                    completed_at: checked.then(Utc::now),
+                    ..Default::default()
+                },
+                None,
+            );
+            if !label.is_empty() && labels.insert(label, task).is_some() {
+                anyhow::bail!(
+                    "Line {}: More than one item is marked (#{})",
+                    number + 1,
+                    label
+                );
+            }
+            Item::Task(task)
+        };
+        if let Some((_, parent)) = open.last() {
+            edges.push((parent.clone(), item.clone()));
+        }
+        open.push((indent, item));
+    }
+
+    let resolve = |item: &Item| match *item {
+        Item::Task(task) => Ok(task),
+        Item::Reference { label, line } => labels
+            .get(label)
+            .copied()
+            .ok_or_else(|| anyhow::anyhow!("Line {}: No item is marked (#{})", line, label)),
+    };
+    for (from, to) in edges {
+        import.add_edge(resolve(&from)?, resolve(&to)?);
+    }
+    Ok(import)
+}
+
+/// How far `line` is indented, with tabs going to the next multiple of four.
+fn indentation(line: &str) -> usize {
+    let mut indent = 0;
+    for c in line.chars() {
+        match c {
+            ' ' => indent += 1,
+            '\t' => indent += 4 - indent % 4,
+            _ => break,
+        }
+    }
+    indent
+}
+
+/// Reads a list item like `- [x] Title`, `* Title` or `1. [ ] Title`,
+/// and returns whether it's checked off and its title.
+fn list_item(text: &str) -> Option<(bool, &str)> {
+    let rest = match text.strip_prefix(['-', '*', '+']) {
+        Some(rest) => rest,
+        None => {
+            let digits = text.find(|c: char| !c.is_ascii_digit())?;
+            if digits == 0 {
+                return None;
+            }
+            text[digits..].strip_prefix(['.', ')'])?
+        }
+    };
+    // A marker has to be followed by a space, or be all there is.
+    if !rest.is_empty() && !rest.starts_with(char::is_whitespace) {
+        return None;
+    }
+    let rest = rest.trim_start();
+    for (checkbox, checked) in [("[ ]", false), ("[x]", true), ("[X]", true)] {
+        if let Some(title) = rest.strip_prefix(checkbox) {
+            if title.is_empty() || title.starts_with(char::is_whitespace) {
+                return Some((checked, title.trim()));
+            }
+        }
+    }
+    Some((false, rest))
+}
+
+/// Splits a label like `(#1)` or `(see #1)` off the end of `title`, if it has one.
+fn split_label<'a>(title: &'a str, prefix: &str) -> Option<(&'a str, &'a str)> {
+    let rest = title.strip_suffix(')')?;
+    let start = rest.rfind(prefix)?;
+    let label = &rest[start + prefix.len()..];
+    if label.is_empty()
+        || !label
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '-' || c == '_')
+    {
+        return None;
+    }
+    Some((rest[..start].trim_end(), label))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graph::{DatabaseGraph, PetgraphGraph};
+    use crate::import::test_helpers::sorted;
+
+    #[test]
+    fn test_import() {
+        let markdown = "# Launch
+
+Some notes which aren't tasks.
+
+- [ ] Ship it
+  Before Friday.
+
+  Really.
+  - [x] Write the code
+    1. [X] Tests
+    2. Docs (#docs)
+  - [ ] Write the announcement
+\t- [ ] Proofread
+\t  - [ ] Docs (see #docs)
+* [ ] Celebrate
++ Plain item
+-not an item
+";
+        let mut graph = PetgraphGraph::default();
+        let indices = import(&mut graph, markdown).unwrap();
+        let nodes: Vec<Node> = indices
+            .iter()
+            .map(|index| graph.get_node(*index).unwrap())
+            .collect();
+        let titles: Vec<&str> = nodes.iter().map(|node| node.title.as_str()).collect();
+        assert_eq!(
+            titles,
+            [
+                "Ship it",
+                "Write the code",
+                "Tests",
+                "Docs",
+                "Write the announcement",
+                "Proofread",
+                "Celebrate",
+                "Plain item",
+            ]
+        );
+        let completed: Vec<bool> = nodes.iter().map(Node::is_completed).collect();
+        assert_eq!(
+            completed,
+            [false, true, true, false, false, false, false, false]
+        );
+        assert_eq!(nodes[0].description, "Before Friday.\n\nReally.");
+        assert_eq!(nodes[7].description, "");
+
+        let [ship, code, tests, docs, announcement, proofread, celebrate, plain] = indices[..]
+        else {
+            unreachable!()
+        };
+        assert_eq!(
+            sorted(graph.neighbors(ship).unwrap()),
+            vec![code, announcement]
+        );
+        assert_eq!(sorted(graph.neighbors(code).unwrap()), vec![tests, docs]);
+        assert_eq!(graph.neighbors(announcement).unwrap(), vec![proofread]);
+        assert_eq!(graph.neighbors(proofread).unwrap(), vec![docs]);
+        assert_eq!(graph.neighbors(celebrate).unwrap(), Vec::<NodeIndex>::new());
+        assert_eq!(graph.neighbors(plain).unwrap(), Vec::<NodeIndex>::new());
+
+        // Every task is laid out below the one which depends on it, without overlapping.
+        let circles: Vec<_> = indices
+            .iter()
+            .map(|index| graph.get_node(*index).unwrap().circle)
+            .collect();
+        assert!(circles[1].center.y > circles[0].center.y);
+        assert!(circles[5].center.y > circles[4].center.y);
+        for (i, a) in circles.iter().enumerate() {
+            for b in &circles[i + 1..] {
+                assert!(a.center.distance(b.center) >= a.radius + b.radius);
+            }
+        }
+    }
+
+    #[test]
+    fn test_export() {
+        let mut graph = PetgraphGraph::default();
+        let mut add = |title: &str, description: &str| {
+            graph
+                .add_node(Node {
+                    title: title.to_owned(),
+                    description: description.to_owned(),
+                    ..Default::default()
+                })
+                .unwrap()
+        };
+        let ship = add("Ship it", "Before Friday.\n\nReally.");
+        let code = add("Write the code", "");
+        let docs = add("Docs", "");
+        let announcement = add("Write the\nannouncement", "");
+        add("Other", "");
+        graph.add_edge(ship, code).unwrap();
+        graph.add_edge(ship, announcement).unwrap();
+        graph.add_edge(code, docs).unwrap();
+        graph.add_edge(announcement, docs).unwrap();
+        graph.mark_completed(code).unwrap();
+        graph.mark_completed(docs).unwrap();
+        assert_eq!(
+            export(&graph).unwrap(),
+            "- [ ] Ship it
+  Before Friday.
+
+  Really.
+  - [x] Write the code
+    - [x] Docs (#1)
+  - [ ] Write the announcement
+    - [x] Docs (see #1)
+- [ ] Other
+"
+        );
+    }
+
+    fn check_roundtrip(from: &mut impl Graph, to: &mut impl Graph) {
+        let markdown = "- [ ] a (#1)
+  - [x] b
+    - [ ] c (#2)
+  - [ ] d
+    - [ ] c (see #2)
+  - [ ] e
+    - [ ] c (see #2)
+- [ ] f
+  - [ ] a (see #1)
+";
+        import(from, markdown).unwrap();
+        assert_eq!(
+            export(from).unwrap(),
+            "- [ ] f
+  - [ ] a
+    - [x] b
+      - [ ] c (#1)
+    - [ ] d
+      - [ ] c (see #1)
+    - [ ] e
+      - [ ] c (see #1)
+"
+        );
+        import(to, &export(from).unwrap()).unwrap();
+        assert_eq!(export(to).unwrap(), export(from).unwrap());
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        check_roundtrip(&mut PetgraphGraph::default(), &mut PetgraphGraph::default());
+        check_roundtrip(
+            &mut DatabaseGraph::open_in_memory().unwrap(),
+            &mut DatabaseGraph::open_in_memory().unwrap(),
+        );
+    }
+
+    #[test]
+    fn test_roundtrip_label_like_titles() {
+        let mut graph = PetgraphGraph::default();
+        let titles = ["Fix crash (see #12)", "Fix crash (#12)", "Plain"];
+        let indices = titles.map(|title| {
+            graph
+                .add_node(Node {
+                    title: title.to_owned(),
+                    ..Default::default()
+                })
+                .unwrap()
+        });
+        graph.add_edge(indices[0], indices[1]).unwrap();
+        let markdown = export(&graph).unwrap();
+        assert_eq!(
+            markdown,
+            "- [ ] Fix crash (see #12) (#1)
+  - [ ] Fix crash (#12) (#2)
+- [ ] Plain
+"
+        );
+        let mut copy = PetgraphGraph::default();
+        let [crash, reference, plain] = import(&mut copy, &markdown).unwrap()[..] else {
+            panic!("Expected three tasks");
+        };
+        for (index, title) in [crash, reference, plain].into_iter().zip(titles) {
+            assert_eq!(copy.get_node(index).unwrap().title, title);
+        }
+        assert_eq!(copy.neighbors(crash).unwrap(), vec![reference]);
+        assert_eq!(export(&copy).unwrap(), markdown);
+    }
+
+    #[test]
+    fn test_import_rejects_invalid_references() {
+        let invalid = [
+            ("- [ ] a (#1)\n  - [ ] a (see #1)", "cycle: \"a\" -> \"a\""),
+            (
+                "- [ ] a\n  - [ ] b (see #2)",
+                "Line 2: No item is marked (#2)",
+            ),
+            (
+                "- [ ] a (#1)\n- [ ] b (#1)",
+                "Line 2: More than one item is marked (#1)",
+            ),
+        ];
+        for (markdown, message) in invalid {
+            let mut graph = PetgraphGraph::default();
+            let error = import(&mut graph, markdown).unwrap_err().to_string();
+            assert!(
+                error.contains(message),
+                "{} should contain {}",
+                error,
+                message
+            );
+            assert_eq!(graph.node_indices().unwrap(), Vec::<NodeIndex>::new());
+        }
+    }
+}
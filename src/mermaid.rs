@@ -0,0 +1,636 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+
+use anyhow::Context;
+use chrono::Utc;
+
+use crate::graph::{Graph, Node, NodeIndex};
+use crate::import::Import;
+
+/// The class given to completed tasks, in the same colors as the graph viewer uses.
+const COMPLETED_CLASS: &str = "classDef completed fill:#565066,stroke:#807a91,color:#ddd";
+
+/// Writes `graph` as a Mermaid flowchart, with an arrow from each task to each task it depends
+/// on. Completed tasks get the `completed` class.
+///
+/// If `root` is given, only it and the tasks it depends on, directly or not, are written out.
+pub fn export<G: Graph + ?Sized>(graph: &G, root: Option<NodeIndex>) -> anyhow::Result<String> {
+    let snapshot = graph.snapshot()?;
+    let included: Option<HashSet<NodeIndex>> = match root {
+        Some(root) => {
+            graph.get_node(root)?;
+            Some(
+                std::iter::once(root)
+                    .chain(graph.descendants(root)?)
+                    .collect(),
+            )
+        }
+        None => None,
+    };
+    let is_included = |index| {
+        included
+            .as_ref()
+            .is_none_or(|included| included.contains(&index))
+    };
+
+    let mut mermaid = String::from("flowchart TD\n");
+    let mut ids = HashMap::new();
+    let mut any_completed = false;
+    for (index, node) in snapshot.nodes().filter(|(index, _)| is_included(*index)) {
+        let id = format!("t{}", ids.len() + 1);
+        write!(mermaid, "    {}[\"{}\"]", id, escape(&node.title))?;
+        if node.is_completed() {
+            mermaid.push_str(":::completed");
+            any_completed = true;
+        }
+        mermaid.push('\n');
+        ids.insert(index, id);
+    }
+    for (from, to) in snapshot.edges() {
+        if let (Some(from), Some(to)) = (ids.get(from), ids.get(to)) {
+            writeln!(mermaid, "    {} --> {}", from, to)?;
+        }
+    }
+    if any_completed {
+        writeln!(mermaid, "    {}", COMPLETED_CLASS)?;
+    }
+    Ok(mermaid)
+}
+
+/// Writes `text` so that it can go in a quoted label.
+fn escape(text: &str) -> String {
+    let mut escaped = String::new();
+    for c in text.chars() {
+        match c {
+            '"' => escaped.push_str("#quot;"),
+            '#' => escaped.push_str("#35;"),
+            '&' => escaped.push_str("#amp;"),
+            '<' => escaped.push_str("#lt;"),
+            '>' => escaped.push_str("#gt;"),
+            '\n' => escaped.push_str("<br>"),
+            '\r' => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Turns the entities and line breaks in a label back into the text they stand for.
+fn unescape(text: &str) -> String {
+    let mut unescaped = String::new();
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        if c == '#' {
+            if let Some((entity, after)) = rest[1..].split_once(';') {
+                let decoded = match entity {
+                    "quot" => Some('"'),
+                    "amp" => Some('&'),
+                    "lt" => Some('<'),
+                    "gt" => Some('>'),
+                    _ => entity.parse::<u32>().ok().and_then(char::from_u32),
+                };
+                if let Some(decoded) = decoded {
+                    unescaped.push(decoded);
+                    rest = after;
+                    continue;
+                }
+            }
+        } else if c == '<' {
+            let line_break = ["<br>", "<br/>", "<br />"].iter().find(|line_break| {
+                rest.get(..line_break.len())
+                    .is_some_and(|start| start.eq_ignore_ascii_case(line_break))
+            });
+            if let Some(line_break) = line_break {
+                unescaped.push('\n');
+                rest = &rest[line_break.len()..];
+                continue;
+            }
+        }
+        unescaped.push(c);
+        rest = &rest[c.len_utf8()..];
+    }
+    unescaped
+}
+
+/// Adds the nodes and links of a Mermaid flowchart to `graph`, and returns the new tasks in the
+/// order they first appear.
+///
+/// The flowchart may be in a fenced ```` ```mermaid ```` block, as in Markdown. Each node becomes
+/// a task titled with its text, or its ID if it has none, and each link `a --> b` makes `a`
+/// depend on `b`. Nodes with the `completed` class are marked as done. Styles and subgraphs are
+/// ignored, and nothing is added if the links form a cycle.
+pub fn import<G: Graph>(graph: &mut G, mermaid: &str) -> anyhow::Result<Vec<NodeIndex>> {
+    parse(mermaid)?.apply(graph)
+}
+
+/// Reads a Mermaid flowchart, without adding it to a graph yet.
+pub fn parse(mermaid: &str) -> anyhow::Result<Import> {
+    let mut lines = mermaid.lines().enumerate();
+    // Only the contents of a fenced block are read, if there is one.
+    let fenced = mermaid
+        .lines()
+        .any(|line| line.trim().starts_with("```mermaid"));
+    if fenced {
+        lines
+            .by_ref()
+            .find(|(_, line)| line.trim().starts_with("```mermaid"));
+    }
+
+    let mut flowchart = Flowchart::default();
+    let mut header = false;
+    for (number, line) in lines {
+        let line = line.trim();
+        if fenced && line.starts_with("```") {
+            break;
+        }
+        if line.is_empty() || line.starts_with("%%") {
+            continue;
+        }
+        if header {
+            flowchart
+                .statements(line)
+                .with_context(|| format!("Line {}", number + 1))?;
+        } else {
+            let keyword = line.split_whitespace().next().unwrap_or_default();
+            if keyword != "flowchart" && keyword != "graph" {
+                anyhow::bail!("Line {}: Only flowcharts can be imported", number + 1);
+            }
+            header = true;
+        }
+    }
+    if !header {
+        anyhow::bail!("Missing a flowchart");
+    }
+
+    let mut import = Import::default();
+    for node in flowchart.nodes {
+        import.add_node(
+            Node {
+                title: node.text.unwrap_or(node.id),
+                completed_at: node.completed.then(Utc::now),
+                ..Default::default()
+            },
+            None,
+        );
+    }
+    for (from, to) in flowchart.edges {
+        import.add_edge(from, to);
+    }
+    Ok(import)
+}
+
+/// Keywords which start statements that don't say anything about the tasks.
+const IGNORED_KEYWORDS: [&str; 10] = [
+    "accDescr",
+    "accTitle",
+    "classDef",
+    "click",
+    "direction",
+    "end",
+    "linkStyle",
+    "style",
+    "subgraph",
+    "title",
+];
+
+/// Node shapes, longest first so that e.g. `((` isn't read as `(`.
+const SHAPES: [(&str, &[&str]); 12] = [
+    ("(((", &[")))"]),
+    ("[[", &["]]"]),
+    ("[(", &[")]"]),
+    ("([", &["])"]),
+    ("((", &["))"]),
+    ("{{", &["}}"]),
+    ("[/", &["/]", "\\]"]),
+    ("[\\", &["\\]", "/]"]),
+    ("[", &["]"]),
+    ("(", &[")"]),
+    ("{", &["}"]),
+    (">", &["]"]),
+];
+
+struct FlowchartNode {
+    id: String,
+    text: Option<String>,
+    completed: bool,
+}
+
+#[derive(Default)]
+struct Flowchart {
+    nodes: Vec<FlowchartNode>,
+    ids: HashMap<String, usize>,
+    edges: Vec<(usize, usize)>,
+}
+
+impl Flowchart {
+    fn node(&mut self, id: &str) -> usize {
+        if let Some(&index) = self.ids.get(id) {
+            return index;
+        }
+        self.nodes.push(FlowchartNode {
+            id: id.to_owned(),
+            text: None,
+            completed: false,
+        });
+        self.ids.insert(id.to_owned(), self.nodes.len() - 1);
+        self.nodes.len() - 1
+    }
+
+    /// Reads a line of statements separated by semicolons.
+    fn statements(&mut self, line: &str) -> anyhow::Result<()> {
+        let keyword = line
+            .split(|c: char| c.is_whitespace() || c == ';')
+            .next()
+            .unwrap_or_default();
+        if IGNORED_KEYWORDS.contains(&keyword) {
+            return Ok(());
+        }
+        if keyword == "class" {
+            // `class a,b completed`
+            let mut words = line.trim_end_matches(';').split_whitespace().skip(1);
+            let ids = words.next().context("Missing the nodes to give a class")?;
+            let class = words.next().context("Missing the class to give")?;
+            for id in ids.split(',') {
+                let index = self.node(id);
+                self.nodes[index].completed |= class == "completed";
+            }
+            return Ok(());
+        }
+
+        let mut cursor = Cursor { rest: line };
+        loop {
+            self.statement(&mut cursor)?;
+            cursor.skip_whitespace();
+            if cursor.rest.is_empty() {
+                return Ok(());
+            }
+            if !cursor.eat(";") {
+                anyhow::bail!("Unexpected {:?}", cursor.rest);
+            }
+            cursor.skip_whitespace();
+            if cursor.rest.is_empty() {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Reads groups of nodes joined by links, like `a & b --> c`.
+    fn statement(&mut self, cursor: &mut Cursor) -> anyhow::Result<()> {
+        let mut group = self.group(cursor)?;
+        while let Some(bidirectional) = cursor.link()? {
+            let next = self.group(cursor)?;
+            for &from in &group {
+                for &to in &next {
+                    self.edges.push((from, to));
+                    if bidirectional {
+                        self.edges.push((to, from));
+                    }
+                }
+            }
+            group = next;
+        }
+        Ok(())
+    }
+
+    fn group(&mut self, cursor: &mut Cursor) -> anyhow::Result<Vec<usize>> {
+        let mut group = vec![self.node_statement(cursor)?];
+        loop {
+            cursor.skip_whitespace();
+            if !cursor.eat("&") {
+                return Ok(group);
+            }
+            group.push(self.node_statement(cursor)?);
+        }
+    }
+
+    /// Reads a node with its text and class, if it has them, like `a["Text"]:::completed`.
+    fn node_statement(&mut self, cursor: &mut Cursor) -> anyhow::Result<usize> {
+        cursor.skip_whitespace();
+        let id = cursor.id().context("Expected a node")?;
+        let index = self.node(id);
+        if let Some(&(open, closes)) = SHAPES
+            .iter()
+            .find(|(open, _)| cursor.rest.starts_with(open))
+        {
+            cursor.eat(open);
+            let text = if cursor.eat("\"") {
+                let (text, rest) = cursor
+                    .rest
+                    .split_once('"')
+                    .with_context(|| format!("Unterminated text for {}", id))?;
+                cursor.rest = rest;
+                text
+            } else {
+                let end = closes
+                    .iter()
+                    .filter_map(|close| cursor.rest.find(close))
+                    .min()
+                    .with_context(|| format!("Unterminated text for {}", id))?;
+                let text = &cursor.rest[..end];
+                cursor.rest = &cursor.rest[end..];
+                text.trim()
+            };
+            if !closes.iter().any(|close| cursor.eat(close)) {
+                anyhow::bail!("Expected {} after the text for {}", closes[0], id);
+            }
+            self.nodes[index].text = Some(unescape(text));
+        }
+        if cursor.eat(":::") {
+            let class = cursor.id().context("Expected a class after :::")?;
+            self.nodes[index].completed |= class == "completed";
+        }
+        Ok(index)
+    }
+}
+
+struct Cursor<'a> {
+    rest: &'a str,
+}
+
+impl<'a> Cursor<'a> {
+    fn skip_whitespace(&mut self) {
+        self.rest = self.rest.trim_start();
+    }
+
+    fn eat(&mut self, prefix: &str) -> bool {
+        match self.rest.strip_prefix(prefix) {
+            Some(rest) => {
+                self.rest = rest;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Reads a node ID. Dashes are allowed in the middle of IDs, but not where they'd be a link.
+    fn id(&mut self) -> Option<&'a str> {
+        let mut chars = self.rest.char_indices().peekable();
+        let mut end = 0;
+        while let Some((i, c)) = chars.next() {
+            let next_is_id = chars
+                .peek()
+                .is_some_and(|&(_, next)| next.is_alphanumeric() || next == '_');
+            if !(c.is_alphanumeric() || c == '_' || (c == '-' && i > 0 && next_is_id)) {
+                break;
+            }
+            end = i + c.len_utf8();
+        }
+        if end == 0 {
+            return None;
+        }
+        let id = &self.rest[..end];
+        self.rest = &self.rest[end..];
+        Some(id)
+    }
+
+    /// Reads a link like `-->`, `-.->`, `==>|text|` or `-- text -->`, if there is one,
+    /// and returns whether it points both ways.
+    fn link(&mut self) -> anyhow::Result<Option<bool>> {
+        self.skip_whitespace();
+        let start = self.rest;
+        let bidirectional = self.eat("<");
+        let line = self.rest.chars().next();
+        let length = self
+            .rest
+            .find(|c| Some(c) != line)
+            .unwrap_or(self.rest.len());
+        match line {
+            Some('~') if length >= 3 => self.rest = &self.rest[length..],
+            Some('-') if length == 1 && self.rest[1..].starts_with('.') => {
+                // Dotted links: `-.->`, or `-. text .->`
+                let dots = self.rest[1..]
+                    .find(|c| c != '.')
+                    .unwrap_or(self.rest.len() - 1);
+                if self.rest[1 + dots..].starts_with('-') {
+                    self.rest = &self.rest[2 + dots..];
+                } else {
+                    let end = self
+                        .rest
+                        .find(".-")
+                        .filter(|&end| end > 1)
+                        .context("Unterminated link text")?;
+                    self.rest = &self.rest[end + 2..];
+                }
+            }
+            Some('-' | '=') if length >= 2 => {
+                let line = line.unwrap();
+                let after = &self.rest[length..];
+                if length == 2 && after.starts_with(char::is_whitespace) {
+                    // Links with text in the middle: `-- text -->`
+                    let head = format!("{}{}", line, line);
+                    let end = after.find(&head).context("Unterminated link text")?;
+                    let after_text = &after[end..];
+                    let length = after_text.find(|c| c != line).unwrap_or(after_text.len());
+                    self.rest = &after_text[length..];
+                } else {
+                    self.rest = after;
+                }
+            }
+            _ => {
+                self.rest = start;
+                return Ok(None);
+            }
+        }
+        // Arrow heads, unless an `o` or `x` is really the start of a node.
+        if !self.eat(">") {
+            let mut chars = self.rest.chars();
+            if matches!(chars.next(), Some('o' | 'x'))
+                && chars.next().is_none_or(|c| c.is_whitespace() || c == '|')
+            {
+                self.rest = &self.rest[1..];
+            }
+        }
+        self.skip_whitespace();
+        if self.eat("|") {
+            let end = self.rest.find('|').context("Unterminated link text")?;
+            self.rest = &self.rest[end + 1..];
+        }
+        Ok(Some(bidirectional))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graph::{DatabaseGraph, PetgraphGraph};
+    use crate::import::test_helpers::sorted;
+
+    fn fill(graph: &mut impl Graph) -> [NodeIndex; 4] {
+        let mut add = |title: &str| {
+            graph
+                .add_node(Node {
+                    title: title.to_owned(),
+                    ..Default::default()
+                })
+                .unwrap()
+        };
+        let [report, data, coffee, other] = [
+            "Write \"the\" report",
+            "Gather data\n#2 <b>",
+            "Coffee",
+            "Other",
+        ]
+        .map(&mut add);
+        graph.add_edge(report, data).unwrap();
+        graph.add_edge(report, coffee).unwrap();
+        graph.add_edge(data, coffee).unwrap();
+        graph.mark_completed(coffee).unwrap();
+        [report, data, coffee, other]
+    }
+
+    #[test]
+    fn test_export() {
+        let mut graph = PetgraphGraph::default();
+        let [report, data, coffee, _] = fill(&mut graph);
+        assert_eq!(
+            export(&graph, None).unwrap(),
+            "flowchart TD
+    t1[\"Write #quot;the#quot; report\"]
+    t2[\"Gather data<br>#35;2 #lt;b#gt;\"]
+    t3[\"Coffee\"]:::completed
+    t4[\"Other\"]
+    t1 --> t2
+    t1 --> t3
+    t2 --> t3
+    classDef completed fill:#565066,stroke:#807a91,color:#ddd
+"
+        );
+        assert_eq!(
+            export(&graph, Some(data)).unwrap(),
+            "flowchart TD
+    t1[\"Gather data<br>#35;2 #lt;b#gt;\"]
+    t2[\"Coffee\"]:::completed
+    t1 --> t2
+    classDef completed fill:#565066,stroke:#807a91,color:#ddd
+"
+        );
+        graph.mark_incomplete(coffee).unwrap();
+        graph.remove_node(data).unwrap();
+        assert_eq!(
+            export(&graph, Some(report)).unwrap(),
+            "flowchart TD
+    t1[\"Write #quot;the#quot; report\"]
+    t2[\"Coffee\"]
+    t1 --> t2
+"
+        );
+    }
+
+    fn check_roundtrip(from: &mut impl Graph, to: &mut impl Graph) {
+        let old = fill(from);
+        let new = import(to, &export(from, None).unwrap()).unwrap();
+        assert_eq!(new.len(), old.len());
+        for (old, new) in old.iter().zip(&new) {
+            let (old, new) = (from.get_node(*old).unwrap(), to.get_node(*new).unwrap());
+            assert_eq!(new.title, old.title);
+            assert_eq!(new.is_completed(), old.is_completed());
+        }
+        assert_eq!(sorted(to.neighbors(new[0]).unwrap()), vec![new[1], new[2]]);
+        assert_eq!(to.neighbors(new[1]).unwrap(), vec![new[2]]);
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        check_roundtrip(&mut PetgraphGraph::default(), &mut PetgraphGraph::default());
+        check_roundtrip(
+            &mut DatabaseGraph::open_in_memory().unwrap(),
+            &mut DatabaseGraph::open_in_memory().unwrap(),
+        );
+    }
+
+    #[test]
+    fn test_import() {
+        let markdown = "# Plan
+
+```mermaid
+%% The plan
+graph LR
+    start([Start]) --> build-app{{Build the app}} -.-> lint & test
+    test -- only on CI --> deploy>Deploy] ==>|later| done((Done))
+    lint --- a; a --o b
+    deploy --x c
+    b[\"Text with #quot;quotes#quot;<br/>and lines\"]:::completed
+    subgraph one [Checks]
+        d ~~~ e
+    end
+    classDef completed fill:#fff
+    class c,e completed
+```
+
+Not part of it --> at all
+";
+        let mut graph = PetgraphGraph::default();
+        let indices = import(&mut graph, markdown).unwrap();
+        let nodes: Vec<Node> = indices
+            .iter()
+            .map(|index| graph.get_node(*index).unwrap())
+            .collect();
+        let titles: Vec<&str> = nodes.iter().map(|node| node.title.as_str()).collect();
+        assert_eq!(
+            titles,
+            [
+                "Start",
+                "Build the app",
+                "lint",
+                "test",
+                "Deploy",
+                "Done",
+                "a",
+                "Text with \"quotes\"\nand lines",
+                "c",
+                "d",
+                "e",
+            ]
+        );
+        let completed: Vec<&str> = nodes
+            .iter()
+            .filter(|node| node.is_completed())
+            .map(|node| node.title.as_str())
+            .collect();
+        assert_eq!(completed, ["Text with \"quotes\"\nand lines", "c", "e"]);
+        let [start, build, lint, test, deploy, done, a, b, c, d, e] = indices[..] else {
+            unreachable!()
+        };
+        assert_eq!(graph.neighbors(start).unwrap(), vec![build]);
+        assert_eq!(sorted(graph.neighbors(build).unwrap()), vec![lint, test]);
+        assert_eq!(graph.neighbors(test).unwrap(), vec![deploy]);
+        assert_eq!(sorted(graph.neighbors(deploy).unwrap()), vec![done, c]);
+        assert_eq!(graph.neighbors(lint).unwrap(), vec![a]);
+        assert_eq!(graph.neighbors(a).unwrap(), vec![b]);
+        assert_eq!(graph.neighbors(d).unwrap(), vec![e]);
+    }
+
+    #[test]
+    fn test_import_rejects_invalid_flowcharts() {
+        let invalid = [
+            (
+                "flowchart TD\n  a --> b --> a",
+                "cycle: \"a\" -> \"b\" -> \"a\"",
+            ),
+            ("flowchart TD\n  a <--> b", "cycle: \"a\" -> \"b\" -> \"a\""),
+            ("sequenceDiagram\n  a->>b: hi", "Line 1: Only flowcharts"),
+            ("", "Missing a flowchart"),
+            (
+                "flowchart TD\n  a[\"text] --> b",
+                "Line 2: Unterminated text for a",
+            ),
+            ("flowchart TD\n  a --> ", "Line 2: Expected a node"),
+            (
+                "flowchart TD\n  a -- text",
+                "Line 2: Unterminated link text",
+            ),
+            ("flowchart TD\n  a b", "Line 2: Unexpected \"b\""),
+        ];
+        for (mermaid, message) in invalid {
+            let mut graph = PetgraphGraph::default();
+            let error = format!("{:#}", import(&mut graph, mermaid).unwrap_err());
+            assert!(
+                error.contains(message),
+                "{} should contain {}",
+                error,
+                message
+            );
+            assert_eq!(graph.node_indices().unwrap(), Vec::<NodeIndex>::new());
+        }
+    }
+}
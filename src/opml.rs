@@ -0,0 +1,339 @@
+use std::fmt::Write;
+
+use anyhow::Context;
+use chrono::Utc;
+use quick_xml::escape::escape;
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+
+use crate::graph::{Graph, Node, NodeIndex};
+use crate::import::Import;
+use crate::outline::{self, OutlineReader};
+
+/// Outline attributes which mean something already, so metadata can't be written as them.
+const RESERVED: [&str; 10] = [
+    "text",
+    "title",
+    "type",
+    "_note",
+    "_complete",
+    "_status",
+    "id",
+    "blocker",
+    "isComment",
+    "isBreakpoint",
+];
+
+/// Writes every task in `graph` as an OPML outline, with the tasks each task depends on
+/// nested under it.
+///
+/// Titles are the `text` of each outline, descriptions are its `_note`, and done tasks are
+/// marked `_complete`, as outliners like OmniOutliner do. Metadata becomes attributes of its own.
+/// A task which more than one task depends on is nested under the first one, with an `id`,
+/// and the others list it in their `blocker` attribute.
+pub fn export<G: Graph + ?Sized>(graph: &G) -> anyhow::Result<String> {
+    let snapshot = graph.snapshot()?;
+    let entries = outline::entries(&snapshot);
+    let mut opml = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <opml version=\"2.0\">\n  \
+         <head>\n    <title>ekad</title>\n  </head>\n  \
+         <body>\n",
+    );
+    for (position, entry) in entries.iter().enumerate() {
+        let node = entry.node;
+        let indent = "  ".repeat(entry.depth + 2);
+        write!(
+            opml,
+            "{}<outline text=\"{}\"",
+            indent,
+            attribute(&node.title)
+        )?;
+        if !node.description.is_empty() {
+            write!(opml, " _note=\"{}\"", attribute(&node.description))?;
+        }
+        if node.is_completed() {
+            opml.push_str(" _complete=\"true\"");
+        }
+        if let Some(id) = &entry.id {
+            write!(opml, " id=\"{}\"", attribute(id))?;
+        }
+        if !entry.blockers.is_empty() {
+            write!(
+                opml,
+                " blocker=\"{}\"",
+                attribute(&entry.blockers.join(" "))
+            )?;
+        }
+        for (key, value) in &node.metadata {
+            if is_attribute_name(key) {
+                write!(opml, " {}=\"{}\"", key, attribute(value))?;
+            }
+        }
+
+        let next_depth = entries.get(position + 1).map_or(0, |next| next.depth);
+        if next_depth > entry.depth {
+            opml.push_str(">\n");
+        } else {
+            opml.push_str("/>\n");
+            for depth in (next_depth..entry.depth).rev() {
+                writeln!(opml, "{}</outline>", "  ".repeat(depth + 2))?;
+            }
+        }
+    }
+    opml.push_str("  </body>\n</opml>\n");
+    Ok(opml)
+}
+
+/// Escapes `value` to go in double quotes, keeping line breaks, which XML would otherwise
+/// turn into spaces.
+fn attribute(value: &str) -> String {
+    escape(value)
+        .replace('\r', "&#13;")
+        .replace('\n', "&#10;")
+        .replace('\t', "&#9;")
+}
+
+/// Adds the outlines in an OPML file to `graph`, and returns the new tasks in order.
+///
+/// Every outline becomes a task which depends on the outlines nested in it, and on the
+/// outlines its `blocker` attribute lists by `id`. Outlines marked `_complete` are marked as
+/// done, `_note` becomes the description, and any other attributes become metadata.
+pub fn import<G: Graph>(graph: &mut G, opml: &str) -> anyhow::Result<Vec<NodeIndex>> {
+    parse(opml)?.apply(graph)
+}
+
+/// Reads the outlines in an OPML file, without adding them to a graph yet.
+pub fn parse(opml: &str) -> anyhow::Result<Import> {
+    let mut reader = Reader::from_str(opml);
+    let mut outlines = OutlineReader::default();
+    let mut elements: Vec<Vec<u8>> = vec![];
+    let mut found = false;
+    // How many outlines the next one is nested in.
+    let mut depth = 0;
+    loop {
+        let position = reader.buffer_position();
+        let event = reader
+            .read_event()
+            .with_context(|| format!("Invalid OPML at byte {}", position))?;
+        match event {
+            Event::Start(start) => {
+                let name = start.name().as_ref().to_vec();
+                check_nesting(&elements, &name)?;
+                found = true;
+                if is_outline(&elements, &name) {
+                    outline(&mut outlines, depth, &start)?;
+                    depth += 1;
+                }
+                elements.push(name);
+            }
+            Event::Empty(start) => {
+                let name = start.name().as_ref().to_vec();
+                check_nesting(&elements, &name)?;
+                if is_outline(&elements, &name) {
+                    outline(&mut outlines, depth, &start)?;
+                }
+            }
+            Event::End(end) => {
+                elements.pop();
+                if end.name().as_ref() == b"outline" && elements.contains(&b"body".to_vec()) {
+                    depth -= 1;
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+    if found && elements.is_empty() {
+        Ok(outlines.finish())
+    } else {
+        anyhow::bail!("Not an OPML document")
+    }
+}
+
+/// Makes sure the document is an OPML one, which everything else is inside of.
+fn check_nesting(elements: &[Vec<u8>], name: &[u8]) -> anyhow::Result<()> {
+    if elements.is_empty() && name != b"opml" {
+        anyhow::bail!("Not an OPML document");
+    }
+    Ok(())
+}
+
+fn is_outline(elements: &[Vec<u8>], name: &[u8]) -> bool {
+    name == b"outline" && elements.iter().any(|element| element == b"body")
+}
+
+/// Adds the task an outline element stands for.
+fn outline(outlines: &mut OutlineReader, depth: usize, start: &BytesStart) -> anyhow::Result<()> {
+    let mut node = Node::default();
+    let mut title = None;
+    let mut id = None;
+    let mut blockers = vec![];
+    for attribute in start.attributes() {
+        let attribute = attribute.context("Invalid OPML attribute")?;
+        let key = std::str::from_utf8(attribute.key.as_ref())?.to_owned();
+        let value = attribute
+            .unescape_value()
+            .with_context(|| format!("Invalid OPML attribute {:?}", key))?
+            .into_owned();
+        match key.as_str() {
+            "text" => node.title = value,
+            "title" => title = Some(value),
+            "_note" => node.description = value,
+            "_complete" if value == "true" => {
+                node.completed_at = Some(Utc::now());
+            }
+            "_status" if value == "checked" => {
+                node.completed_at = Some(Utc::now());
+            }
+            "id" => id = Some(value),
+            "blocker" => blockers = value.split_whitespace().map(str::to_owned).collect(),
+            key if RESERVED.contains(&key) => {}
+            _ => {
+                node.metadata.insert(key, value);
+            }
+        }
+    }
+    // Some outlines only have a title, which is meant to be the same thing.
+    if node.title.is_empty() {
+        node.title = title.unwrap_or_default();
+    }
+    outlines.add(depth, node, id, blockers)
+}
+
+fn is_attribute_name(key: &str) -> bool {
+    !RESERVED.contains(&key)
+        && key.starts_with(|c: char| c.is_alphabetic() || c == '_')
+        && key
+            .chars()
+            .all(|c| c.is_alphanumeric() || "_-.".contains(c))
+        && !key.to_ascii_lowercase().starts_with("xml")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graph::{DatabaseGraph, PetgraphGraph};
+    use crate::import::test_helpers::{self, sorted, task};
+
+    #[test]
+    fn test_export() {
+        let mut graph = PetgraphGraph::default();
+        let root = graph
+            .add_node(Node {
+                description: "Line 1\nLine 2".to_owned(),
+                ..task("Fish & \"chips\"", &[("due", "2024-07-05T00:00:00Z")])
+            })
+            .unwrap();
+        let a = graph
+            .add_node(Node {
+                completed_at: Some(Utc::now()),
+                ..task("a", &[("has space", "dropped")])
+            })
+            .unwrap();
+        let shared = graph.add_node(task("shared", &[])).unwrap();
+        graph.add_node(task("other", &[])).unwrap();
+        graph.add_edge(root, a).unwrap();
+        graph.add_edge(a, shared).unwrap();
+        graph.add_edge(root, shared).unwrap();
+
+        assert_eq!(
+            export(&graph).unwrap(),
+            format!(
+                "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+                 <opml version=\"2.0\">\n\
+                 \x20 <head>\n\
+                 \x20   <title>ekad</title>\n\
+                 \x20 </head>\n\
+                 \x20 <body>\n\
+                 \x20   <outline text=\"Fish &amp; &quot;chips&quot;\" \
+                 _note=\"Line 1&#10;Line 2\" blocker=\"{shared}\" due=\"2024-07-05T00:00:00Z\">\n\
+                 \x20     <outline text=\"a\" _complete=\"true\">\n\
+                 \x20       <outline text=\"shared\" id=\"{shared}\"/>\n\
+                 \x20     </outline>\n\
+                 \x20   </outline>\n\
+                 \x20   <outline text=\"other\"/>\n\
+                 \x20 </body>\n\
+                 </opml>\n"
+            )
+        );
+    }
+
+    fn roundtrip<G: Graph>(graph: G) {
+        let root = Node {
+            description: "First line\n\n\tindented <b>".to_owned(),
+            ..task("", &[("due", "2024-07-05T12:15:00+00:00"), ("tags", "a b")])
+        };
+        for (from, to) in test_helpers::roundtrip(graph, root, export, import) {
+            assert_eq!(to.title, from.title);
+            assert_eq!(to.description, from.description);
+            // OPML only says whether a task is complete, not when.
+            assert_eq!(to.is_completed(), from.is_completed());
+            assert_eq!(to.metadata, from.metadata);
+        }
+    }
+
+    #[test]
+    fn test_roundtrip_petgraph() {
+        roundtrip(PetgraphGraph::default());
+    }
+
+    #[test]
+    fn test_roundtrip_database() {
+        roundtrip(DatabaseGraph::open_in_memory().unwrap());
+    }
+
+    #[test]
+    fn test_import() {
+        let opml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<!-- Written by hand -->
+<opml version="1.0">
+  <head><title>Plans</title></head>
+  <body>
+    <outline text="Project" type="checklist">
+      <outline title="Buy paint" _status="checked"/>
+      <outline text="Paint the fence" _note="Two coats." effort="2h" blocker="sand"/>
+    </outline>
+    <outline text="Errands">
+      <outline text="Sand it" id="sand"></outline>
+    </outline>
+  </body>
+</opml>
+"#;
+        let mut graph = PetgraphGraph::default();
+        let [project, buy, fence, errands, sand] = import(&mut graph, opml).unwrap()[..] else {
+            panic!();
+        };
+        assert_eq!(
+            sorted(graph.neighbors(project).unwrap()),
+            sorted(vec![buy, fence])
+        );
+        assert_eq!(graph.neighbors(fence).unwrap(), vec![sand]);
+        assert_eq!(graph.neighbors(errands).unwrap(), vec![sand]);
+        let project = graph.get_node(project).unwrap();
+        assert!(project.metadata.is_empty());
+        let buy = graph.get_node(buy).unwrap();
+        assert_eq!(buy.title, "Buy paint");
+        assert!(buy.is_completed());
+        let fence = graph.get_node(fence).unwrap();
+        assert_eq!(fence.description, "Two coats.");
+        assert_eq!(fence.metadata["effort"], "2h");
+        assert!(!graph.get_node(sand).unwrap().is_completed());
+    }
+
+    #[test]
+    fn test_invalid() {
+        for opml in [
+            "",
+            "<html><body></body></html>",
+            "<opml><body><outline text=\"a\">",
+        ] {
+            let error = parse(opml).err().unwrap();
+            assert_eq!(error.to_string(), "Not an OPML document", "{:?}", opml);
+        }
+        let error = parse("<opml><body><outline text=\"a></body></opml>")
+            .err()
+            .unwrap();
+        assert!(error.to_string().starts_with("Invalid OPML"), "{}", error);
+    }
+}
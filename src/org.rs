@@ -0,0 +1,510 @@
+use std::fmt::Write;
+
+use anyhow::Context;
+use chrono::{DateTime, Local, NaiveDate, NaiveTime, Timelike, Utc};
+
+use crate::graph::{from_local_time, Graph, MetadataDate, Node, NodeIndex};
+use crate::import::Import;
+use crate::outline::{self, OutlineReader};
+
+/// Metadata which is written in the headline or planning line, rather than as a property.
+const PLANNING_KEYS: [&str; 4] = ["due", "scheduled", "tags", "priority"];
+
+/// Writes every task in `graph` as Org-mode headings, with the tasks each task depends on as
+/// subheadings, and descriptions as the text under each heading.
+///
+/// Tasks are `TODO` or `DONE`, with the time they were done as `CLOSED`. The `scheduled` and
+/// `due` metadata become `SCHEDULED` and `DEADLINE`, `priority` becomes a cookie like `[#A]`,
+/// `tags` become headline tags, and any other metadata becomes a property. A task which more
+/// than one task depends on is written under the first one, with an `ID` property, and the
+/// others list it in a `BLOCKER` property, as org-depend does.
+pub fn export<G: Graph + ?Sized>(graph: &G) -> anyhow::Result<String> {
+    let snapshot = graph.snapshot()?;
+    let mut org = String::new();
+    for entry in outline::entries(&snapshot) {
+        let node = entry.node;
+        let keyword = if node.is_completed() { "DONE" } else { "TODO" };
+        write!(org, "{} {}", "*".repeat(entry.depth + 1), keyword)?;
+        if let Some(priority) = node.metadata.get("priority") {
+            write!(org, " [#{}]", priority)?;
+        }
+        // Titles have to fit on the line.
+        for word in node.title.split_whitespace() {
+            write!(org, " {}", word)?;
+        }
+        if let Some(tags) = node.metadata.get("tags") {
+            let tags: Vec<&str> = tags.split_whitespace().collect();
+            if !tags.is_empty() {
+                write!(org, " :{}:", tags.join(":"))?;
+            }
+        }
+        org.push('\n');
+
+        let mut planning = vec![];
+        if let Some(completed_at) = node.completed_at {
+            planning.push(format!("CLOSED: [{}]", timestamp(completed_at)));
+        }
+        for (key, word) in [("scheduled", "SCHEDULED"), ("due", "DEADLINE")] {
+            if let Some(date) = node.metadata.get(key) {
+                let date = MetadataDate::parse(date)
+                    .with_context(|| format!("Invalid {} date {:?}", key, date))?;
+                let date = match date {
+                    MetadataDate::Day(day) => day.format("%Y-%m-%d %a").to_string(),
+                    MetadataDate::Time(time) => timestamp(time.to_utc()),
+                };
+                planning.push(format!("{}: <{}>", word, date));
+            }
+        }
+        if !planning.is_empty() {
+            writeln!(org, "{}", planning.join(" "))?;
+        }
+
+        let mut properties = vec![];
+        if let Some(id) = &entry.id {
+            properties.push(("ID", id.clone()));
+        }
+        if !entry.blockers.is_empty() {
+            properties.push(("BLOCKER", entry.blockers.join(" ")));
+        }
+        for (key, value) in &node.metadata {
+            // Property values can only be one line.
+            if !PLANNING_KEYS.contains(&key.as_str()) && is_property_name(key) {
+                properties.push((key, value.lines().collect::<Vec<_>>().join(" ")));
+            }
+        }
+        if !properties.is_empty() {
+            org.push_str(":PROPERTIES:\n");
+            for (key, value) in properties {
+                writeln!(org, ":{}: {}", key, value)?;
+            }
+            org.push_str(":END:\n");
+        }
+
+        for line in node.description.lines() {
+            // A line starting with stars would be a heading of its own.
+            if line.starts_with('*') {
+                org.push(' ');
+            }
+            writeln!(org, "{}", line)?;
+        }
+    }
+    Ok(org)
+}
+
+/// Adds the headings in an Org-mode file to `graph`, and returns the new tasks in order.
+///
+/// Every heading becomes a task which depends on its subheadings and on the headings its
+/// `BLOCKER` property lists by `ID`. `DONE` headings are marked as done, and the text under
+/// each heading becomes its description. `SCHEDULED` and `DEADLINE` become the `scheduled` and
+/// `due` metadata, along with the priority, tags and other properties. Text before the first
+/// heading is skipped.
+pub fn import<G: Graph>(graph: &mut G, org: &str) -> anyhow::Result<Vec<NodeIndex>> {
+    parse(org)?.apply(graph)
+}
+
+/// A heading which is still being read.
+struct Heading {
+    level: usize,
+    node: Node,
+    id: Option<String>,
+    blockers: Vec<String>,
+    done: bool,
+    /// Whether the lines so far could still be the planning line or property drawer.
+    in_header: bool,
+    in_properties: bool,
+    description: Vec<String>,
+}
+
+/// Reads the headings in an Org-mode file, without adding them to a graph yet.
+pub fn parse(org: &str) -> anyhow::Result<Import> {
+    let mut reader = OutlineReader::default();
+    let mut heading: Option<Heading> = None;
+    for (number, line) in org.lines().enumerate() {
+        if let Some(next) = self::heading(line) {
+            if let Some(heading) = heading.replace(next) {
+                finish(&mut reader, heading)?;
+            }
+            continue;
+        }
+        let Some(heading) = &mut heading else {
+            continue;
+        };
+        body_line(heading, line).with_context(|| format!("Line {}", number + 1))?;
+    }
+    if let Some(heading) = heading {
+        finish(&mut reader, heading)?;
+    }
+    Ok(reader.finish())
+}
+
+/// Reads a headline like `** TODO [#A] Title :tag:`, if `line` is one.
+fn heading(line: &str) -> Option<Heading> {
+    let level = line.len() - line.trim_start_matches('*').len();
+    let rest = &line[level..];
+    if level == 0 || !(rest.is_empty() || rest.starts_with([' ', '\t'])) {
+        return None;
+    }
+    let mut words: Vec<&str> = rest.split_whitespace().collect();
+    let mut node = Node::default();
+    let mut done = false;
+    if let Some(&keyword) = words.first() {
+        if keyword == "TODO" || keyword == "DONE" {
+            done = keyword == "DONE";
+            words.remove(0);
+        }
+    }
+    if let Some(priority) = words
+        .first()
+        .and_then(|word| word.strip_prefix("[#")?.strip_suffix(']'))
+    {
+        node.metadata
+            .insert("priority".to_owned(), priority.to_owned());
+        words.remove(0);
+    }
+    if let Some(tags) = words
+        .last()
+        .and_then(|word| word.strip_prefix(':')?.strip_suffix(':'))
+    {
+        let tags: Vec<&str> = tags.split(':').collect();
+        if tags.iter().all(|tag| is_tag(tag)) {
+            node.metadata.insert("tags".to_owned(), tags.join(" "));
+            words.pop();
+        }
+    }
+    node.title = words.join(" ");
+    Some(Heading {
+        level,
+        node,
+        id: None,
+        blockers: vec![],
+        done,
+        in_header: true,
+        in_properties: false,
+        description: vec![],
+    })
+}
+
+fn body_line(heading: &mut Heading, line: &str) -> anyhow::Result<()> {
+    let text = line.trim();
+    if heading.in_properties {
+        if text.eq_ignore_ascii_case(":END:") {
+            heading.in_properties = false;
+            heading.in_header = false;
+        } else if let Some((key, value)) =
+            text.strip_prefix(':').and_then(|text| text.split_once(':'))
+        {
+            let value = value.trim();
+            if key.eq_ignore_ascii_case("ID") {
+                heading.id = Some(value.to_owned());
+            } else if key.eq_ignore_ascii_case("BLOCKER") {
+                heading.blockers = value.split_whitespace().map(str::to_owned).collect();
+            } else {
+                heading
+                    .node
+                    .metadata
+                    .insert(key.to_owned(), value.to_owned());
+            }
+        }
+        return Ok(());
+    }
+    if heading.in_header {
+        if text.eq_ignore_ascii_case(":PROPERTIES:") {
+            heading.in_properties = true;
+            return Ok(());
+        }
+        let planning = planning(text)?;
+        if !planning.is_empty() {
+            for (word, date) in planning {
+                match word {
+                    "CLOSED" => heading.node.completed_at = Some(date),
+                    "SCHEDULED" => {
+                        heading
+                            .node
+                            .metadata
+                            .insert("scheduled".to_owned(), date.to_rfc3339());
+                    }
+                    _ => {
+                        heading
+                            .node
+                            .metadata
+                            .insert("due".to_owned(), date.to_rfc3339());
+                    }
+                }
+            }
+            return Ok(());
+        }
+        heading.in_header = false;
+    }
+    // Undo what `export` does to lines which would be headings.
+    let line = match line.strip_prefix(' ') {
+        Some(rest) if rest.starts_with('*') => rest,
+        _ => line,
+    };
+    heading.description.push(line.trim_end().to_owned());
+    Ok(())
+}
+
+fn finish(reader: &mut OutlineReader, heading: Heading) -> anyhow::Result<()> {
+    let mut node = heading.node;
+    if heading.done {
+        node.completed_at.get_or_insert_with(Utc::now);
+    } else {
+        node.completed_at = None;
+    }
+    let lines = &heading.description;
+    let start = lines.iter().position(|line| !line.is_empty());
+    let end = lines.iter().rposition(|line| !line.is_empty());
+    if let (Some(start), Some(end)) = (start, end) {
+        // Text under headings is often indented to line up with the title.
+        let lines = &lines[start..=end];
+        let indent = lines
+            .iter()
+            .filter(|line| !line.is_empty())
+            .map(|line| line.len() - line.trim_start().len())
+            .min()
+            .unwrap_or(0);
+        node.description = lines
+            .iter()
+            .map(|line| line.get(indent..).unwrap_or_default())
+            .collect::<Vec<_>>()
+            .join("\n");
+    }
+    reader.add(heading.level, node, heading.id, heading.blockers)
+}
+
+/// Reads a planning line like `CLOSED: [2024-07-02 Tue 17:00] DEADLINE: <2024-07-05 Fri>`,
+/// or nothing if `text` isn't one.
+fn planning(text: &str) -> anyhow::Result<Vec<(&'static str, DateTime<Utc>)>> {
+    let mut planning = vec![];
+    let mut rest = text;
+    while !rest.is_empty() {
+        let Some((word, after)) = ["CLOSED", "SCHEDULED", "DEADLINE"]
+            .into_iter()
+            .find_map(|word| Some((word, rest.strip_prefix(word)?.strip_prefix(':')?)))
+        else {
+            return Ok(vec![]);
+        };
+        let after = after.trim_start();
+        let close = match after.chars().next() {
+            Some('<') => '>',
+            Some('[') => ']',
+            _ => return Ok(vec![]),
+        };
+        let Some(end) = after.find(close) else {
+            return Ok(vec![]);
+        };
+        planning.push((word, parse_timestamp(&after[1..end])?));
+        rest = after[end + 1..].trim_start();
+    }
+    Ok(planning)
+}
+
+/// Writes an Org timestamp without its brackets, in local time as Org expects, leaving out the
+/// time at midnight.
+fn timestamp(date: DateTime<Utc>) -> String {
+    let date = date.with_timezone(&Local);
+    if date.time() == NaiveTime::MIN {
+        date.format("%Y-%m-%d %a").to_string()
+    } else {
+        date.format("%Y-%m-%d %a %H:%M").to_string()
+    }
+}
+
+/// Reads the inside of an Org timestamp like `2024-07-02 Tue 17:00 +1w`, which is in local
+/// time. Repeaters and the end of a time range are ignored.
+fn parse_timestamp(text: &str) -> anyhow::Result<DateTime<Utc>> {
+    let mut words = text.split_whitespace();
+    let date = words.next().unwrap_or_default();
+    let date = NaiveDate::parse_from_str(date, "%Y-%m-%d")
+        .with_context(|| format!("Invalid date {:?}", text))?;
+    let time = words
+        .filter(|word| word.starts_with(|c: char| c.is_ascii_digit()))
+        .find_map(|word| NaiveTime::parse_from_str(word.get(..5)?, "%H:%M").ok())
+        .unwrap_or(NaiveTime::MIN);
+    let date = date.and_time(time.with_nanosecond(0).unwrap());
+    from_local_time(&Local, date).with_context(|| format!("Invalid date {:?}", text))
+}
+
+fn is_tag(tag: &str) -> bool {
+    !tag.is_empty()
+        && tag
+            .chars()
+            .all(|c| c.is_alphanumeric() || "_@#%".contains(c))
+}
+
+fn is_property_name(key: &str) -> bool {
+    !key.is_empty()
+        && !key.contains(|c: char| c.is_whitespace() || c == ':')
+        && !key.eq_ignore_ascii_case("ID")
+        && !key.eq_ignore_ascii_case("BLOCKER")
+        && !key.eq_ignore_ascii_case("END")
+        && !key.eq_ignore_ascii_case("PROPERTIES")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    use crate::graph::{DatabaseGraph, PetgraphGraph};
+    use crate::import::test_helpers::{self, sorted, task};
+
+    /// A local time, which is what Org timestamps are in.
+    fn local(text: &str) -> DateTime<Utc> {
+        let date = text.parse().unwrap();
+        Local.from_local_datetime(&date).unwrap().to_utc()
+    }
+
+    #[test]
+    fn test_export() {
+        let due = local("2024-07-05T00:00:00").to_rfc3339();
+        let scheduled = local("2024-07-01T09:30:00").to_rfc3339();
+        let mut graph = PetgraphGraph::default();
+        let root = graph
+            .add_node(Node {
+                description: "Notes\n\n* not a heading".to_owned(),
+                ..task(
+                    "Ship\nit",
+                    &[
+                        ("priority", "A"),
+                        ("tags", "work urgent"),
+                        ("scheduled", "2024-07-03"),
+                        ("due", &due),
+                        ("effort", "2h"),
+                    ],
+                )
+            })
+            .unwrap();
+        let a = graph
+            .add_node(Node {
+                completed_at: Some(local("2024-07-02T17:00:00")),
+                ..task("a", &[("scheduled", &scheduled)])
+            })
+            .unwrap();
+        let shared = graph.add_node(task("shared", &[])).unwrap();
+        graph.add_edge(root, a).unwrap();
+        graph.add_edge(a, shared).unwrap();
+        graph.add_edge(root, shared).unwrap();
+
+        assert_eq!(
+            export(&graph).unwrap(),
+            format!(
+                "* TODO [#A] Ship it :work:urgent:\n\
+                 SCHEDULED: <2024-07-03 Wed> DEADLINE: <2024-07-05 Fri>\n\
+                 :PROPERTIES:\n\
+                 :BLOCKER: {shared}\n\
+                 :effort: 2h\n\
+                 :END:\n\
+                 Notes\n\
+                 \n\
+                 \x20* not a heading\n\
+                 ** DONE a\n\
+                 CLOSED: [2024-07-02 Tue 17:00] SCHEDULED: <2024-07-01 Mon 09:30>\n\
+                 *** TODO shared\n\
+                 :PROPERTIES:\n\
+                 :ID: {shared}\n\
+                 :END:\n"
+            )
+        );
+    }
+
+    fn roundtrip<G: Graph>(graph: G) {
+        let root = Node {
+            description: "First line\n  indented\n* stars".to_owned(),
+            ..task(
+                "",
+                &[
+                    ("due", "2024-07-05T12:15:00+00:00"),
+                    ("tags", "a b"),
+                    ("effort", "2h"),
+                ],
+            )
+        };
+        for (from, to) in test_helpers::roundtrip(graph, root, export, import) {
+            assert_eq!(to.title, from.title);
+            assert_eq!(to.description, from.description);
+            assert_eq!(to.completed_at, from.completed_at);
+            assert_eq!(to.metadata, from.metadata);
+        }
+    }
+
+    #[test]
+    fn test_roundtrip_petgraph() {
+        roundtrip(PetgraphGraph::default());
+    }
+
+    #[test]
+    fn test_roundtrip_database() {
+        roundtrip(DatabaseGraph::open_in_memory().unwrap());
+    }
+
+    #[test]
+    fn test_import() {
+        let org = "#+TITLE: Plans\n\
+                   Some text before the first heading.\n\
+                   * Project :home:\n\
+                   ** DONE Buy paint\n\
+                   \x20  CLOSED: [2024-07-02 Tue 17:00]\n\
+                   ** TODO [#B] Paint the fence\n\
+                   \x20  SCHEDULED: <2024-07-06 Sat 10:00-12:00 +1w>\n\
+                   \x20  :PROPERTIES:\n\
+                   \x20  :BLOCKER: paint previous-sibling\n\
+                   \x20  :END:\n\
+                   \x20  Two coats.\n\
+                   *** Sand it\n\
+                   * Errands\n\
+                   ** TODO Get paint\n\
+                   :PROPERTIES:\n\
+                   :ID: paint\n\
+                   :END:\n";
+        let import = parse(org).unwrap();
+        let mut graph = PetgraphGraph::default();
+        let [project, buy, fence, sand, errands, get] = import.apply(&mut graph).unwrap()[..]
+        else {
+            panic!();
+        };
+        assert_eq!(
+            sorted(graph.neighbors(project).unwrap()),
+            sorted(vec![buy, fence])
+        );
+        assert_eq!(
+            sorted(graph.neighbors(fence).unwrap()),
+            sorted(vec![sand, get])
+        );
+        assert_eq!(graph.neighbors(errands).unwrap(), vec![get]);
+
+        let project = graph.get_node(project).unwrap();
+        assert_eq!(project.title, "Project");
+        assert_eq!(project.metadata["tags"], "home");
+        assert!(!project.is_completed());
+        let buy = graph.get_node(buy).unwrap();
+        assert_eq!(buy.completed_at, Some(local("2024-07-02T17:00:00")));
+        let fence = graph.get_node(fence).unwrap();
+        assert_eq!(fence.title, "Paint the fence");
+        assert_eq!(fence.description, "Two coats.");
+        assert_eq!(fence.metadata["priority"], "B");
+        assert_eq!(
+            fence.metadata["scheduled"],
+            local("2024-07-06T10:00:00").to_rfc3339()
+        );
+        assert!(graph.get_node(get).unwrap().metadata.is_empty());
+    }
+
+    #[test]
+    fn test_invalid() {
+        let error = parse("* a\nDEADLINE: <tomorrow>\n").err().unwrap();
+        assert!(format!("{:#}", error).starts_with("Line 2: Invalid date \"tomorrow\""));
+        let error = parse("* a\n:PROPERTIES:\n:ID: x\n:END:\n* b\n:PROPERTIES:\n:ID: x\n:END:\n")
+            .err()
+            .unwrap();
+        assert_eq!(error.to_string(), "More than one task has the ID x");
+        let error = parse("* a\n:PROPERTIES:\n:ID: a\n:BLOCKER: b\n:END:\n** b\n:PROPERTIES:\n:ID: b\n:BLOCKER: a\n:END:\n")
+            .unwrap()
+            .apply(&mut PetgraphGraph::default())
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "The tasks depend on each other in a cycle: \"a\" -> \"b\" -> \"a\""
+        );
+    }
+}
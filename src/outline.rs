@@ -0,0 +1,208 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::graph::{GraphSnapshot, Node, NodeIndex};
+use crate::import::Import;
+
+/// A task as written out in an outline, like nested headings in Org-mode.
+///
+/// Outlines are trees, so each task is nested under the first task which depends on it, and any
+/// other task which depends on it refers to it by ID instead.
+pub struct Entry<'a> {
+    pub node: &'a Node,
+    /// How many tasks this one is nested under.
+    pub depth: usize,
+    /// The ID other tasks refer to this one by, if they do.
+    pub id: Option<String>,
+    /// The IDs of the tasks this one depends on, other than the ones nested under it.
+    pub blockers: Vec<String>,
+}
+
+/// Lays out every task in `snapshot` as an outline, in the order they should be written in.
+/// Set-aside edges are left out.
+pub fn entries(snapshot: &GraphSnapshot) -> Vec<Entry<'_>> {
+    let mut has_parent = HashSet::new();
+    for (_, to) in snapshot.edges() {
+        has_parent.insert(*to);
+    }
+    let mut placed = HashSet::new();
+    let mut order = vec![];
+    let mut blockers: HashMap<NodeIndex, Vec<NodeIndex>> = HashMap::new();
+    let mut stack: Vec<(NodeIndex, usize)> = snapshot
+        .nodes()
+        .map(|(index, _)| index)
+        .filter(|index| !has_parent.contains(index))
+        .map(|index| (index, 0))
+        .collect();
+    stack.reverse();
+    while let Some((index, depth)) = stack.pop() {
+        if !placed.insert(index) {
+            continue;
+        }
+        order.push((index, depth));
+        let children: Vec<NodeIndex> = snapshot.neighbors(index).collect();
+        // Children which are placed already, or end up placed under one of their
+        // siblings first, become blockers instead.
+        for &child in children.iter().rev() {
+            if !placed.contains(&child) {
+                stack.push((child, depth + 1));
+            }
+        }
+        blockers.insert(index, children);
+    }
+
+    // Now that everything is placed, children which ended up somewhere else are blockers.
+    let mut parent_of = HashMap::new();
+    let mut open: Vec<NodeIndex> = vec![];
+    for &(index, depth) in &order {
+        open.truncate(depth);
+        if let Some(&parent) = open.last() {
+            parent_of.insert(index, parent);
+        }
+        open.push(index);
+    }
+    let mut referenced = HashSet::new();
+    let mut entries = vec![];
+    for (index, depth) in order {
+        let blockers: Vec<NodeIndex> = blockers
+            .remove(&index)
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|child| parent_of.get(child) != Some(&index))
+            .collect();
+        referenced.extend(blockers.iter().copied());
+        entries.push((index, depth, blockers));
+    }
+    entries
+        .into_iter()
+        .map(|(index, depth, blockers)| Entry {
+            node: snapshot.node(index).unwrap(),
+            depth,
+            id: referenced.contains(&index).then(|| index.to_string()),
+            blockers: blockers.iter().map(NodeIndex::to_string).collect(),
+        })
+        .collect()
+}
+
+/// Turns an outline back into tasks, with each task depending on the tasks nested under it
+/// and on the tasks its blockers refer to.
+#[derive(Default)]
+pub struct OutlineReader {
+    import: Import,
+    /// The tasks which the next one might be nested under, and how deep they are.
+    open: Vec<(usize, usize)>,
+    ids: HashMap<String, usize>,
+    blockers: Vec<(usize, Vec<String>)>,
+}
+
+impl OutlineReader {
+    /// Adds a task nested under the last task which isn't as deep as it.
+    pub fn add(
+        &mut self,
+        depth: usize,
+        node: Node,
+        id: Option<String>,
+        blockers: Vec<String>,
+    ) -> anyhow::Result<()> {
+        let task = self.import.add_node(node, None);
+        while self.open.last().is_some_and(|&(open, _)| open >= depth) {
+            self.open.pop();
+        }
+        if let Some(&(_, parent)) = self.open.last() {
+            self.import.add_edge(parent, task);
+        }
+        self.open.push((depth, task));
+        if let Some(id) = id {
+            if self.ids.insert(id.clone(), task).is_some() {
+                anyhow::bail!("More than one task has the ID {}", id);
+            }
+        }
+        if !blockers.is_empty() {
+            self.blockers.push((task, blockers));
+        }
+        Ok(())
+    }
+
+    /// Returns the tasks which were read. Blockers which don't match any task's ID are ignored,
+    /// since other tools write other things there.
+    pub fn finish(mut self) -> Import {
+        for (task, blockers) in self.blockers {
+            for blocker in blockers {
+                if let Some(&to) = self.ids.get(&blocker) {
+                    self.import.add_edge(task, to);
+                }
+            }
+        }
+        self.import
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graph::{Graph, PetgraphGraph};
+
+    #[test]
+    fn test_entries() {
+        let mut graph = PetgraphGraph::default();
+        let [a, b, c, d, e] = ["a", "b", "c", "d", "e"].map(|title| {
+            graph
+                .add_node(Node {
+                    title: title.to_owned(),
+                    ..Default::default()
+                })
+                .unwrap()
+        });
+        graph.add_edge(a, b).unwrap();
+        graph.add_edge(b, c).unwrap();
+        graph.add_edge(a, c).unwrap();
+        graph.add_edge(d, c).unwrap();
+        graph.add_edge(d, e).unwrap();
+        let snapshot = graph.snapshot().unwrap();
+        let entries: Vec<_> = entries(&snapshot)
+            .into_iter()
+            .map(|entry| {
+                (
+                    entry.node.title.as_str(),
+                    entry.depth,
+                    entry.id,
+                    entry.blockers,
+                )
+            })
+            .collect();
+        assert_eq!(
+            entries,
+            [
+                ("a", 0, None, vec![c.to_string()]),
+                ("b", 1, None, vec![]),
+                ("c", 2, Some(c.to_string()), vec![]),
+                ("d", 0, None, vec![c.to_string()]),
+                ("e", 1, None, vec![]),
+            ]
+        );
+
+        // Reading them back gives the same graph.
+        let mut reader = OutlineReader::default();
+        for entry in super::entries(&snapshot) {
+            reader
+                .add(entry.depth, entry.node.clone(), entry.id, entry.blockers)
+                .unwrap();
+        }
+        let mut copy = PetgraphGraph::default();
+        let indices = reader.finish().apply(&mut copy).unwrap();
+        let edges = |graph: &PetgraphGraph, indices: &[NodeIndex]| {
+            let mut edges: Vec<(usize, usize)> = graph
+                .snapshot()
+                .unwrap()
+                .edges()
+                .iter()
+                .map(|(from, to)| {
+                    let position = |index| indices.iter().position(|i| *i == index).unwrap();
+                    (position(*from), position(*to))
+                })
+                .collect();
+            edges.sort();
+            edges
+        };
+        assert_eq!(edges(&copy, &indices), edges(&graph, &[a, b, c, d, e]));
+    }
+}
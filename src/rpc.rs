@@ -0,0 +1,667 @@
+use std::io::{self, BufRead, BufReader};
+use std::net::TcpListener;
+#[cfg(unix)]
+use std::os::unix::net::UnixListener;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use masonry::kurbo::{Circle, Point};
+use serde_json::{json, Value};
+use uuid::Uuid;
+
+use crate::graph::{Graph, Node, NodeIndex};
+use crate::graph_viewer::{new_node_position, CIRCLE_RADIUS};
+use crate::history::History;
+use crate::sync::Transport;
+
+/// How often the server checks whether it's been stopped while waiting for connections.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+// Error codes from the JSON-RPC 2.0 spec.
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+/// The request was fine, but the graph refused it, e.g. because it would create a cycle.
+const GRAPH_ERROR: i64 = -32000;
+
+/// Somewhere clients can connect to an `RpcServer`.
+pub trait Listener: Send + 'static {
+    type Stream: Transport;
+
+    fn accept_stream(&self) -> io::Result<Self::Stream>;
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()>;
+}
+
+impl Listener for TcpListener {
+    type Stream = std::net::TcpStream;
+
+    fn accept_stream(&self) -> io::Result<Self::Stream> {
+        let (stream, _) = self.accept()?;
+        // Some platforms pass the listener's non-blocking mode on to the streams it accepts.
+        stream.set_nonblocking(false)?;
+        Ok(stream)
+    }
+
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        TcpListener::set_nonblocking(self, nonblocking)
+    }
+}
+
+#[cfg(unix)]
+impl Listener for UnixListener {
+    type Stream = std::os::unix::net::UnixStream;
+
+    fn accept_stream(&self) -> io::Result<Self::Stream> {
+        let (stream, _) = self.accept()?;
+        stream.set_nonblocking(false)?;
+        Ok(stream)
+    }
+
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        UnixListener::set_nonblocking(self, nonblocking)
+    }
+}
+
+/// Serves JSON-RPC 2.0 requests which read and change a shared graph, so that editors and
+/// scripts can work on the graph while it's open in the GUI.
+///
+/// Requests and responses are sent one per line. Params are passed by name, and nodes are
+/// referred to by their IDs as strings:
+///
+/// - `add_node {title, description?, x?, y?}` returns the new node's ID.
+/// - `remove_node {id}`, `get_node {id}`, `set_title {id, title}`,
+///   `set_description {id, description}`, `mark_completed {id}`, `mark_incomplete {id}`.
+/// - `add_edge {from, to}`, `remove_edge {from, to}`, `would_create_cycle {from, to}`.
+/// - `node_indices {}`, `neighbors {id}`, `parents {id}` return lists of IDs.
+///
+/// Changes are made through `History::apply_external`, so its subscribers hear about them but
+/// undo and redo leave them alone. A connection is closed at the first line which isn't JSON,
+/// so that the headers of an HTTP request, which any web page can send to a local port, stop
+/// its body from being run. The server stops when it's dropped.
+pub struct RpcServer {
+    stopped: Arc<AtomicBool>,
+    listener: Option<JoinHandle<()>>,
+    connections: Arc<Mutex<Vec<Connection>>>,
+}
+
+struct Connection {
+    shutdown: Box<dyn Fn() + Send>,
+    thread: JoinHandle<()>,
+}
+
+impl RpcServer {
+    pub fn start<G: Graph + Send + 'static>(
+        graph: Arc<Mutex<History<G>>>,
+        listener: impl Listener,
+    ) -> io::Result<Self> {
+        // Lets the listener thread notice when it's been stopped.
+        listener.set_nonblocking(true)?;
+        let stopped = Arc::new(AtomicBool::new(false));
+        let connections: Arc<Mutex<Vec<Connection>>> = Arc::default();
+        let thread = {
+            let stopped = stopped.clone();
+            let connections = connections.clone();
+            thread::spawn(move || {
+                while !stopped.load(Ordering::Acquire) {
+                    match listener.accept_stream() {
+                        Ok(stream) => {
+                            let mut connections = connections.lock().unwrap();
+                            // Forget clients which have disconnected, so they don't pile up.
+                            connections.retain(|connection| !connection.thread.is_finished());
+                            match serve(graph.clone(), stream) {
+                                Ok(connection) => connections.push(connection),
+                                Err(e) => log::warn!("Failed to serve RPC connection: {}", e),
+                            }
+                        }
+                        Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                            thread::sleep(POLL_INTERVAL)
+                        }
+                        Err(e) => log::warn!("Failed to accept RPC connection: {}", e),
+                    }
+                }
+            })
+        };
+        Ok(Self {
+            stopped,
+            listener: Some(thread),
+            connections,
+        })
+    }
+}
+
+impl Drop for RpcServer {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::Release);
+        if let Some(thread) = self.listener.take() {
+            let _ = thread.join();
+        }
+        for connection in self.connections.lock().unwrap().drain(..) {
+            (connection.shutdown)();
+            let _ = connection.thread.join();
+        }
+    }
+}
+
+/// Answers requests from one client until it disconnects.
+fn serve<G: Graph + Send + 'static, T: Transport>(
+    graph: Arc<Mutex<History<G>>>,
+    stream: T,
+) -> io::Result<Connection> {
+    let reader = BufReader::new(stream.try_clone()?);
+    let mut writer = stream.try_clone()?;
+    let thread = thread::spawn(move || {
+        for line in reader.lines() {
+            let Ok(line) = line else {
+                return;
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+            let request = match serde_json::from_str(&line) {
+                Ok(request) => request,
+                Err(e) => {
+                    let response = error_response(Value::Null, PARSE_ERROR, e.to_string());
+                    let _ = writeln!(writer, "{}", response).and_then(|()| writer.flush());
+                    let _ = writer.shutdown();
+                    return;
+                }
+            };
+            if let Some(response) = handle(&graph, request) {
+                if writeln!(writer, "{}", response)
+                    .and_then(|()| writer.flush())
+                    .is_err()
+                {
+                    return;
+                }
+            }
+        }
+    });
+    Ok(Connection {
+        shutdown: Box::new(move || {
+            let _ = stream.shutdown();
+        }),
+        thread,
+    })
+}
+
+/// Returns the response to a line of JSON, or `None` if it only held notifications.
+fn handle<G: Graph>(graph: &Mutex<History<G>>, request: Value) -> Option<Value> {
+    match request {
+        Value::Array(requests) if requests.is_empty() => Some(error_response(
+            Value::Null,
+            INVALID_REQUEST,
+            "Empty batch".to_owned(),
+        )),
+        Value::Array(requests) => {
+            let responses: Vec<Value> = requests
+                .iter()
+                .filter_map(|request| respond(graph, request))
+                .collect();
+            (!responses.is_empty()).then_some(Value::Array(responses))
+        }
+        request => respond(graph, &request),
+    }
+}
+
+fn respond<G: Graph>(graph: &Mutex<History<G>>, request: &Value) -> Option<Value> {
+    let id = request.get("id");
+    let method = request.get("method").and_then(Value::as_str);
+    let (Some("2.0"), Some(method)) = (request.get("jsonrpc").and_then(Value::as_str), method)
+    else {
+        return Some(error_response(
+            id.cloned().unwrap_or(Value::Null),
+            INVALID_REQUEST,
+            "Expected a JSON-RPC 2.0 request".to_owned(),
+        ));
+    };
+    let params = request.get("params").unwrap_or(&Value::Null);
+    let result = graph
+        .lock()
+        .unwrap()
+        .apply_external(|graph| Ok(call(graph, method, params)))
+        .unwrap_or_else(|e| Err(e.into()));
+    // Requests without an ID are notifications, which don't get a response.
+    let id = id?.clone();
+    Some(match result {
+        Ok(result) => json!({"jsonrpc": "2.0", "result": result, "id": id}),
+        Err(RpcError { code, message }) => error_response(id, code, message),
+    })
+}
+
+fn error_response(id: Value, code: i64, message: String) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "error": {"code": code, "message": message},
+        "id": id,
+    })
+}
+
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+impl RpcError {
+    fn invalid_params(message: String) -> Self {
+        Self {
+            code: INVALID_PARAMS,
+            message,
+        }
+    }
+}
+
+impl From<anyhow::Error> for RpcError {
+    fn from(e: anyhow::Error) -> Self {
+        Self {
+            code: GRAPH_ERROR,
+            message: format!("{:#}", e),
+        }
+    }
+}
+
+fn param<'a>(params: &'a Value, name: &str) -> Result<&'a Value, RpcError> {
+    params
+        .get(name)
+        .ok_or_else(|| RpcError::invalid_params(format!("Missing param {}", name)))
+}
+
+fn string_param<'a>(params: &'a Value, name: &str) -> Result<&'a str, RpcError> {
+    param(params, name)?
+        .as_str()
+        .ok_or_else(|| RpcError::invalid_params(format!("Param {} must be a string", name)))
+}
+
+fn number_param(params: &Value, name: &str) -> Result<Option<f64>, RpcError> {
+    match params.get(name) {
+        None | Some(Value::Null) => Ok(None),
+        Some(value) => value
+            .as_f64()
+            .map(Some)
+            .ok_or_else(|| RpcError::invalid_params(format!("Param {} must be a number", name))),
+    }
+}
+
+fn index_param(params: &Value, name: &str) -> Result<NodeIndex, RpcError> {
+    Uuid::parse_str(string_param(params, name)?)
+        .map_err(|e| RpcError::invalid_params(format!("Param {} isn't a node ID: {}", name, e)))
+}
+
+fn indices_json(indices: Vec<NodeIndex>) -> Value {
+    indices.into_iter().map(|index| index.to_string()).collect()
+}
+
+fn node_json(index: NodeIndex, node: Node) -> Value {
+    json!({
+        "id": index.to_string(),
+        "title": node.title,
+        "description": node.description,
+        "x": node.circle.center.x,
+        "y": node.circle.center.y,
+        "radius": node.circle.radius,
+        "completed_at": node.completed_at.map(|at| at.to_rfc3339()),
+    })
+}
+
+fn call(graph: &mut impl Graph, method: &str, params: &Value) -> Result<Value, RpcError> {
+    Ok(match method {
+        "add_node" => {
+            let position = match (number_param(params, "x")?, number_param(params, "y")?) {
+                (Some(x), Some(y)) => Point::new(x, y),
+                (None, None) => {
+                    let snapshot = graph.snapshot()?;
+                    new_node_position(&snapshot)
+                }
+                _ => {
+                    return Err(RpcError::invalid_params(
+                        "Pass both x and y, or neither".to_owned(),
+                    ))
+                }
+            };
+            let description = match params.get("description") {
+                None => String::new(),
+                Some(_) => string_param(params, "description")?.to_owned(),
+            };
+            let index = graph.add_node(Node {
+                title: string_param(params, "title")?.to_owned(),
+                description,
+                circle: Circle::new(position, CIRCLE_RADIUS),
+                ..Default::default()
+            })?;
+            Value::from(index.to_string())
+        }
+        "remove_node" => {
+            graph.remove_node(index_param(params, "id")?)?;
+            Value::Null
+        }
+        "get_node" => {
+            let index = index_param(params, "id")?;
+            node_json(index, graph.get_node(index)?)
+        }
+        "set_title" | "set_description" => {
+            let index = index_param(params, "id")?;
+            let mut node = graph.get_node(index)?;
+            if method == "set_title" {
+                node.title = string_param(params, "title")?.to_owned();
+            } else {
+                node.description = string_param(params, "description")?.to_owned();
+            }
+            graph.set_node(index, node)?;
+            Value::Null
+        }
+        "mark_completed" => {
+            graph.mark_completed(index_param(params, "id")?)?;
+            Value::Null
+        }
+        "mark_incomplete" => {
+            graph.mark_incomplete(index_param(params, "id")?)?;
+            Value::Null
+        }
+        "add_edge" => {
+            graph.add_edge(index_param(params, "from")?, index_param(params, "to")?)?;
+            Value::Null
+        }
+        "remove_edge" => {
+            graph.remove_edge(index_param(params, "from")?, index_param(params, "to")?)?;
+            Value::Null
+        }
+        "would_create_cycle" => Value::from(
+            graph.would_create_cycle(index_param(params, "from")?, index_param(params, "to")?)?,
+        ),
+        "node_indices" => indices_json(graph.node_indices()?),
+        "neighbors" => indices_json(graph.neighbors(index_param(params, "id")?)?),
+        "parents" => indices_json(graph.parents(index_param(params, "id")?)?),
+        _ => {
+            return Err(RpcError {
+                code: METHOD_NOT_FOUND,
+                message: format!("Unknown method {}", method),
+            })
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+    #[cfg(unix)]
+    use std::os::unix::net::UnixStream;
+
+    use super::*;
+    use crate::graph::{DatabaseGraph, GraphEvent};
+
+    /// Sends requests one line at a time, like an editor plugin would.
+    struct Client<T: Transport> {
+        reader: BufReader<T>,
+        writer: T,
+        next_id: usize,
+    }
+
+    impl<T: Transport> Client<T> {
+        fn new(stream: T) -> Self {
+            Self {
+                reader: BufReader::new(stream.try_clone().unwrap()),
+                writer: stream,
+                next_id: 0,
+            }
+        }
+
+        fn send(&mut self, line: &str) -> Value {
+            writeln!(self.writer, "{}", line).unwrap();
+            let mut response = String::new();
+            self.reader.read_line(&mut response).unwrap();
+            serde_json::from_str(&response).unwrap()
+        }
+
+        /// Returns the result of calling `method`, or its error message.
+        fn call(&mut self, method: &str, params: Value) -> Result<Value, String> {
+            self.next_id += 1;
+            let request = json!({
+                "jsonrpc": "2.0",
+                "method": method,
+                "params": params,
+                "id": self.next_id,
+            });
+            let response = self.send(&request.to_string());
+            assert_eq!(response.get("id"), Some(&json!(self.next_id)));
+            match (response.get("result"), response.get("error")) {
+                (Some(result), None) => Ok(result.clone()),
+                (None, Some(error)) => {
+                    Err(error.get("message").unwrap().as_str().unwrap().to_owned())
+                }
+                _ => panic!("Invalid response {}", response),
+            }
+        }
+    }
+
+    fn check_client<T: Transport>(graph: &Mutex<History<DatabaseGraph>>, client: &mut Client<T>) {
+        let a = client.call("add_node", json!({"title": "a"})).unwrap();
+        let b = client
+            .call(
+                "add_node",
+                json!({"title": "b", "description": "details", "x": 10.0, "y": 20.0}),
+            )
+            .unwrap();
+        let edge = json!({"from": a, "to": b});
+        let reversed = json!({"from": b, "to": a});
+        client.call("add_edge", edge.clone()).unwrap();
+        assert_eq!(
+            client.call("would_create_cycle", reversed.clone()),
+            Ok(json!(true))
+        );
+        let error = client.call("add_edge", reversed).unwrap_err();
+        assert!(error.contains("cycle"), "{}", error);
+        assert_eq!(client.call("neighbors", json!({"id": a})), Ok(json!([b])));
+        assert_eq!(client.call("parents", json!({"id": b})), Ok(json!([a])));
+
+        client
+            .call("set_title", json!({"id": b, "title": "renamed"}))
+            .unwrap();
+        client.call("mark_completed", json!({"id": b})).unwrap();
+        let node = client.call("get_node", json!({"id": b})).unwrap();
+        assert_eq!(node["title"], "renamed");
+        assert_eq!(node["description"], "details");
+        assert_eq!(node["x"], 10.0);
+        assert!(node.get("completed_at").unwrap().as_str().is_some());
+
+        // The changes were made to the shared graph.
+        let index = |json: &Value| Uuid::parse_str(json.as_str().unwrap()).unwrap();
+        {
+            let graph = graph.lock().unwrap();
+            assert_eq!(graph.neighbors(index(&a)).unwrap(), vec![index(&b)]);
+            assert_eq!(graph.get_node(index(&b)).unwrap().title, "renamed");
+        }
+
+        client.call("remove_edge", edge).unwrap();
+        client.call("remove_node", json!({"id": a})).unwrap();
+        assert_eq!(client.call("node_indices", Value::Null), Ok(json!([b])));
+    }
+
+    fn shared_graph() -> Arc<Mutex<History<DatabaseGraph>>> {
+        Arc::new(Mutex::new(History::new(
+            DatabaseGraph::open_in_memory().unwrap(),
+        )))
+    }
+
+    #[test]
+    fn test_rpc_over_tcp() {
+        let graph = shared_graph();
+        let events = Arc::new(Mutex::new(vec![]));
+        {
+            let events = events.clone();
+            graph
+                .lock()
+                .unwrap()
+                .subscribe(Box::new(move |event| events.lock().unwrap().push(*event)));
+        }
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = RpcServer::start(graph.clone(), listener).unwrap();
+
+        let mut client = Client::new(TcpStream::connect(address).unwrap());
+        check_client(&graph, &mut client);
+        // Subscribers, like the graph viewer, heard about every change.
+        assert!(matches!(
+            events.lock().unwrap()[..],
+            [
+                GraphEvent::NodeAdded(_),
+                GraphEvent::NodeAdded(_),
+                GraphEvent::EdgeAdded { .. },
+                ..
+            ]
+        ));
+
+        // Dropping the server hangs up on its clients.
+        drop(server);
+        let mut line = String::new();
+        assert_eq!(client.reader.read_line(&mut line).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_disconnected_clients_are_forgotten() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = RpcServer::start(shared_graph(), listener).unwrap();
+        for _ in 0..3 {
+            let mut client = Client::new(TcpStream::connect(address).unwrap());
+            client.call("node_indices", Value::Null).unwrap();
+        }
+        // Connecting again sweeps up the clients which have hung up.
+        let deadline = std::time::Instant::now() + Duration::from_secs(10);
+        let mut client = Client::new(TcpStream::connect(address).unwrap());
+        while server.connections.lock().unwrap().len() > 1 {
+            assert!(
+                std::time::Instant::now() < deadline,
+                "Connections weren't forgotten"
+            );
+            client = Client::new(TcpStream::connect(address).unwrap());
+            thread::sleep(Duration::from_millis(10));
+        }
+        client.call("node_indices", Value::Null).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_rpc_over_unix_socket() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("ekad.sock");
+        let graph = shared_graph();
+        let _server = RpcServer::start(graph.clone(), UnixListener::bind(&path).unwrap()).unwrap();
+        check_client(
+            &graph,
+            &mut Client::new(UnixStream::connect(&path).unwrap()),
+        );
+    }
+
+    #[test]
+    fn test_rpc_ignores_http_requests() {
+        let graph = shared_graph();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let _server = RpcServer::start(graph.clone(), listener).unwrap();
+
+        // What a web page's `fetch` to the port would send, with a request as its body.
+        let body = r#"{"jsonrpc": "2.0", "method": "add_node", "params": {"title": "a"}, "id": 1}"#;
+        let mut stream = TcpStream::connect(address).unwrap();
+        write!(
+            stream,
+            "POST / HTTP/1.1\r\nHost: {}\r\nContent-Type: text/plain\r\n\
+             Content-Length: {}\r\n\r\n{}\n",
+            address,
+            body.len() + 1,
+            body
+        )
+        .unwrap();
+        let mut responses = String::new();
+        BufReader::new(stream)
+            .read_to_string(&mut responses)
+            .unwrap();
+        let responses: Vec<Value> = responses
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(responses.len(), 1);
+        assert_eq!(responses[0]["error"]["code"], PARSE_ERROR);
+        assert!(graph.lock().unwrap().node_indices().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_rpc_changes_are_not_undone() {
+        let graph = shared_graph();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let _server = RpcServer::start(graph.clone(), listener).unwrap();
+        let mut client = Client::new(TcpStream::connect(address).unwrap());
+
+        graph
+            .lock()
+            .unwrap()
+            .add_node(Node {
+                title: "local".to_owned(),
+                ..Default::default()
+            })
+            .unwrap();
+        let remote = client.call("add_node", json!({"title": "remote"})).unwrap();
+        let remote = Uuid::parse_str(remote.as_str().unwrap()).unwrap();
+
+        // Undo only takes back the change made in the app.
+        let mut graph = graph.lock().unwrap();
+        assert!(graph.undo().unwrap());
+        assert_eq!(graph.node_indices().unwrap(), vec![remote]);
+        assert!(!graph.can_undo());
+    }
+
+    #[test]
+    fn test_rpc_errors() {
+        let graph = shared_graph();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let _server = RpcServer::start(graph.clone(), listener).unwrap();
+        let mut client = Client::new(TcpStream::connect(address).unwrap());
+        let code = |response: Value| response["error"]["code"].as_i64();
+
+        // Lines which aren't JSON get an error, and then the connection is closed.
+        assert_eq!(code(client.send("{not json")), Some(PARSE_ERROR));
+        let mut line = String::new();
+        assert_eq!(client.reader.read_line(&mut line).unwrap(), 0);
+
+        let mut client = Client::new(TcpStream::connect(address).unwrap());
+        assert_eq!(
+            code(client.send(r#"{"method": "node_indices", "id": 1}"#)),
+            Some(INVALID_REQUEST)
+        );
+        assert_eq!(code(client.send("[]")), Some(INVALID_REQUEST));
+        assert_eq!(
+            client.call("frobnicate", Value::Null),
+            Err("Unknown method frobnicate".to_owned())
+        );
+        assert!(client.call("get_node", Value::Null).is_err());
+        assert!(client
+            .call("get_node", json!({"id": "nope"}))
+            .unwrap_err()
+            .contains("isn't a node ID"));
+        assert!(client
+            .call("add_node", json!({"title": "a", "x": 1.0}))
+            .is_err());
+
+        // Notifications are carried out, but not answered, so the next line is the batch's.
+        writeln!(
+            client.writer,
+            r#"{{"jsonrpc": "2.0", "method": "add_node", "params": {{"title": "a"}}}}"#
+        )
+        .unwrap();
+        let batch = client.send(
+            r#"[{"jsonrpc": "2.0", "method": "node_indices", "id": "first"},
+                {"jsonrpc": "2.0", "method": "frobnicate", "id": "second"}]"#
+                .replace('\n', " ")
+                .as_str(),
+        );
+        let batch = batch.as_array().unwrap();
+        assert_eq!(batch.len(), 2);
+        assert_eq!(batch[0]["id"], "first");
+        assert_eq!(batch[0]["result"].as_array().map(Vec::len), Some(1));
+        assert_eq!(code(batch[1].clone()), Some(METHOD_NOT_FOUND));
+    }
+}
@@ -1,4 +1,4 @@
-use masonry::kurbo::{Affine, Circle, Line, Point};
+use masonry::kurbo::{Affine, Circle, Line, ParamCurveNearest, Point};
 use std::f64::consts::PI;
 
 const ARROW_ARM_LENGTH: f64 = 20.0;
@@ -30,6 +30,11 @@ pub fn in_circle(point: &Point, circle: &Circle) -> bool {
     point.distance_squared(circle.center) < circle.radius * circle.radius
 }
 
+/// Returns true if `point` is within `tolerance` of any part of `line`.
+pub fn near_line(point: &Point, line: &Line, tolerance: f64) -> bool {
+    line.nearest(*point, 1e-6).distance_sq < tolerance * tolerance
+}
+
 pub fn circle_bounding_square_size(radius: f64) -> f64 {
     // This is the width and height of a square whose corners sit on the circumference of a circle.
     //
@@ -45,3 +50,17 @@ pub fn circle_bounding_square_size(radius: f64) -> f64 {
     //   - `a = 2/sqrt(2) * r`
     2f64 / 2f64.sqrt() * radius
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_near_line() {
+        let line = Line::new(Point::new(0.0, 0.0), Point::new(10.0, 0.0));
+        assert!(near_line(&Point::new(5.0, 1.0), &line, 2.0));
+        assert!(!near_line(&Point::new(5.0, 3.0), &line, 2.0));
+        assert!(near_line(&Point::new(-1.0, 0.0), &line, 2.0));
+        assert!(!near_line(&Point::new(13.0, 0.0), &line, 2.0));
+    }
+}
@@ -0,0 +1,398 @@
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream, ToSocketAddrs};
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use automerge::sync;
+
+use crate::automerge_graph::AutomergeGraph;
+use crate::history::History;
+use crate::rpc::Listener;
+
+/// How often a peer checks the local graph for changes it hasn't sent yet.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The longest message a peer may send. Anything longer is refused before it's read,
+/// rather than trusting the peer with how much memory to allocate.
+const MAX_MESSAGE_LEN: usize = 64 * 1024 * 1024;
+
+/// A graph shared with the GUI, whose undo history has to hear about changes from peers.
+pub type SharedGraph = Arc<Mutex<History<AutomergeGraph>>>;
+
+/// A connection which sync messages can be sent over.
+pub trait Transport: Read + Write + Send + Sized + 'static {
+    fn try_clone(&self) -> io::Result<Self>;
+
+    /// Closes both directions of the connection, waking up any blocked reads.
+    fn shutdown(&self) -> io::Result<()>;
+}
+
+impl Transport for TcpStream {
+    fn try_clone(&self) -> io::Result<Self> {
+        TcpStream::try_clone(self)
+    }
+
+    fn shutdown(&self) -> io::Result<()> {
+        TcpStream::shutdown(self, Shutdown::Both)
+    }
+}
+
+#[cfg(unix)]
+impl Transport for UnixStream {
+    fn try_clone(&self) -> io::Result<Self> {
+        UnixStream::try_clone(self)
+    }
+
+    fn shutdown(&self) -> io::Result<()> {
+        UnixStream::shutdown(self, Shutdown::Both)
+    }
+}
+
+/// Keeps an `AutomergeGraph` in sync with a copy of it on the other end of a connection.
+///
+/// Changes from the peer are applied to the graph as they arrive, through
+/// `History::apply_external`, so they're published to the graph's subscribers as `GraphEvent`s
+/// and undo doesn't revert them. Local changes are picked up within `POLL_INTERVAL`.
+/// Syncing stops when either side closes the connection or the `SyncPeer` is dropped.
+pub struct SyncPeer {
+    shutdown: Box<dyn Fn() + Send>,
+    reader: Option<JoinHandle<()>>,
+    writer: Option<JoinHandle<()>>,
+}
+
+impl SyncPeer {
+    /// Connects to a peer listening with `accept` or a `SyncServer`.
+    pub fn connect(graph: SharedGraph, address: impl ToSocketAddrs) -> io::Result<Self> {
+        Self::start(graph, TcpStream::connect(address)?)
+    }
+
+    /// Waits for the next peer to connect to `listener`.
+    pub fn accept(graph: SharedGraph, listener: &TcpListener) -> io::Result<Self> {
+        let (stream, _) = listener.accept()?;
+        Self::start(graph, stream)
+    }
+
+    /// Starts syncing over an already open connection.
+    pub fn start<T: Transport>(graph: SharedGraph, stream: T) -> io::Result<Self> {
+        let state = Arc::new(Mutex::new(sync::State::new()));
+        let (wake, woken) = mpsc::channel();
+
+        let reader = {
+            let graph = graph.clone();
+            let state = state.clone();
+            let stream = stream.try_clone()?;
+            thread::spawn(move || {
+                if let Err(e) = receive_messages(&graph, &state, stream, wake) {
+                    log::warn!("Stopped receiving sync messages: {}", e);
+                }
+            })
+        };
+        let writer = {
+            let stream = stream.try_clone()?;
+            thread::spawn(move || {
+                if let Err(e) = send_messages(&graph, &state, stream, woken) {
+                    log::warn!("Stopped sending sync messages: {}", e);
+                }
+            })
+        };
+
+        Ok(Self {
+            shutdown: Box::new(move || {
+                let _ = stream.shutdown();
+            }),
+            reader: Some(reader),
+            writer: Some(writer),
+        })
+    }
+
+    /// Whether the connection to the peer is still open.
+    pub fn is_connected(&self) -> bool {
+        [&self.reader, &self.writer]
+            .into_iter()
+            .all(|thread| thread.as_ref().is_some_and(|thread| !thread.is_finished()))
+    }
+}
+
+impl Drop for SyncPeer {
+    fn drop(&mut self) {
+        (self.shutdown)();
+        for thread in [self.reader.take(), self.writer.take()]
+            .into_iter()
+            .flatten()
+        {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Accepts peers on a listener and syncs the graph with each of them, until it's dropped.
+pub struct SyncServer {
+    stopped: Arc<AtomicBool>,
+    listener: Option<JoinHandle<()>>,
+}
+
+impl SyncServer {
+    pub fn start(graph: SharedGraph, listener: impl Listener) -> io::Result<Self> {
+        // Lets the listener thread notice when it's been stopped.
+        listener.set_nonblocking(true)?;
+        let stopped = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let stopped = stopped.clone();
+            thread::spawn(move || {
+                // Dropped along with the thread, which disconnects every peer.
+                let mut peers: Vec<SyncPeer> = vec![];
+                while !stopped.load(Ordering::Acquire) {
+                    match listener.accept_stream() {
+                        Ok(stream) => {
+                            peers.retain(SyncPeer::is_connected);
+                            match SyncPeer::start(graph.clone(), stream) {
+                                Ok(peer) => peers.push(peer),
+                                Err(e) => log::warn!("Failed to sync with peer: {}", e),
+                            }
+                        }
+                        Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                            thread::sleep(POLL_INTERVAL)
+                        }
+                        Err(e) => log::warn!("Failed to accept sync peer: {}", e),
+                    }
+                }
+            })
+        };
+        Ok(Self {
+            stopped,
+            listener: Some(thread),
+        })
+    }
+}
+
+impl Drop for SyncServer {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::Release);
+        if let Some(thread) = self.listener.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Applies every message from the peer, waking the writer to answer each one.
+fn receive_messages(
+    graph: &Mutex<History<AutomergeGraph>>,
+    state: &Mutex<sync::State>,
+    mut stream: impl Read,
+    wake: Sender<()>,
+) -> anyhow::Result<()> {
+    while let Some(message) = read_message(&mut stream)? {
+        graph.lock().unwrap().apply_external(|graph| {
+            graph.receive_sync_message(&mut state.lock().unwrap(), message)
+        })?;
+        let _ = wake.send(());
+    }
+    Ok(())
+}
+
+/// Sends whatever the peer is missing whenever it's woken up, or every `POLL_INTERVAL`.
+/// Stops once the reader has stopped.
+fn send_messages(
+    graph: &Mutex<History<AutomergeGraph>>,
+    state: &Mutex<sync::State>,
+    mut stream: impl Write,
+    woken: Receiver<()>,
+) -> anyhow::Result<()> {
+    loop {
+        let message = {
+            let mut graph = graph.lock().unwrap();
+            graph
+                .inner_mut()
+                .generate_sync_message(&mut state.lock().unwrap())
+        };
+        if let Some(message) = message {
+            write_message(&mut stream, message)?;
+        }
+        match woken.recv_timeout(POLL_INTERVAL) {
+            Ok(()) | Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => return Ok(()),
+        }
+    }
+}
+
+/// Messages are framed by their length, as a big-endian `u32`.
+fn write_message(stream: &mut impl Write, message: sync::Message) -> anyhow::Result<()> {
+    let bytes = message.encode();
+    stream.write_all(&u32::try_from(bytes.len())?.to_be_bytes())?;
+    stream.write_all(&bytes)?;
+    stream.flush()?;
+    Ok(())
+}
+
+/// Returns `None` once the peer has closed the connection.
+fn read_message(stream: &mut impl Read) -> anyhow::Result<Option<sync::Message>> {
+    let mut length = [0; 4];
+    match stream.read_exact(&mut length) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    }
+    let length = u32::from_be_bytes(length) as usize;
+    if length > MAX_MESSAGE_LEN {
+        anyhow::bail!(
+            "Sync message of {} bytes is longer than the limit of {}",
+            length,
+            MAX_MESSAGE_LEN
+        );
+    }
+    let mut bytes = vec![0; length];
+    stream.read_exact(&mut bytes)?;
+    Ok(Some(sync::Message::decode(&bytes)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use masonry::kurbo::{Circle, Point};
+
+    use super::*;
+    use crate::graph::{Graph, GraphEvent, Node, NodeIndex};
+
+    fn test_node(title: &str) -> Node {
+        Node {
+            title: title.to_owned(),
+            circle: Circle::new(Point::new(1.0, 2.0), 40.0),
+            ..Default::default()
+        }
+    }
+
+    fn shared_graph() -> SharedGraph {
+        Arc::new(Mutex::new(History::new(
+            AutomergeGraph::open_in_memory().unwrap(),
+        )))
+    }
+
+    /// Polls `condition` until it holds, failing the test if it takes too long.
+    fn wait_until(mut condition: impl FnMut() -> bool) {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while !condition() {
+            assert!(Instant::now() < deadline, "Timed out waiting for sync");
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    fn titles(graph: &Mutex<History<AutomergeGraph>>) -> Vec<String> {
+        let graph = graph.lock().unwrap();
+        let mut titles: Vec<String> = graph
+            .node_indices()
+            .unwrap()
+            .into_iter()
+            .map(|index| graph.get_node(index).unwrap().title)
+            .collect();
+        titles.sort();
+        titles
+    }
+
+    fn neighbors(graph: &Mutex<History<AutomergeGraph>>, index: NodeIndex) -> Vec<NodeIndex> {
+        graph.lock().unwrap().neighbors(index).unwrap()
+    }
+
+    #[test]
+    fn test_sync_over_tcp() {
+        let first = shared_graph();
+        let second = shared_graph();
+        let a = first.lock().unwrap().add_node(test_node("a")).unwrap();
+        let b = second.lock().unwrap().add_node(test_node("b")).unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let events = Arc::new(Mutex::new(vec![]));
+        {
+            let events = events.clone();
+            first
+                .lock()
+                .unwrap()
+                .subscribe(Box::new(move |event| events.lock().unwrap().push(*event)));
+        }
+        let accepted = {
+            let first = first.clone();
+            thread::spawn(move || SyncPeer::accept(first, &listener).unwrap())
+        };
+        let connected = SyncPeer::connect(second.clone(), address).unwrap();
+        let accepted = accepted.join().unwrap();
+
+        wait_until(|| titles(&first) == ["a", "b"] && titles(&second) == ["a", "b"]);
+        assert!(events.lock().unwrap().contains(&GraphEvent::NodeAdded(b)));
+        // Undo only reverts the first graph's own changes.
+        assert!(first.lock().unwrap().undo().unwrap());
+        assert_eq!(titles(&first), ["b"]);
+        assert!(!first.lock().unwrap().can_undo());
+        first.lock().unwrap().redo().unwrap();
+
+        // Changes made after connecting are picked up too.
+        second.lock().unwrap().add_edge(a, b).unwrap();
+        wait_until(|| neighbors(&first, a) == [b]);
+
+        assert!(accepted.is_connected());
+        drop(connected);
+        wait_until(|| !accepted.is_connected());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_sync_over_unix_socket() {
+        let first = shared_graph();
+        let second = shared_graph();
+        let a = first.lock().unwrap().add_node(test_node("a")).unwrap();
+        let b = first.lock().unwrap().add_node(test_node("b")).unwrap();
+
+        let (first_stream, second_stream) = UnixStream::pair().unwrap();
+        let _first_peer = SyncPeer::start(first.clone(), first_stream).unwrap();
+        let _second_peer = SyncPeer::start(second.clone(), second_stream).unwrap();
+        wait_until(|| titles(&second) == ["a", "b"]);
+
+        // Concurrent edits on both sides converge.
+        first.lock().unwrap().add_edge(a, b).unwrap();
+        {
+            let mut second = second.lock().unwrap();
+            let mut node = second.get_node(b).unwrap();
+            node.title = "renamed".to_owned();
+            second.set_node(b, node).unwrap();
+        }
+        wait_until(|| {
+            titles(&first) == ["a", "renamed"]
+                && neighbors(&second, a) == [b]
+                && titles(&second) == ["a", "renamed"]
+                && neighbors(&first, a) == [b]
+        });
+    }
+
+    #[test]
+    fn test_sync_server_accepts_peers() {
+        let first = shared_graph();
+        let a = first.lock().unwrap().add_node(test_node("a")).unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = SyncServer::start(first.clone(), listener).unwrap();
+
+        let seconds = [shared_graph(), shared_graph()];
+        let peers: Vec<SyncPeer> = seconds
+            .iter()
+            .map(|second| SyncPeer::connect(second.clone(), address).unwrap())
+            .collect();
+        wait_until(|| seconds.iter().all(|second| titles(second) == ["a"]));
+        assert_eq!(first.lock().unwrap().node_indices().unwrap(), vec![a]);
+
+        // Stopping the server disconnects its peers.
+        drop(server);
+        wait_until(|| peers.iter().all(|peer| !peer.is_connected()));
+    }
+
+    #[test]
+    fn test_read_message_refuses_huge_frames() {
+        let length = u32::try_from(MAX_MESSAGE_LEN + 1).unwrap().to_be_bytes();
+        let error = read_message(&mut &length[..]).unwrap_err();
+        assert!(error.to_string().contains("longer than the limit"));
+    }
+}
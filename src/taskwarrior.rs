@@ -0,0 +1,470 @@
+use std::collections::HashMap;
+
+use anyhow::Context;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use serde_json::{json, Value};
+use uuid::Uuid;
+
+use crate::graph::{Graph, MetadataDate, Node, NodeIndex};
+use crate::import::Import;
+
+/// The metadata in which a task imported from Taskwarrior keeps its UUID from there,
+/// so that exporting it again updates the same Taskwarrior task.
+pub const UUID_KEY: &str = "taskwarrior_uuid";
+
+/// The metadata in which a task imported from Taskwarrior keeps when its first annotation was
+/// written, so that exporting it again doesn't make the annotation look new.
+pub const ANNOTATION_ENTRY_KEY: &str = "taskwarrior_annotation_entry";
+
+/// How Taskwarrior writes dates, like `20240702T170000Z`.
+const DATE_FORMAT: &str = "%Y%m%dT%H%M%SZ";
+
+/// What `import` added to the graph.
+#[derive(Debug)]
+pub struct Report {
+    /// The new tasks, in the order they were in the export.
+    pub tasks: Vec<NodeIndex>,
+    /// Each cycle of dependencies in the export, like `"a" -> "b" -> "a"`.
+    /// The dependency which closed each cycle was set aside.
+    pub cycles: Vec<String>,
+}
+
+/// Writes every task in `graph` as Taskwarrior JSON, which `task import` reads.
+///
+/// Titles become descriptions, the description becomes an annotation,
+/// and each task `depends` on the tasks it has edges to. The `due` and `tags` metadata are kept.
+/// Set-aside edges are left out, since they'd bring back the cycles they broke.
+pub fn export<G: Graph + ?Sized>(graph: &G) -> anyhow::Result<String> {
+    let snapshot = graph.snapshot()?;
+    let uuids = snapshot.external_ids(UUID_KEY);
+    let mut tasks = vec![];
+    for (index, node) in snapshot.nodes() {
+        let mut task = json!({"uuid": uuids[&index], "description": node.title});
+        match node.completed_at {
+            Some(completed_at) => {
+                task["status"] = json!("completed");
+                task["end"] = json!(format_date(completed_at));
+            }
+            None => task["status"] = json!("pending"),
+        }
+        if let Some(due) = node.metadata.get("due") {
+            let due = MetadataDate::parse(due)
+                .with_context(|| format!("Invalid due date for {:?}: {}", node.title, due))?;
+            task["due"] = json!(format_date(due.to_utc()));
+        }
+        if let Some(tags) = node.metadata.get("tags") {
+            task["tags"] = tags.split_whitespace().collect();
+        }
+        let depends: Vec<&str> = snapshot
+            .neighbors(index)
+            .map(|child| uuids[&child].as_str())
+            .collect();
+        if !depends.is_empty() {
+            task["depends"] = json!(depends);
+        }
+        if !node.description.is_empty() {
+            let entry = match node.metadata.get(ANNOTATION_ENTRY_KEY) {
+                Some(entry) => parse_date(entry)?,
+                None => created_at(index),
+            };
+            task["annotations"] = json!([{
+                "entry": format_date(entry),
+                "description": node.description,
+            }]);
+        }
+        tasks.push(task);
+    }
+    Ok(format!("{:#}\n", Value::Array(tasks)))
+}
+
+/// When the task was added, which its UUIDv7 records, so that exports of the same graph match.
+fn created_at(index: NodeIndex) -> DateTime<Utc> {
+    index
+        .get_timestamp()
+        .and_then(|timestamp| {
+            let (seconds, nanos) = timestamp.to_unix();
+            DateTime::from_timestamp(seconds.try_into().ok()?, nanos)
+        })
+        .unwrap_or(DateTime::UNIX_EPOCH)
+}
+
+fn format_date(date: DateTime<Utc>) -> String {
+    date.format(DATE_FORMAT).to_string()
+}
+
+fn parse_date(date: &str) -> anyhow::Result<DateTime<Utc>> {
+    NaiveDateTime::parse_from_str(date, DATE_FORMAT)
+        .map(|date| date.and_utc())
+        .or_else(|_| DateTime::parse_from_rfc3339(date).map(|date| date.with_timezone(&Utc)))
+        .with_context(|| format!("Invalid date {:?}", date))
+}
+
+/// Adds the tasks from `task export` to `graph`.
+///
+/// Each task's description becomes its title, its annotations its description, and its
+/// `depends` edges to the tasks it depends on. Completed tasks are marked as done, deleted
+/// tasks are skipped, and dependencies on tasks which aren't in the export are dropped.
+/// Dependencies which form cycles are set aside and reported, rather than failing the import.
+pub fn import<G: Graph>(graph: &mut G, text: &str) -> anyhow::Result<Report> {
+    let mut import = parse(text)?;
+    let cycles = import.set_aside_cycles();
+    let cycles = cycles.iter().map(|cycle| import.describe(cycle)).collect();
+    Ok(Report {
+        tasks: import.apply(graph)?,
+        cycles,
+    })
+}
+
+/// Reads a Taskwarrior export, without adding it to a graph yet.
+pub fn parse(text: &str) -> anyhow::Result<Import> {
+    // Older versions of Taskwarrior write one task per line, rather than an array.
+    let tasks = if text.trim_start().starts_with('[') {
+        match serde_json::from_str(text)? {
+            Value::Array(tasks) => tasks,
+            _ => anyhow::bail!("Expected a list of tasks"),
+        }
+    } else {
+        text.lines()
+            .map(|line| line.trim().trim_end_matches(','))
+            .filter(|line| !line.is_empty())
+            .map(serde_json::from_str)
+            .collect::<Result<_, _>>()?
+    };
+
+    let mut import = Import::default();
+    let mut indices = HashMap::new();
+    let mut dependencies = vec![];
+    for (i, task) in tasks.iter().enumerate() {
+        let mut read = || -> anyhow::Result<()> {
+            let uuid = string(task, "uuid")?.context("Missing uuid")?;
+            Uuid::parse_str(uuid).with_context(|| format!("Invalid uuid {:?}", uuid))?;
+            if string(task, "status")? == Some("deleted") {
+                return Ok(());
+            }
+            let node = parse_task(task)?;
+            if indices.insert(uuid, import.add_node(node, None)).is_some() {
+                anyhow::bail!("Task {} appears more than once", uuid);
+            }
+            dependencies.push((uuid, depends(task)?));
+            Ok(())
+        };
+        read().with_context(|| format!("Invalid task {}", i + 1))?;
+    }
+    for (uuid, depends) in dependencies {
+        for dependency in depends {
+            if let Some(&to) = indices.get(dependency) {
+                import.add_edge(indices[uuid], to);
+            }
+        }
+    }
+    Ok(import)
+}
+
+fn string<'a>(task: &'a Value, key: &str) -> anyhow::Result<Option<&'a str>> {
+    match task.get(key) {
+        None => Ok(None),
+        Some(value) => Ok(Some(
+            value
+                .as_str()
+                .with_context(|| format!("{} must be a string", key))?,
+        )),
+    }
+}
+
+/// Reads the UUIDs a task depends on, which Taskwarrior 2.6 and later write as an array,
+/// and earlier versions as a comma-separated string.
+fn depends(task: &Value) -> anyhow::Result<Vec<&str>> {
+    match task.get("depends") {
+        None => Ok(vec![]),
+        Some(Value::String(depends)) => Ok(depends
+            .split(',')
+            .map(str::trim)
+            .filter(|uuid| !uuid.is_empty())
+            .collect()),
+        Some(Value::Array(depends)) => depends
+            .iter()
+            .map(|uuid| uuid.as_str().context("depends must be a list of UUIDs"))
+            .collect(),
+        Some(_) => anyhow::bail!("depends must be a list of UUIDs"),
+    }
+}
+
+fn parse_task(task: &Value) -> anyhow::Result<Node> {
+    let mut node = Node {
+        title: string(task, "description")?
+            .context("Missing description")?
+            .to_owned(),
+        ..Default::default()
+    };
+    node.metadata.insert(
+        UUID_KEY.to_owned(),
+        string(task, "uuid")?.unwrap_or_default().to_owned(),
+    );
+    if string(task, "status")? == Some("completed") {
+        node.completed_at = Some(match string(task, "end")? {
+            Some(end) => parse_date(end)?,
+            None => Utc::now(),
+        });
+    }
+    if let Some(due) = string(task, "due")? {
+        node.metadata
+            .insert("due".to_owned(), parse_date(due)?.to_rfc3339());
+    }
+    if let Some(tags) = task.get("tags") {
+        let tags = tags
+            .as_array()
+            .context("tags must be a list")?
+            .iter()
+            .map(|tag| tag.as_str().context("tags must be strings"))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        if !tags.is_empty() {
+            node.metadata.insert("tags".to_owned(), tags.join(" "));
+        }
+    }
+    if let Some(annotations) = task.get("annotations") {
+        let annotations = annotations
+            .as_array()
+            .context("annotations must be a list")?;
+        if let Some(entry) = annotations
+            .first()
+            .map(|annotation| string(annotation, "entry"))
+        {
+            if let Some(entry) = entry? {
+                node.metadata.insert(
+                    ANNOTATION_ENTRY_KEY.to_owned(),
+                    parse_date(entry)?.to_rfc3339(),
+                );
+            }
+        }
+        node.description = annotations
+            .iter()
+            .map(|annotation| string(annotation, "description").map(Option::unwrap_or_default))
+            .collect::<anyhow::Result<Vec<_>>>()?
+            .join("\n");
+    }
+    Ok(node)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    use crate::graph::{DatabaseGraph, PetgraphGraph};
+
+    const EXPORT: &str = r#"[
+{"id":1,"description":"Write report","entry":"20240701T090000Z","status":"pending","uuid":"5f0c1a2e-8d4b-4d9e-9a51-3c2f4f6b7a01","depends":["5f0c1a2e-8d4b-4d9e-9a51-3c2f4f6b7a02","5f0c1a2e-8d4b-4d9e-9a51-3c2f4f6b7a03"],"due":"20240702T170000Z","tags":["work","urgent"],"urgency":9.1},
+{"id":2,"description":"Gather data","entry":"20240701T090000Z","status":"pending","uuid":"5f0c1a2e-8d4b-4d9e-9a51-3c2f4f6b7a02","depends":"5f0c1a2e-8d4b-4d9e-9a51-3c2f4f6b7a03,5f0c1a2e-8d4b-4d9e-9a51-3c2f4f6b7a04","annotations":[{"entry":"20240701T100000Z","description":"from last quarter"},{"entry":"20240701T110000Z","description":"and this one"}]},
+{"id":0,"description":"Make coffee","end":"20240701T093000Z","entry":"20240701T090000Z","status":"completed","uuid":"5f0c1a2e-8d4b-4d9e-9a51-3c2f4f6b7a03"},
+{"id":0,"description":"Old idea","entry":"20240701T090000Z","status":"deleted","uuid":"5f0c1a2e-8d4b-4d9e-9a51-3c2f4f6b7a04"}
+]"#;
+
+    fn titles(graph: &impl Graph, indices: &[NodeIndex]) -> Vec<String> {
+        indices
+            .iter()
+            .map(|index| graph.get_node(*index).unwrap().title)
+            .collect()
+    }
+
+    #[test]
+    fn test_import() {
+        let mut graph = PetgraphGraph::default();
+        let report = import(&mut graph, EXPORT).unwrap();
+        assert!(report.cycles.is_empty());
+        let [report_task, data, coffee] = report.tasks[..] else {
+            panic!("{:?}", report.tasks)
+        };
+
+        let node = graph.get_node(report_task).unwrap();
+        assert_eq!(node.title, "Write report");
+        assert!(!node.is_completed());
+        assert_eq!(node.metadata["due"], "2024-07-02T17:00:00+00:00");
+        assert_eq!(node.metadata["tags"], "work urgent");
+        assert_eq!(
+            node.metadata[UUID_KEY],
+            "5f0c1a2e-8d4b-4d9e-9a51-3c2f4f6b7a01"
+        );
+        let mut children = graph.neighbors(report_task).unwrap();
+        children.sort();
+        assert_eq!(children, vec![data, coffee]);
+
+        let node = graph.get_node(data).unwrap();
+        assert_eq!(node.description, "from last quarter\nand this one");
+        // The dependency on the deleted task is dropped.
+        assert_eq!(graph.neighbors(data).unwrap(), vec![coffee]);
+
+        let node = graph.get_node(coffee).unwrap();
+        assert_eq!(
+            node.completed_at,
+            Some(parse_date("2024-07-01T09:30:00Z").unwrap())
+        );
+    }
+
+    fn check_roundtrip(from: &mut impl Graph, to: &mut impl Graph) {
+        let old = import(from, EXPORT).unwrap().tasks;
+        let new = import(to, &export(from).unwrap()).unwrap().tasks;
+        assert_eq!(titles(to, &new), titles(from, &old));
+        for (old, new) in old.iter().zip(&new) {
+            let (old_node, new_node) = (from.get_node(*old).unwrap(), to.get_node(*new).unwrap());
+            assert_eq!(new_node.metadata, old_node.metadata);
+            assert_eq!(new_node.completed_at, old_node.completed_at);
+            let mut old_children = titles(from, &from.neighbors(*old).unwrap());
+            let mut new_children = titles(to, &to.neighbors(*new).unwrap());
+            old_children.sort();
+            new_children.sort();
+            assert_eq!(new_children, old_children);
+        }
+        // Several annotations come back as one.
+        assert_eq!(
+            to.get_node(new[1]).unwrap().description,
+            "from last quarter\nand this one"
+        );
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        check_roundtrip(&mut PetgraphGraph::default(), &mut PetgraphGraph::default());
+        check_roundtrip(
+            &mut DatabaseGraph::open_in_memory().unwrap(),
+            &mut DatabaseGraph::open_in_memory().unwrap(),
+        );
+    }
+
+    #[test]
+    fn test_export_new_tasks() {
+        let mut graph = PetgraphGraph::default();
+        let a = graph
+            .add_node(Node {
+                title: "a".to_owned(),
+                ..Default::default()
+            })
+            .unwrap();
+        let tasks: Value = serde_json::from_str(&export(&graph).unwrap()).unwrap();
+        assert_eq!(
+            tasks,
+            json!([{"uuid": a.to_string(), "description": "a", "status": "pending"}])
+        );
+    }
+
+    #[test]
+    fn test_export_due_days() {
+        let mut graph = PetgraphGraph::default();
+        graph
+            .add_node(Node {
+                title: "a".to_owned(),
+                metadata: [("due".to_owned(), "2024-07-03".to_owned())].into(),
+                ..Default::default()
+            })
+            .unwrap();
+        let tasks: Value = serde_json::from_str(&export(&graph).unwrap()).unwrap();
+        // Taskwarrior dates have times, so a day is due from its start.
+        let day = NaiveDate::from_ymd_opt(2024, 7, 3).unwrap();
+        assert_eq!(
+            tasks[0]["due"],
+            json!(format_date(MetadataDate::Day(day).to_utc()))
+        );
+    }
+
+    #[test]
+    fn test_export_duplicate_uuids() {
+        // Importing the same export twice leaves two tasks with each UUID.
+        let mut graph = PetgraphGraph::default();
+        import(&mut graph, EXPORT).unwrap();
+        import(&mut graph, EXPORT).unwrap();
+        let tasks: Value = serde_json::from_str(&export(&graph).unwrap()).unwrap();
+        let mut uuids: Vec<&str> = tasks
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|task| task["uuid"].as_str().unwrap())
+            .collect();
+        uuids.sort();
+        uuids.dedup();
+        assert_eq!(uuids.len(), 6);
+
+        let mut copy = PetgraphGraph::default();
+        let report = import(&mut copy, &export(&graph).unwrap()).unwrap();
+        assert_eq!(report.tasks.len(), 6);
+    }
+
+    #[test]
+    fn test_annotation_entries_are_stable() {
+        let mut graph = PetgraphGraph::default();
+        let a = graph
+            .add_node(Node {
+                title: "a".to_owned(),
+                description: "details".to_owned(),
+                ..Default::default()
+            })
+            .unwrap();
+        import(&mut graph, EXPORT).unwrap();
+        let text = export(&graph).unwrap();
+        assert_eq!(export(&graph).unwrap(), text);
+
+        let tasks: Value = serde_json::from_str(&text).unwrap();
+        let entry = |uuid: &str| {
+            let tasks = tasks.as_array().unwrap();
+            let task = tasks.iter().find(|task| task["uuid"] == uuid).unwrap();
+            task["annotations"][0]["entry"].clone()
+        };
+        // New tasks' annotations date from when the task was added.
+        assert_eq!(entry(&a.to_string()), json!(format_date(created_at(a))));
+        // Imported ones keep the date they had.
+        assert_eq!(
+            entry("5f0c1a2e-8d4b-4d9e-9a51-3c2f4f6b7a02"),
+            json!("20240701T100000Z")
+        );
+    }
+
+    #[test]
+    fn test_import_reports_cycles() {
+        let lines = r#"
+{"description":"a","status":"pending","uuid":"5f0c1a2e-8d4b-4d9e-9a51-3c2f4f6b7a01","depends":"5f0c1a2e-8d4b-4d9e-9a51-3c2f4f6b7a02"},
+{"description":"b","status":"pending","uuid":"5f0c1a2e-8d4b-4d9e-9a51-3c2f4f6b7a02","depends":"5f0c1a2e-8d4b-4d9e-9a51-3c2f4f6b7a01"},
+{"description":"c","status":"pending","uuid":"5f0c1a2e-8d4b-4d9e-9a51-3c2f4f6b7a03","depends":"5f0c1a2e-8d4b-4d9e-9a51-3c2f4f6b7a03"}
+"#;
+        let mut graph = DatabaseGraph::open_in_memory().unwrap();
+        let report = import(&mut graph, lines).unwrap();
+        assert_eq!(report.cycles, ["\"a\" -> \"b\" -> \"a\"", "\"c\" -> \"c\""]);
+        let [a, b, c] = report.tasks[..] else {
+            panic!("{:?}", report.tasks)
+        };
+        assert_eq!(graph.neighbors(a).unwrap(), vec![b]);
+        assert_eq!(graph.neighbors(b).unwrap(), Vec::<NodeIndex>::new());
+        assert_eq!(graph.neighbors(c).unwrap(), Vec::<NodeIndex>::new());
+        assert_eq!(graph.set_aside_edges().unwrap(), vec![(b, a)]);
+    }
+
+    #[test]
+    fn test_import_rejects_invalid_exports() {
+        let invalid = [
+            (r#"[{"description":"a"}]"#, "Invalid task 1: Missing uuid"),
+            (r#"[{"uuid":"x","description":"a"}]"#, "Invalid uuid \"x\""),
+            (
+                r#"[{"uuid":"5f0c1a2e-8d4b-4d9e-9a51-3c2f4f6b7a01"}]"#,
+                "Missing description",
+            ),
+            (
+                r#"[{"uuid":"5f0c1a2e-8d4b-4d9e-9a51-3c2f4f6b7a01","description":"a","due":"soon"}]"#,
+                "Invalid date \"soon\"",
+            ),
+            (
+                r#"[{"uuid":"5f0c1a2e-8d4b-4d9e-9a51-3c2f4f6b7a01","description":"a"},
+                    {"uuid":"5f0c1a2e-8d4b-4d9e-9a51-3c2f4f6b7a01","description":"b"}]"#,
+                "Invalid task 2: Task 5f0c1a2e-8d4b-4d9e-9a51-3c2f4f6b7a01 appears more than once",
+            ),
+            ("[", "EOF while parsing a list"),
+        ];
+        for (text, message) in invalid {
+            let mut graph = PetgraphGraph::default();
+            let error = format!("{:#}", import(&mut graph, text).unwrap_err());
+            assert!(
+                error.contains(message),
+                "{} should contain {}",
+                error,
+                message
+            );
+            assert_eq!(graph.node_indices().unwrap(), Vec::<NodeIndex>::new());
+        }
+    }
+}
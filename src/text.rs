@@ -291,7 +291,7 @@ fn initial_pen_x(
 }
 
 fn next_line(chars: &[char], start: usize) -> &[char] {
-    for (i, char) in chars.into_iter().skip(start).enumerate() {
+    for (i, char) in chars.iter().skip(start).enumerate() {
         if *char == '\n' {
             return &chars[start..i + start];
         }
@@ -330,6 +330,7 @@ fn to_font_ref(font: &FontData) -> Option<FontRef<'_>> {
 }
 
 #[cfg(test)]
+#[allow(non_snake_case)]
 mod tests {
     use super::*;
 
@@ -400,8 +401,8 @@ mod tests {
         let chars = &['\n', '\n'];
         let line1 = next_line(chars, 0);
         let line2 = next_line(chars, line1.len() + 1);
-        assert_eq!(line1, &[]);
-        assert_eq!(line2, &[]);
+        assert_eq!(line1, &[] as &[char]);
+        assert_eq!(line2, &[] as &[char]);
     }
 
     #[test]
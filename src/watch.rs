@@ -0,0 +1,98 @@
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::graph::Graph;
+
+/// How often a watched graph is checked for changes made by other processes.
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Keeps a shared graph up to date with changes which other processes write to its storage,
+/// by calling `Graph::refresh` every `POLL_INTERVAL`.
+///
+/// The changes are published to the graph's subscribers, so a `GraphViewer` showing the graph
+/// repaints on its own. Watching stops when the `Watcher` is dropped.
+pub struct Watcher {
+    stop: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Watcher {
+    pub fn start<G: Graph + Send + 'static>(graph: Arc<Mutex<G>>) -> Self {
+        let (stop, stopped) = mpsc::channel::<()>();
+        let thread = thread::spawn(move || loop {
+            if let Err(e) = graph.lock().unwrap().refresh() {
+                log::warn!("Failed to check the graph for external changes: {}", e);
+            }
+            match stopped.recv_timeout(POLL_INTERVAL) {
+                Err(RecvTimeoutError::Timeout) => {}
+                Ok(()) | Err(RecvTimeoutError::Disconnected) => return,
+            }
+        });
+        Self {
+            stop: Some(stop),
+            thread: Some(thread),
+        }
+    }
+}
+
+impl Drop for Watcher {
+    fn drop(&mut self) {
+        // Hanging up wakes the thread straight away.
+        self.stop.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use masonry::kurbo::{Circle, Point};
+
+    use super::*;
+    use crate::graph::{DatabaseGraph, GraphEvent, Node};
+    use crate::history::History;
+
+    fn test_node(title: &str) -> Node {
+        Node {
+            title: title.to_owned(),
+            circle: Circle::new(Point::new(1.0, 2.0), 40.0),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_watcher_sees_other_processes_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("db.sqlite");
+        let mut other = DatabaseGraph::open(&path).unwrap();
+        let graph = Arc::new(Mutex::new(History::new(
+            DatabaseGraph::open(&path).unwrap(),
+        )));
+        let events = Arc::new(Mutex::new(vec![]));
+        {
+            let events = events.clone();
+            graph
+                .lock()
+                .unwrap()
+                .subscribe(Box::new(move |event| events.lock().unwrap().push(*event)));
+        }
+        let _watcher = Watcher::start(graph.clone());
+
+        let written_at = Instant::now();
+        let a = other.add_node(test_node("a")).unwrap();
+        while events.lock().unwrap().is_empty() {
+            assert!(
+                written_at.elapsed() < Duration::from_secs(1),
+                "Timed out waiting for the change"
+            );
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(*events.lock().unwrap(), vec![GraphEvent::NodeAdded(a)]);
+        assert_eq!(graph.lock().unwrap().get_node(a).unwrap().title, "a");
+    }
+}
@@ -0,0 +1,27 @@
+-- The DatabaseGraph schema at user_version 0, with some tasks in it.
+-- Frozen: never regenerate this from the current migrations.
+BEGIN TRANSACTION;
+CREATE TABLE task_links (
+    parent_id INTEGER NOT NULL,
+    child_id INTEGER NOT NULL,
+    PRIMARY KEY (parent_id, child_id),
+    FOREIGN KEY (parent_id) REFERENCES tasks(id),
+    FOREIGN KEY (child_id) REFERENCES tasks(id)
+);
+INSERT INTO "task_links" VALUES(1,2);
+CREATE TABLE tasks (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    title VARCHAR NOT NULL,
+    x REAL NOT NULL,
+    y REAL NOT NULL,
+    radius REAL NOT NULL,
+    deleted_at DATETIME DEFAULT NULL,
+    completed_at DATETIME DEFAULT NULL,
+    description VARCHAR DEFAULT NULL
+);
+INSERT INTO "tasks" VALUES(1,'a',1.0,2.0,40.0,NULL,'2024-07-02 17:00:00+00:00','First task');
+INSERT INTO "tasks" VALUES(2,'b',3.0,4.0,40.0,NULL,NULL,NULL);
+INSERT INTO "tasks" VALUES(3,'deleted',5.0,6.0,40.0,'2024-07-03 09:00:00+00:00',NULL,NULL);
+DELETE FROM "sqlite_sequence";
+INSERT INTO "sqlite_sequence" VALUES('tasks',3);
+COMMIT;
@@ -0,0 +1,27 @@
+-- The DatabaseGraph schema at user_version 5, with some tasks in it.
+-- Frozen: never regenerate this from the current migrations.
+BEGIN TRANSACTION;
+CREATE TABLE "task_links" (
+        parent_id BLOB NOT NULL,
+        child_id BLOB NOT NULL, created_at DATETIME DEFAULT NULL, set_aside_at DATETIME DEFAULT NULL,
+        PRIMARY KEY (parent_id, child_id),
+        FOREIGN KEY (parent_id) REFERENCES tasks(id),
+        FOREIGN KEY (child_id) REFERENCES tasks(id)
+    );
+INSERT INTO "task_links" VALUES(X'01907000000070008000000000000001',X'01907000000070008000000000000002','2024-07-01 08:00:00+00:00',NULL);
+CREATE TABLE "tasks" (
+        id BLOB PRIMARY KEY NOT NULL,
+        title VARCHAR NOT NULL,
+        x REAL NOT NULL,
+        y REAL NOT NULL,
+        radius REAL NOT NULL,
+        deleted_at DATETIME DEFAULT NULL,
+        completed_at DATETIME DEFAULT NULL,
+        description VARCHAR DEFAULT NULL
+    , metadata VARCHAR DEFAULT NULL);
+INSERT INTO "tasks" VALUES(X'01907000000070008000000000000001','a',1.0,2.0,40.0,NULL,'2024-07-02 17:00:00+00:00','First task','{"due":"2024-07-05T00:00:00+00:00"}');
+INSERT INTO "tasks" VALUES(X'01907000000070008000000000000002','b',3.0,4.0,40.0,NULL,NULL,NULL,NULL);
+INSERT INTO "tasks" VALUES(X'01907000000070008000000000000003','deleted',5.0,6.0,40.0,'2024-07-03 09:00:00+00:00',NULL,NULL,NULL);
+CREATE INDEX task_links_child_id ON task_links (child_id);
+COMMIT;
+PRAGMA user_version = 5;